@@ -0,0 +1,106 @@
+use std::collections::BTreeMap;
+use std::hint::black_box;
+
+use backend::{Instrument, LimitOrder, MarketOrder, Order, OrderBook, types::Side};
+use criterion::{BatchSize, BenchmarkId, Criterion, criterion_group, criterion_main};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use uuid::Uuid;
+
+const LEVELS: i64 = 1_000;
+const ORDERS: usize = 10_000;
+
+fn instrument() -> Instrument {
+    Instrument::new(dec!(0.01), dec!(0.001))
+}
+
+// price ladder around 100.00 with a few orders per level, the same for both key types
+fn ladder() -> Vec<(i64, u64)> {
+    (0..ORDERS)
+        .map(|i| (10_000 + (i as i64 * 7919) % LEVELS, 1 + (i as u64 % 50)))
+        .collect()
+}
+
+// what the book does per order: add to a level, then consume levels from the best price
+fn level_map_workload<K: Ord + Copy>(orders: &[(K, u64)]) -> u64 {
+    let mut levels: BTreeMap<K, u64> = BTreeMap::new();
+    for (price, qty) in orders {
+        *levels.entry(*price).or_insert(0) += qty;
+    }
+    let mut total = 0;
+    while let Some((_, qty)) = levels.pop_first() {
+        total += qty;
+    }
+    total
+}
+
+fn bench_level_map(c: &mut Criterion) {
+    let instrument = instrument();
+    let ticks = ladder();
+    let decimals: Vec<(Decimal, u64)> = ticks
+        .iter()
+        .map(|(p, q)| (instrument.price(*p), *q))
+        .collect();
+
+    let mut group = c.benchmark_group("level_map");
+    group.bench_with_input(BenchmarkId::new("decimal", ORDERS), &decimals, |b, orders| {
+        b.iter(|| level_map_workload(black_box(orders)))
+    });
+    group.bench_with_input(BenchmarkId::new("ticks", ORDERS), &ticks, |b, orders| {
+        b.iter(|| level_map_workload(black_box(orders)))
+    });
+    group.finish();
+}
+
+fn bench_order_book(c: &mut Criterion) {
+    let orders = ladder();
+    let total: u64 = orders.iter().map(|(_, q)| q).sum();
+
+    c.bench_function("order_book/insert_then_sweep", |b| {
+        b.iter_batched(
+            || {
+                orders
+                    .iter()
+                    .map(|(price, quantity)| {
+                        Order::limit_order(LimitOrder {
+                            user_id: Uuid::new_v4(),
+                            side: Side::Sell,
+                            price: *price,
+                            quantity: *quantity,
+                            leverage: dec!(1),
+                        })
+                        .with_id(Uuid::new_v4())
+                    })
+                    .collect::<Vec<_>>()
+            },
+            |resting| {
+                let mut book = OrderBook::new();
+                for order in resting {
+                    book.insert_order(order);
+                }
+                let taker = Order::market_order(MarketOrder {
+                    user_id: Uuid::new_v4(),
+                    side: Side::Buy,
+                    quantity: total,
+                    leverage: dec!(1),
+                });
+                black_box(book.match_order(taker, 0))
+            },
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+// cost paid once per request/event at the boundary
+fn bench_conversion(c: &mut Criterion) {
+    let instrument = instrument();
+    c.bench_function("instrument/to_ticks", |b| {
+        b.iter(|| instrument.to_ticks(black_box(dec!(101.37))))
+    });
+    c.bench_function("instrument/price", |b| {
+        b.iter(|| instrument.price(black_box(10_137)))
+    });
+}
+
+criterion_group!(benches, bench_level_map, bench_order_book, bench_conversion);
+criterion_main!(benches);
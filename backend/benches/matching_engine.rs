@@ -0,0 +1,104 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+use backend::{Instrument, LimitOrder, MarketOrder, MatchingEngine, Order, RingBuffer, types::{EventEnvelope, OrderBookMessage, Priority, Side}};
+use criterion::{BatchSize, Criterion, Throughput, criterion_group, criterion_main};
+use rust_decimal_macros::dec;
+use uuid::Uuid;
+
+const COMMANDS: usize = 10_000;
+
+// deterministic mix of passive and crossing limits, market orders and cancels across all priorities
+fn workload() -> Vec<OrderBookMessage> {
+    let users: Vec<Uuid> = (0..32).map(|_| Uuid::new_v4()).collect();
+    let mut resting = Vec::new();
+    let mut messages = Vec::with_capacity(COMMANDS);
+    let mut seed: u64 = 0x2545_F491_4F6C_DD1D;
+
+    for i in 0..COMMANDS {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        let user_id = users[(seed % users.len() as u64) as usize];
+        let side = if seed & 1 == 0 { Side::Buy } else { Side::Sell };
+        let priority = match i % 8 {
+            0 => Priority::High,
+            1 => Priority::Low,
+            _ => Priority::Normal,
+        };
+
+        let message = match seed % 10 {
+            0..=5 => {
+                let offset = (seed >> 8) as i64 % 20 - 10;
+                let order = Order::limit_order(LimitOrder {
+                    user_id,
+                    side,
+                    price: 10_000 + offset,
+                    quantity: 1 + (seed >> 16) % 50,
+                    leverage: dec!(5),
+                })
+                .with_id(Uuid::new_v4());
+                resting.push((order.order_id, user_id));
+                OrderBookMessage::PlaceOrder { order, priority, responder: None }
+            }
+            6 | 7 => OrderBookMessage::PlaceOrder {
+                order: Order::market_order(MarketOrder { user_id, side, quantity: 1 + (seed >> 16) % 20, leverage: dec!(5) }),
+                priority,
+                responder: None,
+            },
+            _ if !resting.is_empty() => {
+                let (order_id, user_id) = resting.swap_remove((seed >> 24) as usize % resting.len());
+                OrderBookMessage::CancelOrder { order_id, user_id, responder: None }
+            }
+            _ => OrderBookMessage::UpdateMarkPrice { price: dec!(100) },
+        };
+        messages.push(message);
+    }
+    messages
+}
+
+fn bench_run(c: &mut Criterion) {
+    let mut group = c.benchmark_group("matching_engine");
+    group.throughput(Throughput::Elements(COMMANDS as u64));
+    group.sample_size(20);
+    group.bench_function("run_mixed_priorities", |b| {
+        b.iter_batched(
+            || {
+                let (tx, rx) = mpsc::sync_channel(COMMANDS);
+                for message in workload() {
+                    tx.send(message).unwrap();
+                }
+                rx
+            },
+            |rx| {
+                let events = Arc::new(RingBuffer::<EventEnvelope>::new(4096));
+                let done = Arc::new(AtomicBool::new(false));
+                let drain = {
+                    let events = Arc::clone(&events);
+                    let done = Arc::clone(&done);
+                    thread::spawn(move || {
+                        while !done.load(Ordering::Acquire) {
+                            events.drain_batch(256);
+                        }
+                    })
+                };
+                // the sender was dropped with the workload, so run returns once every command is processed
+                let mut engine = MatchingEngine::new(events, Instrument::new(dec!(0.01), dec!(0.001)));
+                engine.run(rx);
+                done.store(true, Ordering::Release);
+                drain.join().unwrap();
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_run);
+criterion_main!(benches);
@@ -0,0 +1,123 @@
+use std::hint::black_box;
+
+use backend::{LimitOrder, Lots, MarketOrder, Order, OrderBook, OrderId, Ticks, UserId, types::Side};
+use criterion::{BatchSize, BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use rust_decimal_macros::dec;
+use uuid::Uuid;
+
+const MID: Ticks = 10_000;
+
+fn limit(user_id: UserId, side: Side, price: Ticks, quantity: Lots) -> Order {
+    Order::limit_order(LimitOrder { user_id, side, price, quantity, leverage: dec!(1) }).with_id(Uuid::new_v4())
+}
+
+fn market(side: Side, quantity: Lots) -> Order {
+    Order::market_order(MarketOrder { user_id: Uuid::new_v4(), side, quantity, leverage: dec!(1) }).with_id(Uuid::new_v4())
+}
+
+// two sided book with `depth` levels per side and `per_level` orders on each level
+fn deep_book(depth: i64, per_level: usize) -> (OrderBook, Vec<(OrderId, UserId)>) {
+    let users: Vec<UserId> = (0..16).map(|_| Uuid::new_v4()).collect();
+    let mut book = OrderBook::new();
+    let mut ids = Vec::new();
+    for level in 1..=depth {
+        for n in 0..per_level {
+            let user = users[n % users.len()];
+            for (side, price) in [(Side::Sell, MID + level), (Side::Buy, MID - level)] {
+                let order = limit(user, side, price, 1 + (n as u64 % 10));
+                ids.push((order.order_id, user));
+                book.insert_order(order);
+            }
+        }
+    }
+    (book, ids)
+}
+
+fn bench_insert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("order_book/insert");
+    for depth in [10, 100, 1_000] {
+        group.throughput(Throughput::Elements(1));
+        group.bench_with_input(BenchmarkId::from_parameter(depth), &depth, |b, &depth| {
+            let (mut book, _) = deep_book(depth, 10);
+            let user = Uuid::new_v4();
+            let mut n = 0;
+            b.iter(|| {
+                // passive orders only, so the book keeps its shape
+                n += 1;
+                book.insert_order(limit(user, Side::Buy, MID - 1 - (n % depth), 1));
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_cancel(c: &mut Criterion) {
+    let mut group = c.benchmark_group("order_book/cancel_heavy");
+    for per_level in [10, 100, 1_000] {
+        group.throughput(Throughput::Elements((per_level * 20) as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(per_level), &per_level, |b, &per_level| {
+            b.iter_batched(
+                || {
+                    let (book, mut ids) = deep_book(10, per_level);
+                    // cancel from the middle of the queues, not just the heads
+                    let len = ids.len();
+                    ids.rotate_left(len / 2);
+                    (book, ids)
+                },
+                |(mut book, ids)| {
+                    for (order_id, user_id) in ids {
+                        black_box(book.cancel_order(&order_id, &user_id).ok());
+                    }
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn bench_match(c: &mut Criterion) {
+    let mut group = c.benchmark_group("order_book/match");
+
+    // small taker against the top of a deep book
+    group.bench_function("top_of_book", |b| {
+        b.iter_batched(
+            || deep_book(1_000, 10).0,
+            |mut book| black_box(book.match_order(market(Side::Buy, 5), 0)),
+            BatchSize::LargeInput,
+        )
+    });
+
+    // market orders that walk through many levels
+    for levels in [10, 100, 500] {
+        let (_, ids) = deep_book(levels, 10);
+        let swept: Lots = (ids.len() as u64 / 2) * 5;
+        group.throughput(Throughput::Elements(ids.len() as u64 / 2));
+        group.bench_with_input(BenchmarkId::new("sweep", levels), &levels, |b, &levels| {
+            b.iter_batched(
+                || deep_book(levels, 10).0,
+                |mut book| black_box(book.match_order(market(Side::Sell, swept), 0)),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+
+    // crossing limit order that fills part way and rests the remainder
+    group.bench_function("crossing_limit_rests", |b| {
+        b.iter_batched(
+            || deep_book(100, 10).0,
+            |mut book| {
+                let (fills, rest) = book.match_order(limit(Uuid::new_v4(), Side::Buy, MID + 5, 500), 0);
+                if let Some(rest) = rest {
+                    book.insert_order(rest);
+                }
+                black_box(fills)
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_insert, bench_cancel, bench_match);
+criterion_main!(benches);
@@ -0,0 +1,69 @@
+use std::{hint::black_box, sync::Arc, thread};
+
+use backend::RingBuffer;
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+
+const MESSAGES: u64 = 100_000;
+
+fn bench_single_thread(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ring_buffer");
+    let ring = RingBuffer::<u64>::new(1024);
+
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("push_pop", |b| {
+        b.iter(|| {
+            ring.push(black_box(1));
+            black_box(ring.try_pop())
+        })
+    });
+
+    group.throughput(Throughput::Elements(256));
+    group.bench_function("push_256_drain_batch", |b| {
+        b.iter(|| {
+            for i in 0..256 {
+                ring.push(i);
+            }
+            black_box(ring.drain_batch(256))
+        })
+    });
+    group.finish();
+}
+
+// producer and consumer on separate threads, the way the engine and its event consumer use it
+fn bench_spsc(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ring_buffer/spsc");
+    group.throughput(Throughput::Elements(MESSAGES));
+    for (name, batch) in [("try_pop", 1), ("drain_batch_64", 64)] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let ring = Arc::new(RingBuffer::<u64>::new(4096));
+                let consumer = {
+                    let ring = Arc::clone(&ring);
+                    thread::spawn(move || {
+                        let mut seen = 0;
+                        while seen < MESSAGES {
+                            if batch == 1 {
+                                if ring.try_pop().is_some() {
+                                    seen += 1;
+                                }
+                            } else {
+                                seen += ring.drain_batch(batch).len() as u64;
+                            }
+                            std::hint::spin_loop();
+                        }
+                    })
+                };
+                for i in 0..MESSAGES {
+                    while !ring.push(i) {
+                        std::hint::spin_loop();
+                    }
+                }
+                consumer.join().unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_single_thread, bench_spsc);
+criterion_main!(benches);
@@ -0,0 +1,246 @@
+// API keys for bots. A bot signs every request with HMAC-SHA256 over
+//
+//     {timestamp}\n{nonce}\n{METHOD}\n{path and query}\n{body}
+//
+// and sends the key id, the timestamp (unix millis), the nonce and the hex signature in the
+// X-API-* headers. Requests outside SIGNATURE_WINDOW_MS of the server clock are refused and a
+// nonce is accepted once per key, so a captured request cannot be replayed.
+//
+// The server has to recompute the signature, so it needs the secret itself. Every key gets its
+// own random secret, shown once when the key is created and stored encrypted with ApiKeyCipher.
+// The database alone cannot sign anything, and neither can the server key without it.
+
+use std::{collections::{HashSet, VecDeque}, net::IpAddr, sync::Mutex};
+
+use aes_gcm::{Aes256Gcm, Nonce, aead::{Aead, Payload}};
+use anyhow::{Context, Result, bail};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::Utc;
+use db::{ApiKey, Db};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::{AuthError, user_role, types::{ApiScope, Role}};
+
+pub const API_KEY_HEADER: &str = "X-API-KEY";
+pub const API_TIMESTAMP_HEADER: &str = "X-API-TIMESTAMP";
+pub const API_NONCE_HEADER: &str = "X-API-NONCE";
+pub const API_SIGNATURE_HEADER: &str = "X-API-SIGNATURE";
+
+// how far a request's timestamp may be from the server clock, either way
+pub const SIGNATURE_WINDOW_MS: i64 = 30_000;
+pub const MAX_NONCE_LEN: usize = 64;
+
+// set by ApiKeyMiddleware next to AuthUser when a request was signed with a key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKeyAuth {
+    pub key_id: Uuid,
+    pub scopes: Vec<ApiScope>,
+    pub role: Role, //of the key's owner
+}
+
+// what the middleware pulled out of a signed request
+pub struct SignedRequest<'a> {
+    pub key_id: &'a str,
+    pub timestamp: &'a str,
+    pub nonce: &'a str,
+    pub signature: &'a str,
+    pub method: &'a str,
+    pub path: &'a str,
+    pub body: &'a [u8],
+    pub peer: Option<IpAddr>,
+}
+
+// 256 random bits, hex encoded
+pub fn generate_api_secret() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    hex(&bytes)
+}
+
+const CIPHER_NONCE_LEN: usize = 12;
+
+// AES-256-GCM over the signing secrets, the key id is the associated data so a stored secret
+// cannot be copied onto another key's row. New secrets are encrypted with the active key; the
+// previous ones still decrypt what was stored before a rotation. Loaded once at startup and
+// kept in AppState.
+pub struct ApiKeyCipher {
+    active: Aes256Gcm,
+    previous: Vec<Aes256Gcm>,
+}
+
+impl ApiKeyCipher {
+    pub fn new(active: &[u8; 32], previous: &[[u8; 32]]) -> Self {
+        Self {
+            active: aes(active),
+            previous: previous.iter().map(aes).collect(),
+        }
+    }
+
+    // a random key, for tests. Secrets encrypted with it are gone with the process
+    pub fn generate() -> Self {
+        let mut key = [0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        ApiKeyCipher::new(&key, &[])
+    }
+
+    // API_KEY_ENCRYPTION_KEY is the active key as 64 hex characters, API_KEY_PREVIOUS_KEYS a comma
+    // separated list of keys that were active before. Like the jwt keys there is no throwaway
+    // fallback, every key issued under it would stop working on restart.
+    pub fn from_env() -> Result<Self> {
+        let active = std::env::var("API_KEY_ENCRYPTION_KEY").context("API_KEY_ENCRYPTION_KEY is not set")?;
+        let active = parse_cipher_key(&active).context("API_KEY_ENCRYPTION_KEY")?;
+        let mut previous = Vec::new();
+        for key in std::env::var("API_KEY_PREVIOUS_KEYS").unwrap_or_default().split(',').map(str::trim).filter(|k| !k.is_empty()) {
+            previous.push(parse_cipher_key(key).context("API_KEY_PREVIOUS_KEYS")?);
+        }
+        Ok(ApiKeyCipher::new(&active, &previous))
+    }
+
+    // hex of the nonce followed by the ciphertext
+    pub fn encrypt(&self, key_id: Uuid, secret: &str) -> String {
+        let mut nonce = [0u8; CIPHER_NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let sealed = self.active
+            .encrypt(&Nonce::from(nonce), Payload { msg: secret.as_bytes(), aad: key_id.as_bytes() })
+            .expect("aes-gcm encrypts any secret");
+        hex(&[&nonce[..], &sealed].concat())
+    }
+
+    // None if no key opens it, or it was stored for another key id
+    pub fn decrypt(&self, key_id: Uuid, stored: &str) -> Option<String> {
+        let bytes = unhex(stored)?;
+        if bytes.len() <= CIPHER_NONCE_LEN {
+            return None;
+        }
+        let (nonce, sealed) = bytes.split_at(CIPHER_NONCE_LEN);
+        let nonce = Nonce::from(<[u8; CIPHER_NONCE_LEN]>::try_from(nonce).ok()?);
+        let payload = || Payload { msg: sealed, aad: key_id.as_bytes() };
+        let secret = std::iter::once(&self.active)
+            .chain(&self.previous)
+            .find_map(|cipher| cipher.decrypt(&nonce, payload()).ok())?;
+        String::from_utf8(secret).ok()
+    }
+}
+
+// KeyInit is not imported, its new_from_slice would clash with the hmac one below
+fn aes(key: &[u8; 32]) -> Aes256Gcm {
+    <Aes256Gcm as aes_gcm::KeyInit>::new(key.into())
+}
+
+fn parse_cipher_key(s: &str) -> Result<[u8; 32]> {
+    match unhex(s.trim()).map(<[u8; 32]>::try_from) {
+        Some(Ok(key)) => Ok(key),
+        _ => bail!("expected 64 hex characters"),
+    }
+}
+
+// the hex signature a client sends in X-API-SIGNATURE
+pub fn sign_request(secret: &str, timestamp: i64, nonce: &str, method: &str, path: &str, body: &[u8]) -> String {
+    hex(&signature_mac(secret, &timestamp.to_string(), nonce, method, path, body).finalize().into_bytes())
+}
+
+fn signature_mac(secret: &str, timestamp: &str, nonce: &str, method: &str, path: &str, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac takes any key length");
+    for part in [timestamp, nonce, method, path] {
+        mac.update(part.as_bytes());
+        mac.update(b"\n");
+    }
+    mac.update(body);
+    mac
+}
+
+// Checks a signed request against its key. The cheap checks run first and the nonce is only
+// recorded once the signature is known to be good, so garbage cannot fill the cache.
+pub async fn authenticate(db: &Db, cipher: &ApiKeyCipher, nonces: &NonceCache, req: SignedRequest<'_>) -> Result<(ApiKey, ApiKeyAuth), AuthError> {
+    let key_id = Uuid::parse_str(req.key_id).map_err(|_| AuthError::InvalidApiKey)?;
+    let timestamp: i64 = req.timestamp.parse().map_err(|_| AuthError::InvalidApiKey)?;
+    let now = Utc::now().timestamp_millis();
+    if (now - timestamp).abs() > SIGNATURE_WINDOW_MS {
+        return Err(AuthError::StaleRequest);
+    }
+    if req.nonce.is_empty() || req.nonce.len() > MAX_NONCE_LEN {
+        return Err(AuthError::InvalidApiKey);
+    }
+    let signature = unhex(req.signature).ok_or(AuthError::InvalidApiKey)?;
+
+    let key = db
+        .get_api_key(key_id)
+        .await
+        .map_err(|e| AuthError::Internal(e.to_string()))?
+        .ok_or(AuthError::InvalidApiKey)?;
+    // encrypted under a key this server no longer has
+    let secret = cipher.decrypt(key_id, &key.secret_encrypted).ok_or(AuthError::InvalidApiKey)?;
+    signature_mac(&secret, req.timestamp, req.nonce, req.method, req.path, req.body)
+        .verify_slice(&signature)
+        .map_err(|_| AuthError::InvalidApiKey)?;
+
+    if !key.allowed_ips.is_empty() {
+        let allowed = req.peer.is_some_and(|peer| key.allowed_ips.iter().any(|ip| ip.parse() == Ok(peer)));
+        if !allowed {
+            return Err(AuthError::IpNotAllowed);
+        }
+    }
+    if !nonces.insert(key_id, req.nonce, now) {
+        return Err(AuthError::NonceReused);
+    }
+    //read on every request, so changing the owner's role applies to their keys at once
+    let role = user_role(db, key.user_id).await?.ok_or(AuthError::InvalidApiKey)?;
+    let scopes = key.scopes.iter().filter_map(|s| s.parse().ok()).collect();
+    Ok((key, ApiKeyAuth { key_id, scopes, role }))
+}
+
+// Nonces seen in the last two windows. A timestamp is accepted for a window on either side of
+// it, so after that long a nonce could not pass the timestamp check anymore and is forgotten.
+// It lives in AppState, every worker has to see the same set.
+#[derive(Default)]
+pub struct NonceCache {
+    inner: Mutex<Nonces>,
+}
+
+#[derive(Default)]
+struct Nonces {
+    seen: HashSet<(Uuid, String)>,
+    //insertion order, so expiry times are ascending
+    expiry: VecDeque<(i64, Uuid, String)>,
+}
+
+impl NonceCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // false if the key used this nonce before
+    pub fn insert(&self, key_id: Uuid, nonce: &str, now_ms: i64) -> bool {
+        let mut nonces = self.inner.lock().unwrap();
+        while let Some((expires, ..)) = nonces.expiry.front() && *expires <= now_ms {
+            let (_, key_id, nonce) = nonces.expiry.pop_front().unwrap();
+            nonces.seen.remove(&(key_id, nonce));
+        }
+        if !nonces.seen.insert((key_id, nonce.to_string())) {
+            return false;
+        }
+        nonces.expiry.push_back((now_ms + 2 * SIGNATURE_WINDOW_MS, key_id, nonce.to_string()));
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok()).collect()
+}
@@ -0,0 +1,164 @@
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    web::{Bytes, Data},
+    Error, HttpMessage, ResponseError,
+};
+use actix_web::body::EitherBody;
+use futures_util::future::LocalBoxFuture;
+use std::{future::ready, rc::Rc};
+use crate::{
+    API_KEY_HEADER, API_NONCE_HEADER, API_SIGNATURE_HEADER, API_TIMESTAMP_HEADER, ApiKeyAuth, AuthError, AuthUser,
+    SignedRequest, authenticate, state::AppState, types::ApiScope,
+};
+
+// Authenticates requests carrying X-API-KEY and passes everything else on untouched. Wrap it
+// outside JwtMiddleware on the same scope, JwtMiddleware lets requests it has authenticated through.
+pub struct ApiKeyMiddleware;
+
+pub struct ApiKeyMiddlewareService<S> {
+    pub service: Rc<S>,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ApiKeyMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = ApiKeyMiddlewareService<S>;
+    type InitError = ();
+    type Future = std::future::Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ApiKeyMiddlewareService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for ApiKeyMiddlewareService<S>
+where
+    B: 'static,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(
+        &self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            if !req.headers().contains_key(API_KEY_HEADER) {
+                let res = service.call(req).await?;
+                return Ok(res.map_into_left_body());
+            }
+
+            //the body is part of the signature, read it and put it back for the handler
+            let body = req.extract::<Bytes>().await?;
+            req.set_payload(body.clone().into());
+
+            let state = req.app_data::<Data<AppState>>().cloned().expect("AppState is registered");
+            let header = |name| req.headers().get(name).and_then(|v| v.to_str().ok()).unwrap_or_default();
+            let signed = SignedRequest {
+                key_id: header(API_KEY_HEADER),
+                timestamp: header(API_TIMESTAMP_HEADER),
+                nonce: header(API_NONCE_HEADER),
+                signature: header(API_SIGNATURE_HEADER),
+                method: req.method().as_str(),
+                path: req.uri().path_and_query().map(|p| p.as_str()).unwrap_or("/"),
+                body: &body,
+                //the socket address, a forwarded-for header would let anyone pick their address
+                peer: req.peer_addr().map(|addr| addr.ip()),
+            };
+
+            match authenticate(&state.db, &state.api_key_cipher, &state.nonces, signed).await {
+                Ok((key, auth)) => {
+                    req.extensions_mut().insert(AuthUser(key.user_id));
+                    req.extensions_mut().insert(auth.role);
+                    req.extensions_mut().insert(auth);
+
+                    let res = service.call(req).await?;
+                    Ok(res.map_into_left_body())
+                }
+
+                Err(e) => {
+                    let resp = e.error_response().map_into_right_body();
+                    Ok(req.into_response(resp))
+                }
+            }
+        })
+    }
+}
+
+// Refuses API key requests without the given scope. Requests authenticated with a token are
+// signed-in users and pass.
+pub struct RequireScope(pub ApiScope);
+
+pub struct RequireScopeService<S> {
+    pub service: Rc<S>,
+    pub scope: ApiScope,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequireScope
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RequireScopeService<S>;
+    type InitError = ();
+    type Future = std::future::Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireScopeService {
+            service: Rc::new(service),
+            scope: self.0,
+        }))
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for RequireScopeService<S>
+where
+    B: 'static,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(
+        &self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let scope = self.scope;
+
+        Box::pin(async move {
+            let refused = req.extensions().get::<ApiKeyAuth>().is_some_and(|key| !key.scopes.contains(&scope));
+            if refused {
+                let resp = AuthError::MissingScope(scope).error_response().map_into_right_body();
+                return Ok(req.into_response(resp));
+            }
+            let res = service.call(req).await?;
+            Ok(res.map_into_left_body())
+        })
+    }
+}
@@ -0,0 +1,126 @@
+use std::fmt;
+
+use actix_web::{HttpResponse, ResponseError, http::{StatusCode, header::RETRY_AFTER}};
+
+use crate::types::{ApiScope, Response};
+
+// Why an auth request failed. The variants are for logs and tests; clients only ever see one
+// message per status, so a failed sign-in does not tell whether the email exists and a refused
+// sign-up does not confirm who is registered.
+#[derive(Debug, PartialEq, Eq)]
+pub enum AuthError {
+    UnknownUser,
+    WrongPassword,
+    DuplicateEmail,
+    InvalidRefreshToken,
+    //an already rotated refresh token came back, its session has been revoked
+    RefreshTokenReused,
+    SessionNotFound,
+    //unknown or revoked key, malformed headers or a wrong signature
+    InvalidApiKey,
+    StaleRequest,
+    NonceReused,
+    IpNotAllowed,
+    MissingScope(ApiScope),
+    ApiKeyNotFound,
+    //the session has no second factor and the endpoint wants one
+    MfaRequired,
+    InvalidMfaChallenge,
+    InvalidSecondFactor,
+    TotpAlreadyEnabled,
+    TotpNotPending,
+    TotpNotEnabled,
+    //the caller's role is not one RequireRole lets through
+    RoleNotAllowed,
+    UserNotFound,
+    //the account or the address failed to sign in too often, seconds until it may try again
+    TooManyAttempts(u64),
+    //the caller's order bucket is empty, seconds until it holds enough again
+    RateLimited(u64),
+    InvalidInput(&'static str),
+    Internal(String),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::UnknownUser => write!(f, "unknown user"),
+            AuthError::WrongPassword => write!(f, "wrong password"),
+            AuthError::DuplicateEmail => write!(f, "email already registered"),
+            AuthError::InvalidRefreshToken => write!(f, "invalid refresh token"),
+            AuthError::RefreshTokenReused => write!(f, "refresh token reused"),
+            AuthError::SessionNotFound => write!(f, "session not found"),
+            AuthError::InvalidApiKey => write!(f, "invalid api key or signature"),
+            AuthError::StaleRequest => write!(f, "request timestamp outside the window"),
+            AuthError::NonceReused => write!(f, "nonce reused"),
+            AuthError::IpNotAllowed => write!(f, "address not on the key's allowlist"),
+            AuthError::MissingScope(scope) => write!(f, "api key lacks the {} scope", scope.as_str()),
+            AuthError::ApiKeyNotFound => write!(f, "api key not found"),
+            AuthError::MfaRequired => write!(f, "session is not 2fa verified"),
+            AuthError::InvalidMfaChallenge => write!(f, "unknown, expired or exhausted mfa challenge"),
+            AuthError::InvalidSecondFactor => write!(f, "wrong or reused code"),
+            AuthError::TotpAlreadyEnabled => write!(f, "totp already enabled"),
+            AuthError::TotpNotPending => write!(f, "no totp enrollment to confirm"),
+            AuthError::TotpNotEnabled => write!(f, "totp not enabled"),
+            AuthError::RoleNotAllowed => write!(f, "role not allowed"),
+            AuthError::UserNotFound => write!(f, "user not found"),
+            AuthError::TooManyAttempts(wait) => write!(f, "too many attempts, retry in {wait}s"),
+            AuthError::RateLimited(wait) => write!(f, "rate limited, retry in {wait}s"),
+            AuthError::InvalidInput(reason) => write!(f, "{reason}"),
+            AuthError::Internal(e) => write!(f, "internal error: {e}"),
+        }
+    }
+}
+
+impl ResponseError for AuthError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AuthError::UnknownUser | AuthError::WrongPassword => StatusCode::UNAUTHORIZED,
+            AuthError::InvalidRefreshToken | AuthError::RefreshTokenReused => StatusCode::UNAUTHORIZED,
+            AuthError::InvalidApiKey | AuthError::StaleRequest | AuthError::NonceReused => StatusCode::UNAUTHORIZED,
+            AuthError::InvalidMfaChallenge | AuthError::InvalidSecondFactor => StatusCode::UNAUTHORIZED,
+            AuthError::IpNotAllowed | AuthError::MissingScope(_) | AuthError::MfaRequired => StatusCode::FORBIDDEN,
+            AuthError::RoleNotAllowed => StatusCode::FORBIDDEN,
+            AuthError::DuplicateEmail | AuthError::TotpAlreadyEnabled => StatusCode::CONFLICT,
+            AuthError::SessionNotFound | AuthError::ApiKeyNotFound | AuthError::UserNotFound => StatusCode::NOT_FOUND,
+            AuthError::TotpNotPending | AuthError::TotpNotEnabled => StatusCode::NOT_FOUND,
+            AuthError::TooManyAttempts(_) | AuthError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            AuthError::InvalidInput(_) => StatusCode::BAD_REQUEST,
+            AuthError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let error = match self {
+            AuthError::UnknownUser | AuthError::WrongPassword => "Invalid email or password",
+            AuthError::DuplicateEmail => "Could not create an account with these details",
+            AuthError::InvalidRefreshToken | AuthError::RefreshTokenReused => "Invalid refresh token",
+            AuthError::SessionNotFound => "Session not found",
+            AuthError::InvalidApiKey => "Invalid API key or signature",
+            AuthError::StaleRequest => "Request timestamp outside the allowed window",
+            AuthError::NonceReused => "Nonce already used",
+            AuthError::IpNotAllowed => "Address not allowed for this API key",
+            AuthError::MissingScope(ApiScope::Read) => "API key lacks the read scope",
+            AuthError::MissingScope(ApiScope::Trade) => "API key lacks the trade scope",
+            AuthError::MissingScope(ApiScope::Withdraw) => "API key lacks the withdraw scope",
+            AuthError::ApiKeyNotFound => "API key not found",
+            AuthError::MfaRequired => "Two-factor authentication required",
+            AuthError::InvalidMfaChallenge => "Invalid or expired two-factor challenge",
+            AuthError::InvalidSecondFactor => "Invalid two-factor code",
+            AuthError::TotpAlreadyEnabled => "Two-factor authentication is already enabled",
+            AuthError::TotpNotPending => "No two-factor enrollment to confirm",
+            AuthError::TotpNotEnabled => "Two-factor authentication is not enabled",
+            AuthError::RoleNotAllowed => "Not permitted for this account",
+            AuthError::UserNotFound => "User not found",
+            AuthError::TooManyAttempts(_) => "Too many sign-in attempts, try again later",
+            AuthError::RateLimited(_) => "Too many order requests, slow down",
+            AuthError::InvalidInput(reason) => reason,
+            AuthError::Internal(_) => "Internal error",
+        };
+        let mut response = HttpResponse::build(self.status_code());
+        if let AuthError::TooManyAttempts(wait) | AuthError::RateLimited(wait) = self {
+            response.insert_header((RETRY_AFTER, wait.to_string()));
+        }
+        response.json(Response { message: String::new(), error: error.to_string() })
+    }
+}
@@ -0,0 +1,51 @@
+use std::future::{Ready, ready};
+
+use actix_web::{FromRequest, HttpMessage, HttpRequest, dev::Payload, error::ErrorUnauthorized};
+use uuid::Uuid;
+
+use crate::{AuthError, UserId};
+
+// The caller as verified by JwtMiddleware. Handlers take the user id from here and never from
+// the request body; outside a JwtMiddleware scope extraction fails with 401.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuthUser(pub UserId);
+
+// The session the caller's token was issued for, set by JwtMiddleware next to AuthUser.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuthSession(pub Uuid);
+
+// Set by JwtMiddleware for tokens of sessions opened with a second factor. Sensitive handlers
+// take it and refuse everything else with 403, API key requests included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MfaVerified;
+
+impl FromRequest for AuthUser {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(from_extensions(req))
+    }
+}
+
+impl FromRequest for AuthSession {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(from_extensions(req))
+    }
+}
+
+impl FromRequest for MfaVerified {
+    type Error = AuthError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(req.extensions().get::<MfaVerified>().copied().ok_or(AuthError::MfaRequired))
+    }
+}
+
+fn from_extensions<T: Copy + 'static>(req: &HttpRequest) -> Result<T, actix_web::Error> {
+    req.extensions().get::<T>().copied().ok_or_else(|| ErrorUnauthorized("Missing Token"))
+}
@@ -1,31 +1,37 @@
-use jsonwebtoken::{encode, Header, EncodingKey};
 use serde::{Serialize, Deserialize};
 use chrono::{Utc, Duration};
 use uuid::Uuid;
 
+use crate::{KeySet, types::Role};
+
+// access tokens are not checked against the session store, so this is how long a revoked
+// session keeps working at most
+pub const ACCESS_TOKEN_TTL: Duration = Duration::minutes(15);
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: Uuid,           
+    pub sid: Uuid,           //session the token was issued for
+    #[serde(default)]
+    pub mfa: bool,           //the session was opened with a second factor
+    #[serde(default)]
+    pub role: Role,          //as of signin or the last refresh
     pub exp: usize,        
 }
 
-pub fn create_jwt(user_id: Uuid) -> String {
-    let secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-
+pub fn create_jwt(keys: &KeySet, user_id: Uuid, session_id: Uuid, mfa: bool, role: Role) -> String {
     let expiration = Utc::now()
-        .checked_add_signed(Duration::hours(24))
+        .checked_add_signed(ACCESS_TOKEN_TTL)
         .unwrap()
         .timestamp() as usize;
 
     let claims = Claims {
         sub: user_id,
+        sid: session_id,
+        mfa,
+        role,
         exp: expiration,
     };
 
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret.as_ref()),
-    )
-    .unwrap()
+    keys.sign(&claims)
 }
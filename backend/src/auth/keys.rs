@@ -0,0 +1,112 @@
+// Access token keys. Tokens are signed with Ed25519 (EdDSA) by the active key and name it in
+// their kid header; any key of the set verifies. To rotate, start signing with a new key and keep
+// the old public key in JWT_VERIFY_KEYS until the last token it signed has expired, that is
+// ACCESS_TOKEN_TTL. Other services verify against the JWKS, which lists every key of the set, and
+// should fetch it again when they meet a kid they do not know.
+
+use std::{collections::HashMap, fs};
+
+use anyhow::{Context, Result};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use ed25519_dalek::{SigningKey, VerifyingKey, pkcs8::{DecodePrivateKey, DecodePublicKey, EncodePrivateKey}};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, decode_header, encode,
+    errors::{Error, ErrorKind},
+    jwk::{AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm, OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, ThumbprintHash},
+};
+
+use crate::Claims;
+
+pub struct KeySet {
+    active_kid: String,
+    signing: EncodingKey,
+    verifying: HashMap<String, DecodingKey>,
+    jwks: JwkSet,
+}
+
+impl KeySet {
+    // signs with `active`, verifies with it and with every key in `previous`
+    pub fn new(active: &SigningKey, previous: &[VerifyingKey]) -> Self {
+        let der = active.to_pkcs8_der().expect("an ed25519 key always encodes");
+        let mut keys = KeySet {
+            active_kid: String::new(),
+            signing: EncodingKey::from_ed_der(der.as_bytes()),
+            verifying: HashMap::new(),
+            jwks: JwkSet { keys: Vec::new() },
+        };
+        keys.active_kid = keys.add(&active.verifying_key());
+        for key in previous {
+            keys.add(key);
+        }
+        keys
+    }
+
+    // a random key that dies with the process, for tests only
+    pub fn generate() -> Self {
+        let mut seed = [0u8; 32];
+        OsRng.fill_bytes(&mut seed);
+        KeySet::new(&SigningKey::from_bytes(&seed), &[])
+    }
+
+    // JWT_SIGNING_KEY is the path of the active PKCS#8 private key, JWT_VERIFY_KEYS a comma
+    // separated list of public key files that are still accepted. There is no throwaway fallback,
+    // every restart would log everyone out and instances would reject each other's tokens.
+    pub fn from_env() -> Result<Self> {
+        let path = std::env::var("JWT_SIGNING_KEY").context("JWT_SIGNING_KEY is not set")?;
+        let pem = fs::read_to_string(&path).with_context(|| format!("reading {path}"))?;
+        let active = SigningKey::from_pkcs8_pem(&pem).with_context(|| format!("{path} is not an ed25519 private key"))?;
+
+        let mut previous = Vec::new();
+        for path in std::env::var("JWT_VERIFY_KEYS").unwrap_or_default().split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let pem = fs::read_to_string(path).with_context(|| format!("reading {path}"))?;
+            previous.push(VerifyingKey::from_public_key_pem(&pem).with_context(|| format!("{path} is not an ed25519 public key"))?);
+        }
+        Ok(KeySet::new(&active, &previous))
+    }
+
+    // the kid is the RFC 7638 thumbprint of the public key, so it is the same on every instance
+    fn add(&mut self, key: &VerifyingKey) -> String {
+        let x = URL_SAFE_NO_PAD.encode(key.as_bytes());
+        let mut jwk = Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                key_algorithm: Some(KeyAlgorithm::EdDSA),
+                ..Default::default()
+            },
+            algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: x.clone(),
+            }),
+        };
+        let kid = jwk.thumbprint(ThumbprintHash::SHA256);
+        if !self.verifying.contains_key(&kid) {
+            jwk.common.key_id = Some(kid.clone());
+            self.jwks.keys.push(jwk);
+            self.verifying.insert(kid.clone(), DecodingKey::from_ed_components(&x).expect("x is valid base64"));
+        }
+        kid
+    }
+
+    pub fn active_kid(&self) -> &str {
+        &self.active_kid
+    }
+
+    pub fn jwks(&self) -> &JwkSet {
+        &self.jwks
+    }
+
+    pub fn sign(&self, claims: &Claims) -> String {
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some(self.active_kid.clone());
+        encode(&header, claims, &self.signing).expect("claims always serialize")
+    }
+
+    // only EdDSA tokens naming a key of the set are accepted
+    pub fn verify(&self, token: &str) -> Result<Claims, Error> {
+        let kid = decode_header(token)?.kid.ok_or(ErrorKind::InvalidToken)?;
+        let key = self.verifying.get(&kid).ok_or(ErrorKind::InvalidToken)?;
+        Ok(decode::<Claims>(token, key, &Validation::new(Algorithm::EdDSA))?.claims)
+    }
+}
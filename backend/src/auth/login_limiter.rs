@@ -0,0 +1,254 @@
+// Sign-in throttling against credential stuffing and password guessing. Failed sign-ins, wrong
+// passwords and wrong second factors alike, are counted per account and per client address.
+// Past a few free attempts every failure makes the key wait twice as long as the last one before
+// it may try again, and a long enough run locks it out. A locked key is refused with 429 before
+// the password is looked at, the right one included. Every failure is also written to
+// login_failures for audits.
+//
+// Counters live behind AttemptStore, in memory unless LOGIN_LIMITER_STORE=postgres. The memory
+// store is per process, so with several instances each one counts on its own.
+
+use std::{collections::HashMap, net::IpAddr, sync::Mutex};
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use db::Db;
+use futures_util::future::BoxFuture;
+
+use crate::AuthError;
+
+// how failures of one key turn into waiting time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoginPolicy {
+    pub free_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub lockout_after: u32,
+    pub lockout: Duration,
+    //a failure this long after the previous one starts a new run
+    pub forget_after: Duration,
+}
+
+impl LoginPolicy {
+    pub fn per_account() -> Self {
+        LoginPolicy {
+            free_attempts: 3,
+            base_delay: Duration::seconds(1),
+            max_delay: Duration::minutes(1),
+            lockout_after: 10,
+            lockout: Duration::minutes(15),
+            forget_after: Duration::minutes(15),
+        }
+    }
+
+    // many users can share an address, so it gets more room before slowing down
+    pub fn per_ip() -> Self {
+        LoginPolicy {
+            free_attempts: 10,
+            lockout_after: 50,
+            ..LoginPolicy::per_account()
+        }
+    }
+
+    // how long a key waits after its `failures`th failure in a row, None if it may try again at once
+    pub fn lock_for(&self, failures: u32) -> Option<Duration> {
+        if failures >= self.lockout_after {
+            return Some(self.lockout);
+        }
+        let over = failures.checked_sub(self.free_attempts).filter(|&over| over > 0)?;
+        let delay = self.base_delay.checked_mul(1 << (over - 1).min(20)).unwrap_or(self.max_delay);
+        Some(delay.min(self.max_delay))
+    }
+}
+
+// Where the counters are kept. Keys are opaque strings, an account or an address.
+pub trait AttemptStore: Send + Sync {
+    fn locked_until<'a>(&'a self, key: &'a str, now: DateTime<Utc>) -> BoxFuture<'a, Result<Option<DateTime<Utc>>>>;
+
+    // counts a failure and returns the end of the lock it earns under `policy`, if any
+    fn record_failure<'a>(&'a self, key: &'a str, now: DateTime<Utc>, policy: &'a LoginPolicy) -> BoxFuture<'a, Result<Option<DateTime<Utc>>>>;
+
+    fn clear<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<()>>;
+}
+
+// keys nobody failed with for this many entries are dropped once the map reaches the size
+const MAX_TRACKED_KEYS: usize = 100_000;
+
+#[derive(Default)]
+pub struct MemoryAttemptStore {
+    attempts: Mutex<HashMap<String, Attempts>>,
+}
+
+struct Attempts {
+    failures: u32,
+    last_failure: DateTime<Utc>,
+    locked_until: Option<DateTime<Utc>>,
+    forget_after: Duration,
+}
+
+impl MemoryAttemptStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl AttemptStore for MemoryAttemptStore {
+    fn locked_until<'a>(&'a self, key: &'a str, now: DateTime<Utc>) -> BoxFuture<'a, Result<Option<DateTime<Utc>>>> {
+        let attempts = self.attempts.lock().unwrap();
+        let locked_until = attempts.get(key).and_then(|a| a.locked_until).filter(|&until| until > now);
+        Box::pin(async move { Ok(locked_until) })
+    }
+
+    fn record_failure<'a>(&'a self, key: &'a str, now: DateTime<Utc>, policy: &'a LoginPolicy) -> BoxFuture<'a, Result<Option<DateTime<Utc>>>> {
+        let mut attempts = self.attempts.lock().unwrap();
+        if attempts.len() >= MAX_TRACKED_KEYS {
+            attempts.retain(|_, a| a.last_failure + a.forget_after > now || a.locked_until.is_some_and(|until| until > now));
+        }
+        let entry = attempts.entry(key.to_string()).or_insert(Attempts {
+            failures: 0,
+            last_failure: now,
+            locked_until: None,
+            forget_after: policy.forget_after,
+        });
+        if entry.last_failure + policy.forget_after <= now {
+            entry.failures = 0;
+        }
+        entry.failures += 1;
+        entry.last_failure = now;
+        entry.forget_after = policy.forget_after;
+        entry.locked_until = policy.lock_for(entry.failures).map(|wait| now + wait);
+        let locked_until = entry.locked_until;
+        Box::pin(async move { Ok(locked_until) })
+    }
+
+    fn clear<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<()>> {
+        self.attempts.lock().unwrap().remove(key);
+        Box::pin(async move { Ok(()) })
+    }
+}
+
+// counters in the login_throttle table, shared by every instance on the database
+pub struct PgAttemptStore {
+    db: Db,
+}
+
+impl PgAttemptStore {
+    pub fn new(db: Db) -> Self {
+        Self { db }
+    }
+}
+
+impl AttemptStore for PgAttemptStore {
+    fn locked_until<'a>(&'a self, key: &'a str, now: DateTime<Utc>) -> BoxFuture<'a, Result<Option<DateTime<Utc>>>> {
+        Box::pin(async move { Ok(self.db.login_locked_until(key).await?.filter(|&until| until > now)) })
+    }
+
+    fn record_failure<'a>(&'a self, key: &'a str, now: DateTime<Utc>, policy: &'a LoginPolicy) -> BoxFuture<'a, Result<Option<DateTime<Utc>>>> {
+        Box::pin(async move {
+            let failures = self.db.count_login_failure(key, now, now - policy.forget_after).await?;
+            let locked_until = policy.lock_for(failures.max(0) as u32).map(|wait| now + wait);
+            if let Some(until) = locked_until {
+                self.db.lock_login(key, until).await?;
+            }
+            Ok(locked_until)
+        })
+    }
+
+    fn clear<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move { self.db.clear_login_failures(key).await })
+    }
+}
+
+pub struct LoginLimiter {
+    store: Box<dyn AttemptStore>,
+    per_account: LoginPolicy,
+    per_ip: LoginPolicy,
+}
+
+impl LoginLimiter {
+    pub fn new(store: impl AttemptStore + 'static) -> Self {
+        Self {
+            store: Box::new(store),
+            per_account: LoginPolicy::per_account(),
+            per_ip: LoginPolicy::per_ip(),
+        }
+    }
+
+    pub fn with_policies(mut self, per_account: LoginPolicy, per_ip: LoginPolicy) -> Self {
+        self.per_account = per_account;
+        self.per_ip = per_ip;
+        self
+    }
+
+    pub fn in_memory() -> Self {
+        LoginLimiter::new(MemoryAttemptStore::new())
+    }
+
+    // LOGIN_LIMITER_STORE=postgres shares the counters between instances, memory otherwise
+    pub fn from_env(db: &Db) -> Self {
+        match std::env::var("LOGIN_LIMITER_STORE").as_deref() {
+            Ok("postgres") => LoginLimiter::new(PgAttemptStore::new(db.clone())),
+            _ => LoginLimiter::in_memory(),
+        }
+    }
+
+    // TooManyAttempts while the account or the address has to wait
+    pub async fn check(&self, email: &str, ip: Option<IpAddr>, now: DateTime<Utc>) -> Result<(), AuthError> {
+        let mut until = None;
+        for key in std::iter::once(account_key(email)).chain(ip.map(ip_key)) {
+            let locked = self.store.locked_until(&key, now).await.map_err(|e| AuthError::Internal(e.to_string()))?;
+            until = until.max(locked);
+        }
+        match until {
+            Some(until) => Err(AuthError::TooManyAttempts(retry_after(until, now))),
+            None => Ok(()),
+        }
+    }
+
+    // counts the failure against the account and the address and writes the audit entry
+    pub async fn failed(&self, db: &Db, email: &str, ip: Option<IpAddr>, reason: &AuthError, now: DateTime<Utc>) -> Result<(), AuthError> {
+        self.audit(db, email, ip, reason).await?;
+        self.store.record_failure(&account_key(email), now, &self.per_account).await.map_err(|e| AuthError::Internal(e.to_string()))?;
+        if let Some(ip) = ip {
+            self.store.record_failure(&ip_key(ip), now, &self.per_ip).await.map_err(|e| AuthError::Internal(e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    // an attempt refused by check, audited but not counted so waiting it out is enough
+    pub async fn refused(&self, db: &Db, email: &str, ip: Option<IpAddr>, reason: &AuthError) -> Result<(), AuthError> {
+        self.audit(db, email, ip, reason).await
+    }
+
+    // the account starts over, the address does not: one good password must not reset a
+    // stuffing run from there
+    pub async fn succeeded(&self, email: &str) -> Result<(), AuthError> {
+        self.store.clear(&account_key(email)).await.map_err(|e| AuthError::Internal(e.to_string()))
+    }
+
+    async fn audit(&self, db: &Db, email: &str, ip: Option<IpAddr>, reason: &AuthError) -> Result<(), AuthError> {
+        let ip = ip.map(|ip| ip.to_string());
+        db.record_login_failure(&normalize(email), ip.as_deref(), &reason.to_string())
+            .await
+            .map_err(|e| AuthError::Internal(e.to_string()))
+    }
+}
+
+// case and surrounding spaces do not make a new account to guess against
+fn normalize(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+fn account_key(email: &str) -> String {
+    format!("account:{}", normalize(email))
+}
+
+fn ip_key(ip: IpAddr) -> String {
+    format!("ip:{ip}")
+}
+
+// whole seconds, rounded up so a client that waits exactly that long gets through
+fn retry_after(until: DateTime<Utc>, now: DateTime<Utc>) -> u64 {
+    let millis = (until - now).num_milliseconds().max(0) as u64;
+    millis.div_ceil(1000).max(1)
+}
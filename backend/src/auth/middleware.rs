@@ -4,9 +4,9 @@ use actix_web::{
 };
 use actix_web::body::EitherBody;
 use futures_util::future::LocalBoxFuture;
-use jsonwebtoken::{decode, DecodingKey, Validation};
 use std::{future::ready, rc::Rc};
-use crate::Claims;
+use actix_web::web::Data;
+use crate::{AuthSession, AuthUser, MfaVerified, state::AppState};
 
 pub struct JwtMiddleware;
 
@@ -50,11 +50,17 @@ where
         self.service.poll_ready(cx)
     }
 
-    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+    fn call(&self, req: ServiceRequest) -> Self::Future {
         let service = self.service.clone();
 
         Box::pin(async move {
 
+            //a signed API key request, ApiKeyMiddleware already authenticated it
+            if req.extensions().contains::<AuthUser>() {
+                let res = service.call(req).await?;
+                return Ok(res.map_into_left_body());
+            }
+
             let auth_header = req
                 .headers()
                 .get("Authorization")
@@ -71,18 +77,17 @@ where
             let token = auth_header.unwrap().trim_start_matches("Bearer ").trim();
 
 
-            let secret = std::env::var("JWT_SECRET").unwrap();
-            let validation = Validation::default();
-
-            let decoded = decode::<Claims>(
-                token,
-                &DecodingKey::from_secret(secret.as_bytes()),
-                &validation,
-            );
+            let state = req.app_data::<Data<AppState>>().cloned().expect("AppState is registered");
+            let decoded = state.keys.verify(token);
 
             match decoded {
-                Ok(data) => {
-                    req.extensions_mut().insert(data.claims.sub);
+                Ok(claims) => {
+                    req.extensions_mut().insert(AuthUser(claims.sub));
+                    req.extensions_mut().insert(AuthSession(claims.sid));
+                    req.extensions_mut().insert(claims.role);
+                    if claims.mfa {
+                        req.extensions_mut().insert(MfaVerified);
+                    }
 
                     let res = service.call(req).await?;
                     Ok(res.map_into_left_body())  
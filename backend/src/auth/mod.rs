@@ -1,4 +1,26 @@
 pub mod jwt;
 pub use jwt::*;
+pub mod keys;
+pub use keys::*;
 pub mod middleware;
-pub use middleware::*;
\ No newline at end of file
+pub use middleware::*;
+pub mod extractor;
+pub use extractor::*;
+pub mod password;
+pub use password::*;
+pub mod error;
+pub use error::*;
+pub mod session;
+pub use session::*;
+pub mod totp;
+pub use totp::*;
+pub mod api_key;
+pub use api_key::*;
+pub mod api_key_middleware;
+pub use api_key_middleware::*;
+pub mod role_guard;
+pub use role_guard::*;
+pub mod login_limiter;
+pub use login_limiter::*;
+pub mod rate_limit;
+pub use rate_limit::*;
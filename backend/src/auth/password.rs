@@ -0,0 +1,57 @@
+// Argon2id password hashing. Hashes are stored as PHC strings, so algorithm, parameters and salt
+// travel with every hash and the defaults can be raised later without a migration.
+
+use argon2::{
+    Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier,
+    password_hash::{self, SaltString, rand_core::OsRng},
+};
+use db::User;
+use std::sync::LazyLock;
+use subtle::ConstantTimeEq;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordCheck {
+    Valid,
+    // correct, but stored as legacy plaintext or with outdated parameters
+    ValidNeedsRehash,
+    Invalid,
+}
+
+pub fn hash_password(password: &str) -> Result<String, password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default().hash_password(password.as_bytes(), &salt)?.to_string())
+}
+
+pub fn verify_password(user: &User, password: &str) -> PasswordCheck {
+    if let Some(stored) = &user.password_hash {
+        let Ok(hash) = PasswordHash::new(stored) else {
+            return PasswordCheck::Invalid;
+        };
+        //the comparison of the computed and stored hash inside is constant time
+        if Argon2::default().verify_password(password.as_bytes(), &hash).is_err() {
+            return PasswordCheck::Invalid;
+        }
+        return if is_current(&hash) { PasswordCheck::Valid } else { PasswordCheck::ValidNeedsRehash };
+    }
+    match &user.password {
+        Some(legacy) if bool::from(legacy.as_bytes().ct_eq(password.as_bytes())) => PasswordCheck::ValidNeedsRehash,
+        _ => PasswordCheck::Invalid,
+    }
+}
+
+fn is_current(hash: &PasswordHash) -> bool {
+    let current = Params::default();
+    hash.algorithm == argon2::ARGON2ID_IDENT
+        && Params::try_from(hash).is_ok_and(|params| {
+            (params.m_cost(), params.t_cost(), params.p_cost()) == (current.m_cost(), current.t_cost(), current.p_cost())
+        })
+}
+
+// Runs a full verification against a throwaway hash, for sign-ins with an unknown email, so the
+// response time does not give away whether an account exists.
+pub fn verify_unknown_user(password: &str) {
+    static DUMMY: LazyLock<Option<String>> = LazyLock::new(|| hash_password("unknown user").ok());
+    if let Some(hash) = DUMMY.as_deref().and_then(|hash| PasswordHash::new(hash).ok()) {
+        let _ = Argon2::default().verify_password(password.as_bytes(), &hash);
+    }
+}
@@ -0,0 +1,222 @@
+// Order rate limiting in front of the engine. book_tx is one bounded channel for every user, so a
+// single client sending as fast as it can would fill it and everyone else's orders would wait
+// behind its. Each user and each API key has a token bucket sized by the caller's role. Placing or
+// cancelling takes one token per order from the user's bucket and, for signed requests, from the
+// key's too. A batch takes one per command it sends, items it refuses itself cost nothing. A
+// request that finds a bucket short is refused with 429 and a Retry-After before anything is sent
+// to the engine.
+//
+// Buckets live in memory and are per process, like the memory store of the LoginLimiter.
+
+use std::{collections::HashMap, future::ready, rc::Rc, sync::Mutex, time::Instant};
+
+use actix_web::{
+    Error, HttpMessage, ResponseError,
+    body::EitherBody,
+    dev::{Extensions, Service, ServiceRequest, ServiceResponse, Transform},
+    web::Data,
+};
+use futures_util::future::LocalBoxFuture;
+use uuid::Uuid;
+
+use crate::{ApiKeyAuth, AuthError, AuthUser, UserId, state::AppState, types::Role};
+
+// how many orders a bucket holds when full and how many it gets back each second
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_second: u32,
+}
+
+impl RateLimit {
+    pub fn new(burst: u32, per_second: u32) -> Self {
+        assert!(burst > 0 && per_second > 0, "a rate limit must let something through");
+        Self { burst, per_second }
+    }
+
+    // "burst,per_second", as in ORDER_RATE_LIMIT_TRADER=100,20
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let (burst, per_second) = s.split_once(',').ok_or_else(|| format!("expected burst,per_second, got {s}"))?;
+        let number = |n: &str| n.trim().parse::<u32>().ok().filter(|&n| n > 0).ok_or_else(|| format!("{n} is not a positive number"));
+        Ok(Self { burst: number(burst)?, per_second: number(per_second)? })
+    }
+}
+
+// buckets that are full again are dropped once there are this many
+const MAX_BUCKETS: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum BucketKey {
+    User(UserId),
+    ApiKey(Uuid),
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    limit: RateLimit,
+}
+
+impl Bucket {
+    // tops the bucket up for the time since the last request, a role change applies from here on
+    fn refill(&mut self, limit: RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_second as f64).min(limit.burst as f64);
+        self.updated = now;
+        self.limit = limit;
+    }
+
+    fn is_full(&self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens + elapsed * self.limit.per_second as f64 >= self.limit.burst as f64
+    }
+}
+
+pub struct OrderRateLimiter {
+    limits: HashMap<Role, RateLimit>,
+    buckets: Mutex<HashMap<BucketKey, Bucket>>,
+}
+
+impl Default for OrderRateLimiter {
+    // market makers quote both sides and requote on every move, they get the most room
+    fn default() -> Self {
+        let trader = RateLimit::new(100, 20);
+        Self {
+            limits: HashMap::from([
+                (Role::Trader, trader),
+                (Role::MarketMaker, RateLimit::new(500, 200)),
+                (Role::Admin, trader),
+                (Role::Auditor, trader),
+            ]),
+            buckets: Mutex::default(),
+        }
+    }
+}
+
+impl OrderRateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_limit(mut self, role: Role, limit: RateLimit) -> Self {
+        self.limits.insert(role, limit);
+        self
+    }
+
+    // ORDER_RATE_LIMIT_<ROLE>=burst,per_second replaces the default of that role
+    pub fn from_env() -> Result<Self, String> {
+        let mut limiter = Self::new();
+        for role in [Role::Trader, Role::MarketMaker, Role::Admin, Role::Auditor] {
+            let var = format!("ORDER_RATE_LIMIT_{}", role.as_str().to_uppercase());
+            if let Ok(value) = std::env::var(&var) {
+                limiter = limiter.with_limit(role, RateLimit::parse(&value).map_err(|e| format!("{var}: {e}"))?);
+            }
+        }
+        Ok(limiter)
+    }
+
+    pub fn limit(&self, role: Role) -> RateLimit {
+        self.limits[&role]
+    }
+
+    // Takes `cost` tokens from the user's bucket and the key's, or from neither: RateLimited with
+    // the seconds until both hold enough. A cost above the burst could never go through.
+    pub fn take(&self, user_id: UserId, api_key: Option<Uuid>, role: Role, cost: u32, now: Instant) -> Result<(), AuthError> {
+        let limit = self.limit(role);
+        if cost > limit.burst {
+            return Err(AuthError::InvalidInput("More orders in one request than the rate limit allows"));
+        }
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_BUCKETS {
+            buckets.retain(|_, bucket| !bucket.is_full(now));
+        }
+        let keys: Vec<_> = std::iter::once(BucketKey::User(user_id)).chain(api_key.map(BucketKey::ApiKey)).collect();
+        let mut short = 0f64;
+        for key in &keys {
+            let bucket = buckets.entry(*key).or_insert(Bucket { tokens: limit.burst as f64, updated: now, limit });
+            bucket.refill(limit, now);
+            short = short.max(cost as f64 - bucket.tokens);
+        }
+        if short > 0.0 {
+            return Err(AuthError::RateLimited(retry_after(short, limit)));
+        }
+        for key in &keys {
+            buckets.get_mut(key).unwrap().tokens -= cost as f64;
+        }
+        Ok(())
+    }
+
+    // charges the caller JwtMiddleware or ApiKeyMiddleware put on the request, nobody there means
+    // the handler's AuthUser answers 401 anyway
+    pub fn charge(&self, extensions: &Extensions, cost: u32, now: Instant) -> Result<(), AuthError> {
+        let Some(AuthUser(user_id)) = extensions.get::<AuthUser>().copied() else {
+            return Ok(());
+        };
+        let role = extensions.get::<Role>().copied().unwrap_or_default();
+        let api_key = extensions.get::<ApiKeyAuth>().map(|key| key.key_id);
+        self.take(user_id, api_key, role, cost, now)
+    }
+}
+
+// whole seconds, rounded up so a client that waits exactly that long gets through
+fn retry_after(short: f64, limit: RateLimit) -> u64 {
+    ((short / limit.per_second as f64).ceil() as u64).max(1)
+}
+
+// Charges one order per request against AppState::order_limiter. Mount it as the innermost wrap
+// of a resource, so requests the auth and role guards turn away do not use up tokens.
+pub struct OrderRateLimit;
+
+pub struct OrderRateLimitService<S> {
+    pub service: Rc<S>,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for OrderRateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = OrderRateLimitService<S>;
+    type InitError = ();
+    type Future = std::future::Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(OrderRateLimitService { service: Rc::new(service) }))
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for OrderRateLimitService<S>
+where
+    B: 'static,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(
+        &self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            let state = req.app_data::<Data<AppState>>().cloned().expect("AppState is registered");
+            let charged = state.order_limiter.charge(&req.extensions(), 1, Instant::now());
+            if let Err(e) = charged {
+                let resp = e.error_response().map_into_right_body();
+                return Ok(req.into_response(resp));
+            }
+            let res = service.call(req).await?;
+            Ok(res.map_into_left_body())
+        })
+    }
+}
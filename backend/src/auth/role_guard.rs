@@ -0,0 +1,71 @@
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    Error, HttpMessage, ResponseError,
+};
+use actix_web::body::EitherBody;
+use futures_util::future::LocalBoxFuture;
+use std::{future::ready, rc::Rc};
+use crate::{AuthError, types::Role};
+
+// Lets through callers whose role is one of the given ones, tokens and API keys alike. Goes inside
+// the JwtMiddleware scope, which is what puts the caller's role on the request.
+pub struct RequireRole(pub &'static [Role]);
+
+pub struct RequireRoleService<S> {
+    pub service: Rc<S>,
+    pub roles: &'static [Role],
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequireRole
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RequireRoleService<S>;
+    type InitError = ();
+    type Future = std::future::Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireRoleService {
+            service: Rc::new(service),
+            roles: self.0,
+        }))
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for RequireRoleService<S>
+where
+    B: 'static,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(
+        &self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let roles = self.roles;
+
+        Box::pin(async move {
+            //no role at all means the guard was mounted outside the authenticated scope
+            let allowed = req.extensions().get::<Role>().is_some_and(|role| roles.contains(role));
+            if !allowed {
+                let resp = AuthError::RoleNotAllowed.error_response().map_into_right_body();
+                return Ok(req.into_response(resp));
+            }
+            let res = service.call(req).await?;
+            Ok(res.map_into_left_body())
+        })
+    }
+}
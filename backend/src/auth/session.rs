@@ -0,0 +1,72 @@
+// Sessions and refresh tokens. Signing in opens a session that lives SESSION_TTL. Each refresh
+// retires the presented refresh token and hands out a new one in the same session; a retired
+// token showing up again means it leaked, and the whole session is revoked with it.
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::{Duration, Utc};
+use db::{Db, RefreshOutcome};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{ACCESS_TOKEN_TTL, AuthError, KeySet, UserId, create_jwt, types::{Role, TokenResponse}};
+
+pub const SESSION_TTL: Duration = Duration::days(30);
+
+// mfa marks a session opened with a second factor, see totp
+pub async fn open_session(db: &Db, keys: &KeySet, user_id: UserId, mfa: bool) -> Result<TokenResponse, AuthError> {
+    let role = user_role(db, user_id).await?.ok_or(AuthError::UnknownUser)?;
+    let (refresh_token, token_hash) = new_opaque_token();
+    let session_id = db
+        .create_session(user_id, Utc::now() + SESSION_TTL, &token_hash, mfa)
+        .await
+        .map_err(|e| AuthError::Internal(e.to_string()))?;
+    Ok(tokens(keys, user_id, session_id, mfa, role, refresh_token))
+}
+
+pub async fn refresh_session(db: &Db, keys: &KeySet, refresh_token: &str) -> Result<TokenResponse, AuthError> {
+    let (next, next_hash) = new_opaque_token();
+    let outcome = db
+        .rotate_refresh_token(&hash_opaque_token(refresh_token), &next_hash)
+        .await
+        .map_err(|e| AuthError::Internal(e.to_string()))?;
+    match outcome {
+        //the role is read again, so a changed role reaches the token on the next refresh
+        RefreshOutcome::Rotated { session_id, user_id, mfa, role } => {
+            let role = role.parse().map_err(AuthError::Internal)?;
+            Ok(tokens(keys, user_id, session_id, mfa, role, next))
+        }
+        RefreshOutcome::Reused { session_id, user_id } => {
+            eprintln!("[AUTH] refresh token reused, revoked session {session_id} of {user_id}");
+            Err(AuthError::RefreshTokenReused)
+        }
+        RefreshOutcome::Invalid => Err(AuthError::InvalidRefreshToken),
+    }
+}
+
+// None for an unknown user
+pub async fn user_role(db: &Db, user_id: UserId) -> Result<Option<Role>, AuthError> {
+    let role = db.get_user_role(user_id).await.map_err(|e| AuthError::Internal(e.to_string()))?;
+    role.map(|role| role.parse().map_err(AuthError::Internal)).transpose()
+}
+
+fn tokens(keys: &KeySet, user_id: UserId, session_id: Uuid, mfa: bool, role: Role, refresh_token: String) -> TokenResponse {
+    TokenResponse {
+        token: create_jwt(keys, user_id, session_id, mfa, role),
+        refresh_token,
+        expires_in: ACCESS_TOKEN_TTL.num_seconds(),
+    }
+}
+
+// 256 random bits for refresh tokens and mfa challenges, only the hash is stored
+pub(crate) fn new_opaque_token() -> (String, String) {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let token: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+    let hash = hash_opaque_token(&token);
+    (token, hash)
+}
+
+// the token is random already, a plain digest is enough to keep it out of the database
+pub(crate) fn hash_opaque_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes()).iter().map(|b| format!("{b:02x}")).collect()
+}
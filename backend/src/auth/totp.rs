@@ -0,0 +1,176 @@
+// Time based one time passwords (RFC 6238) as the second factor: HMAC-SHA1, six digits and a
+// thirty second step, which is what authenticator apps expect from an otpauth URI without
+// further parameters. Recovery codes stand in for the authenticator once each.
+//
+// A user with a confirmed authenticator gets an mfa challenge from /signin instead of a session,
+// and only /signin/2fa with the challenge and a code opens one. Such sessions carry the mfa
+// claim, which MfaVerified requires.
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::{Duration, Utc};
+use db::Db;
+use hmac::{Hmac, Mac};
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use crate::{AuthError, UserId, hash_opaque_token, new_opaque_token, types::MfaChallengeResponse};
+
+pub const TOTP_ISSUER: &str = "Perp";
+pub const TOTP_PERIOD_SECS: i64 = 30;
+pub const TOTP_DIGITS: u32 = 6;
+pub const RECOVERY_CODES: usize = 10;
+pub const MFA_CHALLENGE_TTL: Duration = Duration::minutes(5);
+// wrong codes a challenge takes before the password has to be entered again
+pub const MAX_MFA_ATTEMPTS: i32 = 5;
+
+const BASE32: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+// 160 random bits, base32 encoded as authenticator apps take them
+pub fn generate_totp_secret() -> String {
+    let mut bytes = [0u8; 20];
+    OsRng.fill_bytes(&mut bytes);
+    base32(&bytes)
+}
+
+pub fn otpauth_uri(email: &str, secret: &str) -> String {
+    let label = utf8_percent_encode(&format!("{TOTP_ISSUER}:{email}"), NON_ALPHANUMERIC).to_string();
+    format!("otpauth://totp/{label}?secret={secret}&issuer={TOTP_ISSUER}&algorithm=SHA1&digits={TOTP_DIGITS}&period={TOTP_PERIOD_SECS}")
+}
+
+pub fn totp_step(unix_secs: i64) -> i64 {
+    unix_secs.div_euclid(TOTP_PERIOD_SECS)
+}
+
+// the code an authenticator shows during `step`, None if the secret is not base32
+pub fn totp_code(secret: &str, step: i64) -> Option<String> {
+    let mut mac = Hmac::<Sha1>::new_from_slice(&unbase32(secret)?).expect("hmac takes any key length");
+    mac.update(&step.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    let offset = (digest[19] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([digest[offset] & 0x7f, digest[offset + 1], digest[offset + 2], digest[offset + 3]]);
+    Some(format!("{:0width$}", binary % 10u32.pow(TOTP_DIGITS), width = TOTP_DIGITS as usize))
+}
+
+// The step the code belongs to. One step of clock drift is accepted either way; whether the step
+// was used already is for the caller to check.
+pub fn check_totp(secret: &str, code: &str, unix_secs: i64) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != TOTP_DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let now = totp_step(unix_secs);
+    (now - 1..=now + 1).find(|&step| totp_code(secret, step).is_some_and(|expected| bool::from(expected.as_bytes().ct_eq(code.as_bytes()))))
+}
+
+// codes to show the user once and the hashes to store
+pub fn new_recovery_codes() -> (Vec<String>, Vec<String>) {
+    (0..RECOVERY_CODES)
+        .map(|_| {
+            let mut bytes = [0u8; 7];
+            OsRng.fill_bytes(&mut bytes);
+            let raw = base32(&bytes).to_lowercase();
+            let code = format!("{}-{}", &raw[..5], &raw[5..10]);
+            let hash = hash_recovery_code(&code);
+            (code, hash)
+        })
+        .unzip()
+}
+
+// dashes, spaces and case do not matter when the code is typed back
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code.chars().filter(|c| c.is_ascii_alphanumeric()).map(|c| c.to_ascii_lowercase()).collect();
+    Sha256::digest(normalized.as_bytes()).iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub async fn open_mfa_challenge(db: &Db, user_id: UserId) -> Result<MfaChallengeResponse, AuthError> {
+    let (mfa_token, token_hash) = new_opaque_token();
+    db.create_mfa_challenge(&token_hash, user_id, Utc::now() + MFA_CHALLENGE_TTL)
+        .await
+        .map_err(|e| AuthError::Internal(e.to_string()))?;
+    Ok(MfaChallengeResponse { mfa_token, expires_in: MFA_CHALLENGE_TTL.num_seconds() })
+}
+
+// whose challenge this is, so the sign-in limiter can hold the account before a code is checked
+pub async fn mfa_challenge_email(db: &Db, mfa_token: &str) -> Result<String, AuthError> {
+    db.mfa_challenge_email(&hash_opaque_token(mfa_token))
+        .await
+        .map_err(|e| AuthError::Internal(e.to_string()))?
+        .ok_or(AuthError::InvalidMfaChallenge)
+}
+
+// the user of a challenge once a code or a recovery code checked out, the challenge is spent then
+pub async fn complete_mfa_challenge(db: &Db, mfa_token: &str, code: Option<&str>, recovery_code: Option<&str>) -> Result<UserId, AuthError> {
+    let token_hash = hash_opaque_token(mfa_token);
+    let user_id = db
+        .attempt_mfa_challenge(&token_hash, MAX_MFA_ATTEMPTS)
+        .await
+        .map_err(|e| AuthError::Internal(e.to_string()))?
+        .ok_or(AuthError::InvalidMfaChallenge)?;
+    verify_second_factor(db, user_id, code, recovery_code).await?;
+    db.delete_mfa_challenge(&token_hash).await.map_err(|e| AuthError::Internal(e.to_string()))?;
+    Ok(user_id)
+}
+
+// exactly one of a current code or an unused recovery code
+pub async fn verify_second_factor(db: &Db, user_id: UserId, code: Option<&str>, recovery_code: Option<&str>) -> Result<(), AuthError> {
+    let accepted = match (code, recovery_code) {
+        (Some(code), None) => {
+            let credential = db
+                .get_totp(user_id)
+                .await
+                .map_err(|e| AuthError::Internal(e.to_string()))?
+                .filter(|c| c.confirmed_at.is_some())
+                .ok_or(AuthError::TotpNotEnabled)?;
+            match check_totp(&credential.secret, code, Utc::now().timestamp()) {
+                Some(step) => db.use_totp_step(user_id, step).await.map_err(|e| AuthError::Internal(e.to_string()))?,
+                None => false,
+            }
+        }
+        (None, Some(recovery_code)) => db
+            .use_recovery_code(user_id, &hash_recovery_code(recovery_code))
+            .await
+            .map_err(|e| AuthError::Internal(e.to_string()))?,
+        _ => return Err(AuthError::InvalidInput("Send either a code or a recovery code")),
+    };
+    if !accepted {
+        return Err(AuthError::InvalidSecondFactor);
+    }
+    Ok(())
+}
+
+fn base32(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(5) * 8);
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32[((buffer >> bits) & 31) as usize] as char);
+        }
+        buffer &= (1 << bits) - 1;
+    }
+    if bits > 0 {
+        out.push(BASE32[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    out
+}
+
+// case, spaces and padding are ignored, as apps and users write secrets in all of these ways
+fn unbase32(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for c in s.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+        let value = BASE32.iter().position(|&b| b as char == c.to_ascii_uppercase())? as u32;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(out)
+}
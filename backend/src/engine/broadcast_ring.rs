@@ -0,0 +1,232 @@
+// Single producer, many consumer ring (disruptor style).
+//
+// Every consumer sees every item. Each one has its own cursor, the producer never overwrites a
+// slot before the slowest consumer has moved past it, and a consumer registered after others can
+// be made to trail them (e.g. market data only sees what persistence already handled).
+//
+// Each consumer waits with its own WaitStrategy; publishing and committing notify all of them,
+// since a commit can be what a dependent consumer is waiting for.
+//
+// Cursors count items: `published` is how many the producer has written, a consumer cursor how
+// many it has finished with. Item n lives in slot n & mask.
+
+use crate::{SpinThenYield, WaitStrategy};
+
+use std::{
+    cell::UnsafeCell,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+#[repr(align(64))]
+struct Cursor(AtomicU64);
+
+struct Shared<T> {
+    slots: Box<[UnsafeCell<Option<T>>]>,
+    mask: u64,
+    published: Cursor,
+    cursors: Box<[Cursor]>,
+    waits: Box<[Box<dyn WaitStrategy>]>,
+    names: Vec<String>,
+}
+
+impl<T> Shared<T> {
+    fn notify(&self) {
+        for wait in self.waits.iter() {
+            wait.notify();
+        }
+    }
+}
+
+// consumers read slots through shared references while the producer writes other slots
+unsafe impl<T: Send + Sync> Sync for Shared<T> {}
+unsafe impl<T: Send> Send for Shared<T> {}
+
+pub struct BroadcastRingBuilder {
+    capacity: usize,
+    consumers: Vec<(String, Vec<usize>, Box<dyn WaitStrategy>)>,
+}
+
+impl BroadcastRingBuilder {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity.is_power_of_two(), "Capacity must be power of 2");
+        Self { capacity, consumers: Vec::new() }
+    }
+
+    // `after` names consumers registered earlier, this one never gets ahead of any of them
+    pub fn consumer(self, name: &str, after: &[&str]) -> Self {
+        self.consumer_with_wait(name, after, SpinThenYield::default())
+    }
+
+    pub fn consumer_with_wait(mut self, name: &str, after: &[&str], wait: impl WaitStrategy + 'static) -> Self {
+        assert!(self.position(name).is_none(), "consumer {name} registered twice");
+        let deps = after
+            .iter()
+            .map(|dep| self.position(dep).unwrap_or_else(|| panic!("consumer {name} depends on unknown consumer {dep}")))
+            .collect();
+        self.consumers.push((name.to_string(), deps, Box::new(wait)));
+        self
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.consumers.iter().position(|(n, _, _)| n == name)
+    }
+
+    // consumers come back in registration order
+    pub fn build<T>(self) -> (BroadcastProducer<T>, Vec<BroadcastConsumer<T>>) {
+        assert!(!self.consumers.is_empty(), "a broadcast ring needs at least one consumer");
+        let mut names = Vec::new();
+        let mut deps = Vec::new();
+        let mut waits = Vec::new();
+        for (name, after, wait) in self.consumers {
+            names.push(name);
+            deps.push(after);
+            waits.push(wait);
+        }
+        let shared = Arc::new(Shared {
+            slots: (0..self.capacity).map(|_| UnsafeCell::new(None)).collect(),
+            mask: self.capacity as u64 - 1,
+            published: Cursor(AtomicU64::new(0)),
+            cursors: (0..names.len()).map(|_| Cursor(AtomicU64::new(0))).collect(),
+            waits: waits.into_boxed_slice(),
+            names,
+        });
+        let consumers = deps
+            .into_iter()
+            .enumerate()
+            .map(|(id, deps)| BroadcastConsumer {
+                shared: Arc::clone(&shared),
+                id,
+                deps,
+                next: 0,
+            })
+            .collect();
+        let producer = BroadcastProducer {
+            shared,
+            next: 0,
+            gate: 0,
+        };
+        (producer, consumers)
+    }
+}
+
+pub struct BroadcastProducer<T> {
+    shared: Arc<Shared<T>>,
+    next: u64,
+    gate: u64, //cached cursor of the slowest consumer
+}
+
+impl<T> BroadcastProducer<T> {
+    pub fn try_publish(&mut self, item: T) -> Result<(), T> {
+        let capacity = self.shared.slots.len() as u64;
+        if self.next - self.gate >= capacity {
+            //only reload the consumer cursors when the cached one says we are full
+            self.gate = self.slowest();
+            if self.next - self.gate >= capacity {
+                return Err(item);
+            }
+        }
+        unsafe {
+            //every consumer is past this slot, nobody reads it until `published` moves
+            *self.shared.slots[(self.next & self.shared.mask) as usize].get() = Some(item);
+        }
+        self.next += 1;
+        self.shared.published.0.store(self.next, Ordering::Release);
+        self.shared.notify();
+        Ok(())
+    }
+
+    // waits for the slowest consumer when the ring is full
+    pub fn publish(&mut self, mut item: T) {
+        let mut spins = 0u32;
+        while let Err(back) = self.try_publish(item) {
+            item = back;
+            spins = spins.saturating_add(1);
+            if spins < 100 {
+                std::hint::spin_loop();
+            } else {
+                std::thread::yield_now();
+            }
+        }
+    }
+
+    fn slowest(&self) -> u64 {
+        self.shared
+            .cursors
+            .iter()
+            .map(|cursor| cursor.0.load(Ordering::Acquire))
+            .min()
+            .unwrap_or(self.next)
+    }
+
+    pub fn published(&self) -> u64 {
+        self.next
+    }
+}
+
+pub struct BroadcastConsumer<T> {
+    shared: Arc<Shared<T>>,
+    id: usize,
+    deps: Vec<usize>,
+    next: u64,
+}
+
+impl<T: Clone> BroadcastConsumer<T> {
+    pub fn name(&self) -> &str {
+        &self.shared.names[self.id]
+    }
+
+    // how far this consumer may read: what is published and already done by its dependencies
+    fn available(&self) -> u64 {
+        self.deps
+            .iter()
+            .map(|dep| self.shared.cursors[*dep].0.load(Ordering::Acquire))
+            .fold(self.shared.published.0.load(Ordering::Acquire), u64::min)
+    }
+
+    pub fn try_next(&mut self) -> Option<T> {
+        self.drain_batch(1).pop()
+    }
+
+    pub fn drain_batch(&mut self, max_items: usize) -> Vec<T> {
+        let batch = self.peek_batch(max_items);
+        self.commit(batch.len());
+        batch
+    }
+
+    // copies of the next items without moving the cursor, so neither the producer nor dependent
+    // consumers get past them until commit is called
+    pub fn peek_batch(&self, max_items: usize) -> Vec<T> {
+        let end = self.available().min(self.next + max_items as u64);
+        (self.next..end)
+            .map(|seq| {
+                let item = unsafe { (*self.shared.slots[(seq & self.shared.mask) as usize].get()).as_ref() };
+                item.expect("published slot is empty").clone()
+            })
+            .collect()
+    }
+
+    pub fn commit(&mut self, count: usize) {
+        if count == 0 {
+            return;
+        }
+        let end = self.next + count as u64;
+        assert!(end <= self.available(), "commit past the available items");
+        self.next = end;
+        //hands the slots back to the producer and releases dependents
+        self.shared.cursors[self.id].0.store(end, Ordering::Release);
+        self.shared.notify();
+    }
+
+    // waits with this consumer's strategy, false if it gave up with nothing available
+    pub fn wait_for_items(&self) -> bool {
+        self.shared.waits[self.id].wait_until(&mut || self.available() > self.next)
+    }
+
+    // nothing published is left for this consumer
+    pub fn is_caught_up(&self) -> bool {
+        self.next == self.shared.published.0.load(Ordering::Acquire)
+    }
+}
@@ -0,0 +1,51 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// Source of the timestamps the engine puts on events and fills. The engine reads it once per
+// command before journaling, so replay reuses the recorded time instead of asking again.
+pub trait Clock: Send {
+    fn now_nanos(&self) -> u128;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_nanos(&self) -> u128 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos()
+    }
+}
+
+// Only moves when told to. Clones share the same time, so a test can keep one and hand the other
+// to the engine.
+#[derive(Clone, Default)]
+pub struct ManualClock {
+    nanos: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    pub fn new(nanos: u64) -> Self {
+        Self { nanos: Arc::new(AtomicU64::new(nanos)) }
+    }
+
+    pub fn set(&self, nanos: u64) {
+        self.nanos.store(nanos, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self, nanos: u64) {
+        self.nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_nanos(&self) -> u128 {
+        self.nanos.load(Ordering::Relaxed) as u128
+    }
+}
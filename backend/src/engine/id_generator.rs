@@ -0,0 +1,37 @@
+use uuid::Uuid;
+
+use crate::OrderId;
+
+// Hands out ids for orders that arrive without one. Like the clock it is only consulted for live
+// commands, the ids end up in the journal and replay takes them from there.
+pub trait IdGenerator: Send {
+    fn next_id(&mut self) -> OrderId;
+}
+
+pub struct RandomIdGenerator;
+
+impl IdGenerator for RandomIdGenerator {
+    fn next_id(&mut self) -> OrderId {
+        Uuid::new_v4()
+    }
+}
+
+// seed in the high half, counter in the low half, so two generators with the same seed agree
+pub struct SequentialIdGenerator {
+    seed: u64,
+    next: u64,
+}
+
+impl SequentialIdGenerator {
+    pub fn new(seed: u64) -> Self {
+        Self { seed, next: 1 }
+    }
+}
+
+impl IdGenerator for SequentialIdGenerator {
+    fn next_id(&mut self) -> OrderId {
+        let id = Uuid::from_u64_pair(self.seed, self.next);
+        self.next += 1;
+        id
+    }
+}
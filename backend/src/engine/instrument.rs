@@ -0,0 +1,46 @@
+use rust_decimal::{Decimal, prelude::ToPrimitive};
+use serde::{Deserialize, Serialize};
+
+use crate::{Lots, Price, Quantity, Ticks};
+
+// Per instrument scaling between the decimal prices/quantities used by the api and events
+// and the integer ticks/lots the order book works on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Instrument {
+    pub tick_size: Decimal,
+    pub lot_size: Decimal,
+}
+
+impl Instrument {
+    pub fn new(tick_size: Decimal, lot_size: Decimal) -> Self {
+        assert!(tick_size > Decimal::ZERO, "tick size must be positive");
+        assert!(lot_size > Decimal::ZERO, "lot size must be positive");
+        Self { tick_size, lot_size }
+    }
+
+    // None if the price is not a whole number of ticks or does not fit in i64
+    pub fn to_ticks(&self, price: Price) -> Option<Ticks> {
+        let ticks = price.checked_div(self.tick_size)?;
+        if !ticks.fract().is_zero() {
+            return None;
+        }
+        ticks.to_i64()
+    }
+
+    // None if the quantity is negative, not a whole number of lots or does not fit in u64
+    pub fn to_lots(&self, quantity: Quantity) -> Option<Lots> {
+        let lots = quantity.checked_div(self.lot_size)?;
+        if !lots.fract().is_zero() {
+            return None;
+        }
+        lots.to_u64()
+    }
+
+    pub fn price(&self, ticks: Ticks) -> Price {
+        Decimal::from(ticks) * self.tick_size
+    }
+
+    pub fn quantity(&self, lots: Lots) -> Quantity {
+        Decimal::from(lots) * self.lot_size
+    }
+}
@@ -0,0 +1,196 @@
+// Write-ahead journal of the commands fed to the matching engine.
+//
+// Every frame is [len u32][crc32 u32][seq u64][payload], little endian. The crc covers seq and
+// payload, the payload is the json encoded OrderBookMessage (responders are not journaled) together
+// with the time the engine stamped on it. Sequence numbers start at 1 and have no gaps.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::OrderBookMessage;
+
+const HEADER_LEN: u64 = 16;
+const MAX_PAYLOAD: u32 = 1 << 20;
+
+pub struct JournalEntry {
+    pub seq: u64,
+    pub timestamp: u128,
+    pub message: OrderBookMessage,
+}
+
+#[derive(Serialize)]
+struct RecordRef<'a> {
+    timestamp: u128,
+    message: &'a OrderBookMessage,
+}
+
+#[derive(Deserialize)]
+struct Record {
+    timestamp: u128,
+    message: OrderBookMessage,
+}
+
+pub struct Journal {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    last_seq: u64,
+}
+
+impl Journal {
+    // Opens or creates the journal. A frame torn by a crash in the middle of a write is cut off,
+    // anything invalid before the last frame is reported as corruption instead of being dropped.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+
+        let mut reader = FrameReader::new(BufReader::new(&mut file))?;
+        let mut last_seq = 0;
+        while let Some((seq, _)) = reader.next_frame()? {
+            last_seq = seq;
+        }
+        let (valid_len, file_len) = (reader.offset, reader.file_len);
+        if valid_len < file_len {
+            eprintln!(
+                "[JOURNAL] dropping {} bytes of torn frame at the end of {}",
+                file_len - valid_len,
+                path.display()
+            );
+            file.set_len(valid_len)?;
+            file.sync_data()?;
+        }
+        file.seek(SeekFrom::Start(valid_len))?;
+
+        Ok(Self {
+            path,
+            writer: BufWriter::new(file),
+            last_seq,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn last_seq(&self) -> u64 {
+        self.last_seq
+    }
+
+    // buffered, call sync before acting on the appended messages
+    pub fn append(&mut self, timestamp: u128, message: &OrderBookMessage) -> io::Result<u64> {
+        let payload = serde_json::to_vec(&RecordRef { timestamp, message })?;
+        let len = u32::try_from(payload.len())
+            .ok()
+            .filter(|len| *len <= MAX_PAYLOAD)
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "journal entry too large"))?;
+        let seq = self.last_seq + 1;
+
+        self.writer.write_all(&len.to_le_bytes())?;
+        self.writer.write_all(&checksum(seq, &payload).to_le_bytes())?;
+        self.writer.write_all(&seq.to_le_bytes())?;
+        self.writer.write_all(&payload)?;
+        self.last_seq = seq;
+        Ok(seq)
+    }
+
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()
+    }
+
+    // entries with seq > after_seq, in order
+    pub fn read_from(path: impl AsRef<Path>, after_seq: u64) -> io::Result<Vec<JournalEntry>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut reader = FrameReader::new(BufReader::new(file))?;
+        let mut entries = Vec::new();
+        while let Some((seq, payload)) = reader.next_frame()? {
+            if seq <= after_seq {
+                continue;
+            }
+            let Record { timestamp, message } = serde_json::from_slice(&payload)?;
+            entries.push(JournalEntry { seq, timestamp, message });
+        }
+        Ok(entries)
+    }
+}
+
+fn checksum(seq: u64, payload: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&seq.to_le_bytes());
+    hasher.update(payload);
+    hasher.finalize()
+}
+
+struct FrameReader<R> {
+    inner: R,
+    offset: u64,
+    file_len: u64,
+    last_seq: u64,
+}
+
+impl<R: Read + Seek> FrameReader<R> {
+    fn new(mut inner: R) -> io::Result<Self> {
+        let file_len = inner.seek(SeekFrom::End(0))?;
+        inner.seek(SeekFrom::Start(0))?;
+        Ok(Self {
+            inner,
+            offset: 0,
+            file_len,
+            last_seq: 0,
+        })
+    }
+
+    // None at the end of the valid frames, offset then points just past the last good one
+    fn next_frame(&mut self) -> io::Result<Option<(u64, Vec<u8>)>> {
+        if self.file_len - self.offset < HEADER_LEN {
+            return Ok(None);
+        }
+        let mut header = [0u8; HEADER_LEN as usize];
+        self.inner.read_exact(&mut header)?;
+        let len = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let crc = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        let seq = u64::from_le_bytes(header[8..16].try_into().unwrap());
+
+        let end = self.offset + HEADER_LEN + len as u64;
+        if end > self.file_len {
+            // the write of the last frame never completed
+            return Ok(None);
+        }
+        if len > MAX_PAYLOAD {
+            return Err(self.corrupt(format!("frame length {len} over the limit")));
+        }
+        let mut payload = vec![0u8; len as usize];
+        self.inner.read_exact(&mut payload)?;
+
+        if checksum(seq, &payload) != crc {
+            if end == self.file_len {
+                return Ok(None);
+            }
+            return Err(self.corrupt(format!("checksum mismatch for seq {seq}")));
+        }
+        if seq != self.last_seq + 1 {
+            return Err(self.corrupt(format!("expected seq {} found {seq}", self.last_seq + 1)));
+        }
+        self.offset = end;
+        self.last_seq = seq;
+        Ok(Some((seq, payload)))
+    }
+
+    fn corrupt(&self, reason: String) -> io::Error {
+        io::Error::new(
+            ErrorKind::InvalidData,
+            format!("journal corrupted at offset {}: {reason}", self.offset),
+        )
+    }
+}
@@ -1,22 +1,207 @@
-use std::sync::{Arc, mpsc}; 
-use rust_decimal::Decimal;
+use std::{io, sync::{Arc, mpsc}}; 
 use rust_decimal_macros::dec;
 use tokio::sync::{oneshot};
 
-use crate::{Order, OrderBook, OrderId, Price, Quantity, RingBuffer, UserId, now_nanos, types::{Event, OrderBookMessage, OrderResponse, OrderStatus, OrderType}};
+use crate::{Clock, EngineSnapshot, IdGenerator, Instrument, Journal, Lots, Order, OrderBook, OrderId, OverflowPolicy, Price, RandomIdGenerator, RingBuffer, SnapshotStore, SystemClock, UserId, types::{Event, EventEnvelope, OrderBookMessage, OrderResponse, OrderStatus, OrderType, RiskLimits, Trade}};
 
 pub struct MatchingEngine{
-   event_buffer : Arc<RingBuffer<Event>>,
-   order_book :OrderBook
+   event_buffer : Arc<RingBuffer<EventEnvelope>>,
+   order_book :OrderBook,
+   instrument : Instrument,
+   clock : Box<dyn Clock>,
+   ids : Box<dyn IdGenerator>,
+   now : u128, //time stamped on the command being processed
+   journal : Option<Journal>,
+   snapshots : Option<(SnapshotStore, u64)>, //store and interval in commands
+   last_snapshot_seq : u64,
+   command_seq : u64, //seq of the last applied command, matches the journal seq
+   event_seq : u64, //seq of the last event, also counted during replay so it carries on after a restart
+   mark_price : Option<Price>,
+   replaying : bool,
+   overflow : OverflowPolicy,
+   overflowed : bool, //the ring was full during this batch, only acted on with OverflowPolicy::Halt
+   halted : Option<&'static str>, //why the engine stopped taking commands
+   market_halt : Option<String>, //why an admin halted trading, cancels still go through
+   limits : RiskLimits
 }
 
 impl MatchingEngine{
    pub fn new(
-      event: Arc<RingBuffer<Event>>
+      event: Arc<RingBuffer<EventEnvelope>>,
+      instrument: Instrument
    )->Self{
       Self {
          event_buffer: event ,
-         order_book:OrderBook::new()
+         order_book:OrderBook::new(),
+         instrument,
+         clock: Box::new(SystemClock),
+         ids: Box::new(RandomIdGenerator),
+         now: 0,
+         journal: None,
+         snapshots: None,
+         last_snapshot_seq: 0,
+         command_seq: 0,
+         event_seq: 0,
+         mark_price: None,
+         replaying: false,
+         overflow: OverflowPolicy::Block,
+         overflowed: false,
+         halted: None,
+         market_halt: None,
+         limits: RiskLimits::default()
+      }
+   }
+
+   //with a fixed clock and id generator the same commands give the same fills and events
+   pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+      self.clock = Box::new(clock);
+      self
+   }
+
+   pub fn with_id_generator(mut self, ids: impl IdGenerator + 'static) -> Self {
+      self.ids = Box::new(ids);
+      self
+   }
+
+   //what happens to events when the ring is full, Block unless set
+   pub fn with_overflow_policy(mut self, overflow: OverflowPolicy) -> Self {
+      self.overflow = overflow;
+      self
+   }
+
+   //every command is appended (and synced) to the journal before it touches the book
+   pub fn with_journal(mut self, journal: Journal) -> Self {
+      self.journal = Some(journal);
+      self
+   }
+
+   //writes a snapshot every `every` commands, recover starts from the newest one that verifies
+   pub fn with_snapshots(mut self, store: SnapshotStore, every: u64) -> Self {
+      self.snapshots = Some((store, every.max(1)));
+      self
+   }
+
+   pub fn order_book(&self) -> &OrderBook {
+      &self.order_book
+   }
+
+   pub fn command_seq(&self) -> u64 {
+      self.command_seq
+   }
+
+   pub fn halted(&self) -> Option<&'static str> {
+      self.halted
+   }
+
+   pub fn market_halt(&self) -> Option<&str> {
+      self.market_halt.as_deref()
+   }
+
+   pub fn risk_limits(&self) -> RiskLimits {
+      self.limits
+   }
+
+   pub fn event_seq(&self) -> u64 {
+      self.event_seq
+   }
+
+   pub fn mark_price(&self) -> Option<Price> {
+      self.mark_price
+   }
+
+   //rebuilds state from the latest good snapshot plus the journal after it,
+   //events were already published the first time so none are emitted
+   pub fn recover(&mut self) -> io::Result<usize> {
+      self.restore_latest_snapshot()?;
+
+      let Some(journal) = self.journal.as_ref() else {
+         return Ok(0);
+      };
+      if journal.last_seq() < self.command_seq {
+         return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("snapshot at seq {} is ahead of the journal at seq {}", self.command_seq, journal.last_seq()),
+         ));
+      }
+      let entries = Journal::read_from(journal.path(), self.command_seq)?;
+      let replayed = entries.len();
+
+      self.replaying = true;
+      for entry in entries {
+         self.command_seq = entry.seq;
+         self.process(entry.timestamp, entry.message);
+      }
+      self.replaying = false;
+      Ok(replayed)
+   }
+
+   pub fn snapshot(&self) -> EngineSnapshot {
+      EngineSnapshot {
+         journal_seq: self.command_seq,
+         event_seq: self.event_seq,
+         instrument: self.instrument,
+         mark_price: self.mark_price,
+         market_halt: self.market_halt.clone(),
+         limits: self.limits,
+         book: self.order_book.snapshot(),
+         state_hash: self.order_book.state_hash(),
+      }
+   }
+
+   fn restore_latest_snapshot(&mut self) -> io::Result<()> {
+      let Some((store, _)) = self.snapshots.as_ref() else {
+         return Ok(());
+      };
+      for (seq, path) in store.list()? {
+         let restored = SnapshotStore::read(&path).and_then(|snapshot| {
+            let admin = (snapshot.market_halt.clone(), snapshot.limits);
+            self.verify_snapshot(snapshot).map(|restored| (restored, admin))
+         });
+         match restored {
+            Ok(((book, mark_price, event_seq), (market_halt, limits))) => {
+               println!("[ENGINE] Restored snapshot at seq {seq}");
+               self.order_book = book;
+               self.mark_price = mark_price;
+               self.event_seq = event_seq;
+               self.market_halt = market_halt;
+               self.limits = limits;
+               self.command_seq = seq;
+               self.last_snapshot_seq = seq;
+               return Ok(());
+            }
+            //an older snapshot plus a longer journal tail gets us to the same state
+            Err(e) => eprintln!("[ENGINE] skipping snapshot {}: {e}", path.display()),
+         }
+      }
+      Ok(())
+   }
+
+   fn verify_snapshot(&self, snapshot: EngineSnapshot) -> io::Result<(OrderBook, Option<Price>, u64)> {
+      if snapshot.instrument != self.instrument {
+         return Err(io::Error::new(io::ErrorKind::InvalidData, "snapshot was taken with a different instrument"));
+      }
+      let book = OrderBook::restore(snapshot.book);
+      let hash = book.state_hash();
+      if hash != snapshot.state_hash {
+         return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("restored state hash {hash} does not match {}", snapshot.state_hash),
+         ));
+      }
+      Ok((book, snapshot.mark_price, snapshot.event_seq))
+   }
+
+   fn maybe_snapshot(&mut self) {
+      let Some((store, every)) = self.snapshots.as_ref() else {
+         return;
+      };
+      if self.command_seq < self.last_snapshot_seq + every {
+         return;
+      }
+      match store.write(&self.snapshot()) {
+         Ok(_) => self.last_snapshot_seq = self.command_seq,
+         //the journal still has everything, so this only makes the next recovery slower
+         Err(e) => eprintln!("[ENGINE] snapshot at seq {} failed: {e}", self.command_seq),
       }
    }
 
@@ -46,6 +231,7 @@ impl MatchingEngine{
          batch.sort_by_key(|cmd| cmd.priority());
 
          self.process_batch(&mut batch);
+         self.maybe_snapshot();
          
          //clear batch
          batch.clear();
@@ -54,11 +240,55 @@ impl MatchingEngine{
    }
 
    fn process_batch(&mut self, batch: &mut Vec<OrderBookMessage>) {
-      for cmd in batch.drain(..) {
+      //ids and time are fixed before journaling so replay sees exactly what was processed
+      let stamped: Vec<(u128, OrderBookMessage)> = batch.drain(..).flat_map(OrderBookMessage::flatten).map(|cmd| self.admit(cmd)).collect();
+      if self.halted.is_none() && let Err(e) = self.journal_batch(&stamped) {
+         //we cannot tell which of these made it to disk, so stop trading rather than diverge from the journal
+         eprintln!("[ENGINE] journal write failed, halting: {e}");
+         self.halted = Some("engine halted: journal unavailable");
+      }
+      if let Some(reason) = self.halted {
+         for (_, cmd) in stamped {
+            reject(cmd, reason);
+         }
+         return;
+      }
+      for (timestamp, cmd) in stamped {
+         self.command_seq += 1;
+         self.process(timestamp, cmd);
+      }
+      //the whole batch is journaled, so it is finished before stopping to keep replay in step
+      if self.overflowed && self.overflow == OverflowPolicy::Halt {
+         eprintln!("[ENGINE] event ring overflowed, halting: {:?}", self.event_buffer.stats());
+         self.halted = Some("engine halted: event ring overflow");
+      }
+      self.overflowed = false;
+   }
+
+   fn admit(&mut self, mut cmd: OrderBookMessage) -> (u128, OrderBookMessage) {
+      if let OrderBookMessage::PlaceOrder { order, .. } = &mut cmd && order.order_id.is_nil() {
+         order.order_id = self.ids.next_id();
+      }
+      (self.clock.now_nanos(), cmd)
+   }
+
+   fn journal_batch(&mut self, batch: &[(u128, OrderBookMessage)]) -> io::Result<()> {
+      let Some(journal) = self.journal.as_mut() else {
+         return Ok(());
+      };
+      for (i, (timestamp, cmd)) in batch.iter().enumerate() {
+         let seq = journal.append(*timestamp, cmd)?;
+         debug_assert_eq!(seq, self.command_seq + 1 + i as u64, "journal and engine sequence diverged");
+      }
+      journal.sync()
+   }
+
+   fn process(&mut self, timestamp: u128, cmd: OrderBookMessage) {
+         self.now = timestamp;
          match cmd {
                OrderBookMessage::PlaceOrder {
                   order,
-                  mut priority,
+                  priority: _,
                   mut responder,
                } => {
                   self.handle_place_order(order, &mut responder);
@@ -75,8 +305,36 @@ impl MatchingEngine{
                OrderBookMessage::UpdateMarkPrice { price } => {
                   self.handle_update_mark_price(price);
                }
+
+               OrderBookMessage::HaltMarket { reason, responder } => {
+                  println!("[ENGINE] market halted: {reason}");
+                  self.market_halt = Some(reason);
+                  self.reply_market_status(responder);
+               }
+
+               OrderBookMessage::ResumeMarket { responder } => {
+                  if self.market_halt.take().is_some() {
+                     println!("[ENGINE] market resumed");
+                  }
+                  self.reply_market_status(responder);
+               }
+
+               OrderBookMessage::ForceCancel { user_id, responder } => {
+                  self.handle_force_cancel(user_id, responder);
+               }
+
+               OrderBookMessage::SetRiskLimits { limits, responder } => {
+                  self.limits = limits;
+                  self.reply_market_status(responder);
+               }
+
+               //process_batch flattens these before journaling, so this only runs if one is nested oddly
+               OrderBookMessage::Batch { commands } => {
+                  for cmd in commands {
+                     self.process(timestamp, cmd);
+                  }
+               }
          }
-      }
    }
 
    fn handle_place_order(
@@ -92,20 +350,47 @@ impl MatchingEngine{
             order_id:order.order_id,
             user_id :order.user_id,
             reason : ("problem while validatin".to_string()),
-            timestamp : now_nanos()
+            timestamp : self.now
         });
         return;
       }
       let order_quantity = order.quantity;
       let order_id = order.order_id;
       let order_type = order.order_type;
-      let (fills,remaining_order) = self.order_book.match_order(order);
+      let user_id = order.user_id;
+      let (fills,remaining_order) = self.order_book.match_order(order, self.now);
 
       for fill in fills.iter() {
-         self.emit_event(Event::Fill(fill.clone()));
+         self.emit_event(Event::Fill(Trade::new(fill, &self.instrument)));
       }
+      let total_filled:Lots = fills.iter().map(|f|f.quantity).sum();
 
-      if let Some(rem_order) = remaining_order {
+      //the open order cap only holds back what would rest, fills go through at the cap, so a user
+      //there can still close positions
+      let capped = remaining_order.as_ref().and_then(|_| self.open_orders_at_cap(user_id));
+      if let Some(max) = capped && total_filled == 0 {
+         let reason = format!("too many open orders (max {max})");
+         self.emit_event(Event::OrderRejected {
+            order_id,
+            user_id,
+            reason: reason.clone(),
+            timestamp: self.now
+         });
+         if let Some(tx) = responder.take() {
+            let _ = tx.send(Err(reason));
+         }
+         return;
+      }
+      //the dropped rest ends the order, consumers would otherwise see its fills and never a final state
+      if capped.is_some() {
+         self.emit_event(Event::OrderCancelled {
+            order_id,
+            user_id,
+            timestamp: self.now
+         });
+      }
+
+      if let Some(rem_order) = remaining_order.filter(|_| capped.is_none()) {
          let order_id = rem_order.order_id;
          let user_id  = rem_order.user_id;
          let side     = rem_order.side;
@@ -119,30 +404,31 @@ impl MatchingEngine{
             order_id,
             user_id,
             side,
-            price,
-            quantity:quantity.checked_sub(filled).unwrap(),
-            timestamp: now_nanos(),
+            price: self.instrument.price(price),
+            quantity: self.instrument.quantity(quantity.checked_sub(filled).unwrap()),
+            timestamp: self.now,
          });
       }
       //Prepare the send resposne for api layer
       let original_qty = order_quantity;
-      let total_filled:Quantity = fills.iter().map(|f|f.quantity).sum();
       let remaining = original_qty.checked_sub(total_filled).ok_or("err").unwrap();
 
       let status = match order_type {
          OrderType::Market => {
-            if total_filled == dec!(0) {
+            if total_filled == 0 {
                   OrderStatus::Rejected   
-            } else if remaining == dec!(0) {
+            } else if remaining == 0 {
                   OrderStatus::FullyFilled
             } else {
                   OrderStatus::PartiallyFilled
             }
          }
+         //partly filled at the cap, the rest was dropped instead of resting
+         OrderType::Limit if capped.is_some() => OrderStatus::Cancelled,
          OrderType::Limit => {
-            if total_filled == dec!(0) {
+            if total_filled == 0 {
                   OrderStatus::New
-            } else if remaining == dec!(0) {
+            } else if remaining == 0 {
                   OrderStatus::FullyFilled
             } else {
                   OrderStatus::PartiallyFilled
@@ -154,8 +440,8 @@ impl MatchingEngine{
          let _ = tx.send(Ok(OrderResponse::PlacedOrder {
                order_id,
                status,
-               filled: total_filled, 
-               remaining 
+               filled: self.instrument.quantity(total_filled), 
+               remaining: self.instrument.quantity(remaining) 
          }));
       }
    }
@@ -168,11 +454,11 @@ impl MatchingEngine{
    ){
 
       match self.order_book.cancel_order(&order_id, &user_id){
-         Ok(order)=>{
+         Ok(_)=>{
             self.emit_event(Event::OrderCancelled { 
                order_id,
                user_id, 
-               timestamp: now_nanos() 
+               timestamp: self.now 
             });
             if let Some(tx) = responder.take(){
                 let _ = tx.send(Ok(OrderResponse::CanceledOrder { 
@@ -194,26 +480,146 @@ impl MatchingEngine{
    }
  
    fn handle_update_mark_price(&mut self , price: Price){
+      self.mark_price = Some(price);
+
+   }
+
+   //every resting order of the user, in id order since user_orders is a HashSet and the events
+   //have to come out the same on every run
+   fn handle_force_cancel(
+      &mut self,
+      user_id: UserId,
+      responder: Option<oneshot::Sender<Result<OrderResponse, String>>>
+   ){
+      let mut resting: Vec<OrderId> = self.order_book.user_orders
+         .get(&user_id)
+         .into_iter()
+         .flatten()
+         .filter_map(|handle| self.order_book.orders.get(*handle))
+         .map(|resting| resting.order.order_id)
+         .collect();
+      resting.sort_unstable();
 
+      let mut order_ids = Vec::with_capacity(resting.len());
+      for order_id in resting {
+         if self.order_book.cancel_order(&order_id, &user_id).is_ok() {
+            self.emit_event(Event::OrderCancelled { order_id, user_id, timestamp: self.now });
+            order_ids.push(order_id);
+         }
+      }
+      if let Some(tx) = responder {
+         let _ = tx.send(Ok(OrderResponse::CancelledAll { user_id, order_ids }));
+      }
+   }
 
+   fn reply_market_status(&self, responder: Option<oneshot::Sender<Result<OrderResponse, String>>>) {
+      if let Some(tx) = responder {
+         let _ = tx.send(Ok(OrderResponse::MarketStatus { halted: self.market_halt.clone(), limits: self.limits }));
+      }
+   }
+   fn emit_event(&mut self,event:Event){
+      self.event_seq += 1;
+      if self.replaying {
+         return;
+      }
+      let mut envelope = EventEnvelope {
+         seq: self.event_seq,
+         command_seq: self.command_seq,
+         event
+      };
+      match self.overflow {
+         OverflowPolicy::Block => self.push_blocking(envelope),
+         OverflowPolicy::Halt => {
+            if let Err(back) = self.event_buffer.try_push(envelope) {
+               self.overflowed = true;
+               self.push_blocking(back);
+            }
+         }
+         OverflowPolicy::SpinThenFail(spins) => {
+            for _ in 0..spins {
+               match self.event_buffer.try_push(envelope) {
+                  Ok(()) => return,
+                  Err(back) => envelope = back,
+               }
+               std::hint::spin_loop();
+            }
+            if let Err(lost) = self.event_buffer.try_push(envelope) {
+               self.event_buffer.record_drop();
+               eprintln!("[ENGINE] event ring full, dropped event #{}", lost.seq);
+            }
+         }
+         OverflowPolicy::DropOldest => {
+            if let Some(lost) = self.event_buffer.force_push(envelope) {
+               eprintln!("[ENGINE] event ring full, dropped oldest event #{}", lost.seq);
+            }
+         }
+      }
    }
-   fn emit_event(&self,event:Event){
-      self.event_buffer.push(event);
+
+   //back-pressure: a full ring stalls matching until the dispatcher catches up
+   fn push_blocking(&self, mut envelope: EventEnvelope) {
+      let mut spins = 0u32;
+      while let Err(back) = self.event_buffer.try_push(envelope) {
+         envelope = back;
+         spins += 1;
+         if spins == 1_000_000 {
+            eprintln!("[ENGINE] event ring full, waiting for the dispatcher");
+         }
+         if spins < 100 {
+            std::hint::spin_loop();
+         } else {
+            std::thread::yield_now();
+         }
+      }
    }
  
    
+   //the cap when the user already has that many resting orders
+   fn open_orders_at_cap(&self, user_id: UserId) -> Option<u32> {
+      let max = self.limits.max_open_orders?;
+      let open = self.order_book.user_orders.get(&user_id).map_or(0, |orders| orders.len());
+      (open >= max as usize).then_some(max)
+   }
+
    fn validate_order(&self,order:&Order)->Result<(),String>{
 
       if order.order_type != OrderType::Limit && order.order_type != OrderType::Market{
          return Err("invalid order_type".to_string());
       }
-      if order.quantity <= Decimal::ZERO {
+      if order.quantity == 0 {
          return Err("quantity should be greater then the zero".to_string());
       }
-      if order.leverage < dec!(1)|| order.leverage > dec!(125) {
-            return Err("Invalid leverage (1-125x)".to_string());
+      if order.leverage < dec!(1)|| order.leverage > self.limits.max_leverage {
+            return Err(format!("Invalid leverage (1-{}x)", self.limits.max_leverage));
+      }
+      if self.limits.max_order_lots.is_some_and(|max| order.quantity > max) {
+         return Err("quantity above the maximum order size".to_string());
+      }
+      if let Some(reason) = &self.market_halt {
+         return Err(format!("market halted: {reason}"));
       }
       Ok(())
    }
 
 }
+
+fn reject(cmd: OrderBookMessage, reason: &str) {
+   let responder = match cmd {
+      OrderBookMessage::PlaceOrder { responder, .. } => responder,
+      OrderBookMessage::CancelOrder { responder, .. } => responder,
+      OrderBookMessage::UpdateMarkPrice { .. } => None,
+      OrderBookMessage::HaltMarket { responder, .. } => responder,
+      OrderBookMessage::ResumeMarket { responder } => responder,
+      OrderBookMessage::ForceCancel { responder, .. } => responder,
+      OrderBookMessage::SetRiskLimits { responder, .. } => responder,
+      OrderBookMessage::Batch { commands } => {
+         for cmd in commands {
+            reject(cmd, reason);
+         }
+         None
+      }
+   };
+   if let Some(tx) = responder {
+      let _ = tx.send(Err(reason.to_string()));
+   }
+}
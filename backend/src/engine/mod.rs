@@ -4,3 +4,19 @@ pub mod matching_engine;
 pub use matching_engine::*;
 pub mod ring_buffer;
 pub use ring_buffer::*;
+pub mod instrument;
+pub use instrument::*;
+pub mod slab;
+pub use slab::*;
+pub mod journal;
+pub use journal::*;
+pub mod snapshot;
+pub use snapshot::*;
+pub mod clock;
+pub use clock::*;
+pub mod id_generator;
+pub use id_generator::*;
+pub mod broadcast_ring;
+pub use broadcast_ring::*;
+pub mod wait_strategy;
+pub use wait_strategy::*;
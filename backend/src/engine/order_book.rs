@@ -1,84 +1,171 @@
-use std::{collections::{BTreeMap, HashMap, VecDeque}, time::{SystemTime, UNIX_EPOCH}};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use rust_decimal::Decimal;
-use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::types::{ OrderType, Side};
+use crate::{Slab, SlabKey, types::{ OrderType, Side}};
 pub type Price = Decimal;
 pub type OrderId = Uuid;
 pub type UserId = Uuid;
 pub type Quantity = Decimal;
+//the book itself works on integer ticks and lots, see Instrument for the scaling
+pub type Ticks = i64;
+pub type Lots = u64;
+//compact internal reference to a resting order, uuids are only resolved at the edge
+pub type OrderHandle = SlabKey;
 
 
 pub struct LimitOrder{
     pub user_id :Uuid,
     pub side : Side,
-    pub price : Price,
-    pub quantity : Quantity,
+    pub price : Ticks,
+    pub quantity : Lots,
     pub leverage : Decimal,
 }
 pub struct MarketOrder{
     pub user_id : Uuid,
     pub side : Side,
-    pub quantity : Quantity,
+    pub quantity : Lots,
     pub leverage : Decimal,
 }
 
+//orders at a price are kept as a doubly linked list threaded through RestingOrder.prev/next,
+//so the level only stores the ends and any order can be unlinked in O(1) from its handle
 pub struct PriceLevel{
-    pub price : Price,
-    pub orders : VecDeque<OrderId>,
-    pub total_qty : Quantity
+    pub price : Ticks,
+    pub head : Option<OrderHandle>,
+    pub tail : Option<OrderHandle>,
+    pub len : usize,
+    pub total_qty : Lots
 }
 
+impl PriceLevel {
+    pub fn new(price: Ticks) -> Self {
+        Self {
+            price,
+            head: None,
+            tail: None,
+            len: 0,
+            total_qty: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.is_none()
+    }
+
+    //appends at the back of the queue (lowest time priority)
+    fn push_back(&mut self, order: Order, orders: &mut Slab<RestingOrder>) -> OrderHandle {
+        self.total_qty += order.remaining();
+        self.len += 1;
+
+        let handle = orders.insert(RestingOrder {
+            order,
+            prev: self.tail,
+            next: None,
+        });
+        match self.tail {
+            Some(tail) => orders.get_mut(tail).expect("broken level link").next = Some(handle),
+            None => self.head = Some(handle),
+        }
+        self.tail = Some(handle);
+        handle
+    }
+
+    //removes the order from the list and frees its slot, the caller keeps total_qty in sync for fills
+    fn unlink(&mut self, handle: OrderHandle, orders: &mut Slab<RestingOrder>) -> Option<RestingOrder> {
+        let resting = orders.remove(handle)?;
+        match resting.prev {
+            Some(prev) => orders.get_mut(prev).expect("broken level link").next = resting.next,
+            None => self.head = resting.next,
+        }
+        match resting.next {
+            Some(next) => orders.get_mut(next).expect("broken level link").prev = resting.prev,
+            None => self.tail = resting.prev,
+        }
+        self.len -= 1;
+        Some(resting)
+    }
+}
+
+pub struct RestingOrder {
+    pub order : Order,
+    pub prev : Option<OrderHandle>,
+    pub next : Option<OrderHandle>,
+}
+
+//walks a level from head to tail, i.e. in time priority
+pub struct LevelIter<'a> {
+    orders : &'a Slab<RestingOrder>,
+    next : Option<OrderHandle>,
+}
+
+impl<'a> Iterator for LevelIter<'a> {
+    type Item = &'a Order;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let resting = self.orders.get(self.next?)?;
+        self.next = resting.next;
+        Some(&resting.order)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Order {
     pub order_id : Uuid,
     pub user_id : Uuid,
-    pub price : Option<Price>,
+    pub price : Option<Ticks>,
     pub leverage : Decimal,
     pub side : Side,
     pub order_type : OrderType,
-    pub quantity : Quantity,
-    pub filled : Quantity,
+    pub quantity : Lots,
+    pub filled : Lots,
 }
 
+//orders are built without an id (nil), the engine assigns one from its IdGenerator on admission
 impl Order {
     pub fn limit_order(limit_order:LimitOrder)->Self{
         Self{
-            order_id : Uuid::new_v4(),
+            order_id : OrderId::nil(),
             user_id : limit_order.user_id,
             side : limit_order.side,
             price : Some(limit_order.price),
             quantity : limit_order.quantity,
             leverage : limit_order.leverage,
             order_type : OrderType::Limit,
-            filled : dec!(0),
+            filled : 0,
         }
     }
     pub fn market_order(market_order : MarketOrder)->Self{
         Self{
-            order_id : Uuid::new_v4(),
+            order_id : OrderId::nil(),
             user_id : market_order.user_id,
             price : None,
             leverage : market_order.leverage,
             side : market_order.side,
             order_type : OrderType::Market,
             quantity : market_order.quantity,
-            filled : dec!(0),
+            filled : 0,
         }
     } 
-    pub fn remaining(&self)->Quantity{
+    pub fn with_id(mut self, order_id: OrderId) -> Self {
+        self.order_id = order_id;
+        self
+    }
+    pub fn remaining(&self)->Lots{
         self.quantity-self.filled
     }
 }
 
 pub struct OrderBook {
-   pub bids : BTreeMap<Price,PriceLevel>,
-   pub asks : BTreeMap<Price,PriceLevel>,
-   pub orders : HashMap<OrderId,Order>,
-   pub user_orders : HashMap<UserId,Vec<OrderId>>,
-   pub best_bid : Option<Price>,
-   pub best_ask :Option<Price>,
+   pub bids : BTreeMap<Ticks,PriceLevel>,
+   pub asks : BTreeMap<Ticks,PriceLevel>,
+   pub orders : Slab<RestingOrder>,
+   pub order_index : HashMap<OrderId,OrderHandle>,
+   pub user_orders : HashMap<UserId,HashSet<OrderHandle>>,
+   pub best_bid : Option<Ticks>,
+   pub best_ask :Option<Ticks>,
    pub fill_seq:u64  //sequence numners for fills
 }
 #[derive(Clone,Copy)]
@@ -88,8 +175,8 @@ pub struct Fill{
     pub taker_order_id:OrderId,
     pub maker_user_id:OrderId,
     pub taker_user_id :OrderId,
-    pub price : Price,
-    pub quantity : Quantity,
+    pub price : Ticks,
+    pub quantity : Lots,
     pub taker_leverage : Decimal,
     pub maker_leverage : Decimal,
     pub maker_side : Side,
@@ -97,12 +184,19 @@ pub struct Fill{
     pub timestamp_: u128
 
 }
+impl Default for OrderBook {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl OrderBook{
     pub fn new()->Self{
         Self{
             bids : BTreeMap::new(),
             asks : BTreeMap::new(),
-            orders : HashMap::new(),
+            orders : Slab::new(),
+            order_index : HashMap::new(),
             user_orders : HashMap::new(),
             best_bid : None,
             best_ask : None,
@@ -111,14 +205,14 @@ impl OrderBook{
     }
     
 
-    pub fn get_orderbook_side(&mut self,side:Side)->&mut  BTreeMap<Price,PriceLevel>{
+    pub fn get_orderbook_side(&mut self,side:Side)->&mut  BTreeMap<Ticks,PriceLevel>{
         match side {
             Side::Buy => &mut self.bids,
             Side::Sell => &mut self.asks
         }
     }
    
-    pub fn get_opposite_side(&mut self,side:Side)->&mut BTreeMap<Price,PriceLevel>{
+    pub fn get_opposite_side(&mut self,side:Side)->&mut BTreeMap<Ticks,PriceLevel>{
         match side {
             Side::Buy => &mut self.asks,
             Side::Sell => &mut self.bids
@@ -129,6 +223,18 @@ impl OrderBook{
         self.best_ask = self.asks.keys().next().cloned();
         self.best_bid = self.bids.keys().next_back().cloned();
     }
+
+    pub fn get_order(&self, order_id: &OrderId) -> Option<&Order> {
+        let handle = self.order_index.get(order_id)?;
+        self.orders.get(*handle).map(|resting| &resting.order)
+    }
+
+    pub fn iter_level<'a>(&'a self, level: &PriceLevel) -> LevelIter<'a> {
+        LevelIter {
+            orders: &self.orders,
+            next: level.head,
+        }
+    }
    
 
     pub fn insert_order (&mut self,order: Order){
@@ -138,55 +244,51 @@ impl OrderBook{
         let order_id = order.order_id;
         let user_id = order.user_id;
         let price = order.price.unwrap();
-        let amount = order.remaining();
-        let side = order.side;
-
 
-        let book = self.get_orderbook_side(side);
-        
-        let level = book.entry(price).or_insert_with(|| PriceLevel{
-            price,
-            orders : VecDeque::new(),
-            total_qty : dec!(0)
-        });
-        level.orders.push_back(order_id);
-        level.total_qty += amount;
+        let book = match order.side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        };
+        let handle = book.entry(price)
+            .or_insert_with(|| PriceLevel::new(price))
+            .push_back(order, &mut self.orders);
 
+        self.order_index.insert(order_id, handle);
         self.user_orders
             .entry(user_id)
-            .or_insert_with(Vec::new)
-            .push(order_id);
+            .or_default()
+            .insert(handle);
 
-        self.orders.insert(order_id,order);
         self.update_best_prices();
 
     }
     
     pub fn cancel_order(&mut self, order_id : &OrderId, user_id :&UserId)->Result<Order,String>{
-        let order = self.orders.get(order_id).ok_or("order is not found").unwrap();
+        let handle = *self.order_index.get(order_id).ok_or("order is not found")?;
+        let resting = self.orders.get(handle).ok_or("order is not found")?;
 
-        if &order.user_id != user_id{
+        if &resting.order.user_id != user_id{
             return Err("unauthorized : not owner order".into());
         }
 
-        let order = self.orders.remove(order_id).unwrap();
-        let price = order.price.unwrap();
-        let side = order.side;
-
-        let book = self.get_orderbook_side(side);
-
-         //if always expects a boolean expression but if let is not a boolean expression
-         //if let is syntactic sugar for a match, not a normal if.
-        if let Some(level) =  book.get_mut(&price){
-            level.orders.retain(|id|id!=order_id); //retain keep the element where clouser return true
-            level.total_qty -= order.quantity;
+        let price = resting.order.price.unwrap();
+        let book = match resting.order.side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        };
+        let level = book.get_mut(&price).ok_or("order level is not found")?;
+        let order = level.unlink(handle, &mut self.orders).ok_or("order is not found")?.order;
+        self.order_index.remove(order_id);
+        level.total_qty -= order.remaining();
 
-            if level.orders.is_empty() {
-                book.remove(&price);
-            }
+        if level.is_empty() {
+            book.remove(&price);
         }
         if let Some(user_orders) = self.user_orders.get_mut(user_id){
-            user_orders.retain(|id|id!=order_id);
+            user_orders.remove(&handle);
+            if user_orders.is_empty() {
+                self.user_orders.remove(user_id);
+            }
         }
         self.update_best_prices();
 
@@ -194,26 +296,27 @@ impl OrderBook{
     }
    
 
-    pub fn match_order(&mut self, mut taker:  Order) -> (Vec<Fill>, Option<Order>) {
+    pub fn match_order(&mut self, mut taker:  Order, timestamp: u128) -> (Vec<Fill>, Option<Order>) {
         let mut fills: Vec<Fill> = Vec::new();
 
         loop {
-            if taker.remaining() <= dec!(0) {
+            if taker.remaining() == 0 {
                 break;
             }
 
-            let best_price = {
-                let side = self.get_opposite_side(taker.side);
-                match taker.side {
-                    Side::Buy => side.keys().next().cloned(),
-                    Side::Sell => side.keys().next_back().cloned(),
-                }
+            let book = match taker.side {
+                Side::Buy => &mut self.asks,
+                Side::Sell => &mut self.bids,
             };
-
-            let best_price = match best_price {
-                Some(p) => p,
+            let best = match taker.side {
+                Side::Buy => book.first_entry(),
+                Side::Sell => book.last_entry(),
+            };
+            let mut best = match best {
+                Some(level) => level,
                 None => break,
             };
+            let best_price = *best.key();
 
             if let OrderType::Limit = taker.order_type {
                 let taker_price = taker.price.unwrap();
@@ -226,27 +329,13 @@ impl OrderBook{
                 }
             }
 
-            let maker_ids: Vec<Uuid> = {
-                let side = self.get_opposite_side(taker.side);
-                if let Some(level) = side.get(&best_price) {
-                    level.orders.iter().cloned().collect()
-                } else {
-                    break;
-                }
-            };
-
-            let mut orders_to_remove = Vec::new();
-            let mut total_qty_decrease = dec!(0);
-
-            for maker_id in maker_ids {
-                if taker.remaining() <= dec!(0) {
-                    break;
-                }
-
-                let maker = match self.orders.get_mut(&maker_id) {
-                    Some(m) => m,
-                    None => continue,
+            let level = best.get_mut();
+            while taker.remaining() > 0 {
+                let maker_handle = match level.head {
+                    Some(handle) => handle,
+                    None => break,
                 };
+                let maker = &mut self.orders.get_mut(maker_handle).expect("broken level link").order;
 
                 let qty = maker.remaining().min(taker.remaining());
 
@@ -263,32 +352,30 @@ impl OrderBook{
                     taker_leverage: taker.leverage,
                     maker_side: maker.side,
                     taker_side: taker.side,
-                    timestamp_: now_nanos(),
+                    timestamp_: timestamp,
                 });
 
                 maker.filled += qty;
                 taker.filled += qty;
-                total_qty_decrease += qty;
-
-                if maker.remaining() <= dec!(0) {
-                    orders_to_remove.push(maker_id);
-                }
-            }
-
-            {
-                let side = self.get_opposite_side(taker.side);
-                if let Some(level) = side.get_mut(&best_price) {
-                    level.total_qty -= total_qty_decrease;
-                    level.orders.retain(|id| !orders_to_remove.contains(id));
+                level.total_qty -= qty;
+
+                if maker.remaining() == 0 {
+                    let maker_user = maker.user_id;
+                    let maker_id = maker.order_id;
+                    level.unlink(maker_handle, &mut self.orders);
+                    self.order_index.remove(&maker_id);
+                    if let Some(list) = self.user_orders.get_mut(&maker_user) {
+                        list.remove(&maker_handle);
+                        if list.is_empty() {
+                            self.user_orders.remove(&maker_user);
+                        }
+                    }
                 }
             }
 
-            for id in orders_to_remove {
-                if let Some(order) = self.orders.remove(&id) {
-                    if let Some(list) = self.user_orders.get_mut(&order.user_id) {
-                        list.retain(|x| x != &id);
-                    }
-                }
+            //drop the emptied level, otherwise the next iteration finds it again as best price and never advances
+            if level.is_empty() {
+                best.remove();
             }
         }
 
@@ -297,7 +384,7 @@ impl OrderBook{
 
         let remaining = match taker.order_type {
             OrderType::Limit => {
-                if taker.remaining() > dec!(0) {
+                if taker.remaining() > 0 {
                     Some(taker)
                 } else {
                     None
@@ -310,11 +397,3 @@ impl OrderBook{
     }
     
 }
-pub fn now_nanos() -> u128 {
-        SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_nanos()
-}
-
-
@@ -1,17 +1,105 @@
 // Lockless ring buffer for high-throughput event streaming
+//
+// One producer, one consumer. The only exception is force_push (drop-oldest overflow), where the
+// producer takes the oldest item itself: both sides then race for read_idx with a CAS, and the
+// producer waits out any read in progress before reusing a slot it freed that way.
+//
+// Built with --cfg backend_loom the atomics and slots come from loom, so tests/loom_ring_buffer.rs can
+// model-check the interleavings (see the note at the top of that file).
 
 use std::mem::MaybeUninit;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::Ordering;
+
+use crate::{SpinThenYield, WaitStrategy};
+
+use self::sync::{AtomicBool, AtomicU64, AtomicUsize, UnsafeCell, spin_loop};
+
+#[cfg(backend_loom)]
+mod sync {
+    pub use loom::cell::UnsafeCell;
+    pub use loom::hint::spin_loop;
+    pub use loom::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize};
+}
+
+#[cfg(not(backend_loom))]
+mod sync {
+    pub use std::hint::spin_loop;
+    pub use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize};
+
+    // std's UnsafeCell behind the closure api of loom's, so the ring reads the same either way
+    pub struct UnsafeCell<T>(std::cell::UnsafeCell<T>);
+
+    impl<T> UnsafeCell<T> {
+        pub fn new(value: T) -> Self {
+            Self(std::cell::UnsafeCell::new(value))
+        }
+
+        pub fn with<R>(&self, f: impl FnOnce(*const T) -> R) -> R {
+            f(self.0.get())
+        }
+
+        pub fn with_mut<R>(&self, f: impl FnOnce(*mut T) -> R) -> R {
+            f(self.0.get())
+        }
+    }
+}
 
 #[repr(align(64))]
 pub struct AlignedUsize(pub AtomicUsize);
 
 pub struct RingBuffer<T> {
-    buffer: Vec<MaybeUninit<T>>,
+    buffer: Box<[UnsafeCell<MaybeUninit<T>>]>, //slots between read_idx and write_idx are initialized
     capacity: usize,
     mask: usize,  //This does wraparound for free using fast bitmasking, instead of slow modulo.
     write_idx: AlignedUsize,
     read_idx: AlignedUsize,
+    consumer_active: AtomicBool, //set while the consumer copies items out, see force_push
+    wait: Box<dyn WaitStrategy>, //how the consumer waits in pop_wait/wait_for_items
+    high_water: AtomicUsize,
+    full: AtomicU64,
+    dropped: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct RingStats {
+    pub capacity: usize,
+    pub occupancy: usize,
+    pub high_water_mark: usize,
+    //pushes that found the buffer full, whatever the caller did about it
+    pub full: u64,
+    //items that never reached the consumer: evicted by force_push or given up on (record_drop)
+    pub dropped: u64,
+}
+
+// What a producer does when the buffer is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    // wait for the consumer, nothing is lost
+    Block,
+    // spin this many times, then give up on the new item
+    SpinThenFail(u32),
+    // make room by discarding the oldest unread item
+    DropOldest,
+    // deliver this item (waiting if needed) and stop the producer afterwards
+    Halt,
+}
+
+// block, drop-oldest, halt or spin:<n>
+impl std::str::FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(Self::Block),
+            "drop-oldest" => Ok(Self::DropOldest),
+            "halt" => Ok(Self::Halt),
+            _ => s
+                .strip_prefix("spin:")
+                .and_then(|spins| spins.parse().ok())
+                .map(Self::SpinThenFail)
+                .ok_or_else(|| format!("unknown overflow policy {s}")),
+        }
+    }
 }
 
 unsafe impl<T: Send> Send for RingBuffer<T> {}
@@ -24,10 +112,7 @@ impl<T> RingBuffer<T> {
         assert!(capacity > 1, "Capacity must be > 1");
         
         // Pre-allocate buffer with uninitialized memory
-        let mut buffer = Vec::with_capacity(capacity);
-        for _ in 0..capacity {
-            buffer.push(MaybeUninit::uninit());  //They are uninitialized (MaybeUninit).
-        }
+        let buffer = (0..capacity).map(|_| UnsafeCell::new(MaybeUninit::uninit())).collect();  //They are uninitialized (MaybeUninit).
 
         Self {
             buffer,
@@ -35,10 +120,55 @@ impl<T> RingBuffer<T> {
             mask: capacity - 1,
             write_idx: AlignedUsize(AtomicUsize::new(0)),
             read_idx: AlignedUsize(AtomicUsize::new(0)),
+            consumer_active: AtomicBool::new(false),
+            wait: Box::new(SpinThenYield::default()),
+            high_water: AtomicUsize::new(0),
+            full: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
         }
     }
+
+    pub fn with_wait_strategy(mut self, wait: impl WaitStrategy + 'static) -> Self {
+        self.wait = Box::new(wait);
+        self
+    }
+
+    //one slot always stays empty to tell full from empty
+    pub fn capacity(&self) -> usize {
+        self.capacity - 1
+    }
+
+    pub fn len(&self) -> usize {
+        let write = self.write_idx.0.load(Ordering::Acquire);
+        let read = self.read_idx.0.load(Ordering::Acquire);
+        write.wrapping_sub(read) & self.mask
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.read_idx.0.load(Ordering::Acquire) == self.write_idx.0.load(Ordering::Acquire)
+    }
+
+    pub fn stats(&self) -> RingStats {
+        RingStats {
+            capacity: self.capacity(),
+            occupancy: self.len(),
+            high_water_mark: self.high_water.load(Ordering::Relaxed),
+            full: self.full.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+
+    //for producers that give up on an item after a failed push, so the loss shows in stats
+    pub fn record_drop(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
   
     pub fn push(&self,item:T)->bool{
+        self.try_push(item).is_ok()
+    }
+
+    //like push but hands the item back when the buffer is full, so the caller can wait and retry
+    pub fn try_push(&self,item:T)->Result<(),T>{
         let write = self.write_idx.0.load(Ordering::Relaxed);
         let read = self.read_idx.0.load(Ordering::Acquire);  //why accquire here (Acquiew ensure Producer sees all consumer-side memory writes before the consumer updated read_idx.)
         //Consumer updates read_idx with Release
@@ -46,16 +176,48 @@ impl<T> RingBuffer<T> {
         let next_write = (write+1) & self.mask;
 
         if next_write == read {  //Cannot overwrite unread data.
-            return false;
+            self.full.fetch_add(1, Ordering::Relaxed);
+            return Err(item);
         }
 
-        unsafe {  //happens before publishng write index
-            let slot = self.buffer.get_unchecked(write);
-            let ptr = slot.as_ptr() as *mut T;
-            ptr.write(item);
-        }
+        self.write_slot(write, item);
         self.write_idx.0.store(next_write,Ordering::Release);  //Publish the new write index (Release)
-        true
+        self.high_water.fetch_max(next_write.wrapping_sub(read) & self.mask, Ordering::Relaxed);
+        self.wait.notify();  //wakes a parked consumer, free for the spinning strategies
+        Ok(())
+    }
+
+    //never fails: when full the oldest unread item is taken out and returned to the caller
+    pub fn force_push(&self,item:T)->Option<T>{
+        let mut item = item;
+        loop {
+            match self.try_push(item) {
+                Ok(()) => return None,
+                Err(back) => item = back,
+            }
+            let write = self.write_idx.0.load(Ordering::Relaxed);
+            let read = self.read_idx.0.load(Ordering::SeqCst);
+            if (write+1) & self.mask != read {
+                //the consumer made room since try_push looked, slot read may already be empty
+                continue;
+            }
+            let oldest = self.read_slot(read);
+            if self.read_idx.0.compare_exchange(read, (read+1) & self.mask, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+                //the consumer took it first, so there is room now
+                std::mem::forget(oldest);
+                continue;
+            }
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            //a consumer that loaded read_idx before our CAS may still be copying the slot we are
+            //about to reuse; it will lose its own CAS, but it must finish reading first
+            while self.consumer_active.load(Ordering::SeqCst) {
+                spin_loop();
+            }
+            if self.try_push(item).is_err() {
+                unreachable!("slot freed by eviction was taken");
+            }
+            return Some(oldest);
+        }
     }
 
     pub fn push_spin(&mut self,item : T,max_spins: usize)->bool
@@ -72,27 +234,8 @@ impl<T> RingBuffer<T> {
         false  //still full after spining
     }
     pub fn try_pop(&self)->Option<T>{
-        //Load current read index (relaxed)
-        let read = self.read_idx.0.load(Ordering::Relaxed);  //Consumer owns read_idx. No ordering required for loading.
-        let write = self.write_idx.0.load(Ordering::Acquire);  //Producer stored write_idx using Release.
-        //Consumer must see all the writes that happened before producer’s Release.Acquire guarantees:Consumer sees the correct data written into slot before reading it.
-
-        if read == write {
-            return None; //No unread data.
-        }
-
-        let item = unsafe {
-
-                let slot = self.buffer.get_unchecked(read);
-                let ptr = slot.as_ptr() as *mut T;
-                ptr.read()
-        };
-        let next_read = (read+1) &self.mask;
-        self.read_idx.0.store(next_read,Ordering::Release); //Consumer uses Release because:
-        //Consumer is saying: "I finished reading this slot, it is now free."
-        //Producer loads read_idx with Acquire in push.
-        Some(item)
-        
+        let mut batch = self.drain_batch(1);
+        batch.pop()
     }  
     pub fn pop_spin(&self,max_spins:usize)->Option<T>{
         for i in 0..max_spins {
@@ -109,40 +252,76 @@ impl<T> RingBuffer<T> {
         }
         None
     }
+    //waits with the configured strategy, false if it gave up with the buffer still empty
+    pub fn wait_for_items(&self)->bool{
+        self.wait.wait_until(&mut || !self.is_empty())
+    }
+
+    pub fn pop_wait(&self)->Option<T>{
+        if self.wait_for_items() {
+            self.try_pop()
+        } else {
+            None
+        }
+    }
+
     //Drain multiple item at once (batched read)
     //more efficent then calling try_pop() in loop (becasue we only update read_idx once)
 
     pub fn drain_batch(&self,max_items:usize)->Vec<T>{
-        let mut batch = Vec::with_capacity(max_items);
-
-        let read = self.read_idx.0.load(Ordering::Relaxed);
-        let write = self.write_idx.0.load(Ordering::Acquire);
+        loop {
+            //announce the read before looking at read_idx, pairs with the check in force_push
+            self.consumer_active.store(true, Ordering::SeqCst);
+            let read = self.read_idx.0.load(Ordering::SeqCst);
+            let write = self.write_idx.0.load(Ordering::Acquire);  //Producer stored write_idx using Release.
+            //Consumer must see all the writes that happened before producer’s Release.Acquire guarantees:Consumer sees the correct data written into slot before reading it.
 
-        let available = if write>= read{
-            write - read
-        }else {
-            self.capacity - read + write
-        };
-        let to_read = available.min(max_items);
-
-        let mut current = read;
-        
-        for _ in 0..to_read {
-            let item = unsafe {
-                let slot = self.buffer.get_unchecked(current);
-                let ptr = slot.as_ptr();
-                ptr.read()
-            };
-            batch.push(item);
-            current = (current+1) & self.mask;
-        }
-        if !batch.is_empty() {
-            self.read_idx.0.store(current,Ordering::Release);
+            let to_read = (write.wrapping_sub(read) & self.mask).min(max_items);
+            let mut batch = Vec::with_capacity(to_read);
+            let mut current = read;
+            for _ in 0..to_read {
+                batch.push(self.read_slot(current));
+                current = (current+1) & self.mask;
+            }
+            if batch.is_empty() {
+                self.consumer_active.store(false, Ordering::SeqCst);
+                return batch;
+            }
+            //Consumer is saying: "I finished reading these slots, they are now free."
+            //Producer loads read_idx with Acquire in push.
+            let claimed = self.read_idx.0.compare_exchange(read, current, Ordering::SeqCst, Ordering::SeqCst).is_ok();
+            self.consumer_active.store(false, Ordering::SeqCst);
+            if claimed {
+                return batch;
+            }
+            //force_push evicted the oldest item meanwhile, it owns that one now, so none of these
+            //copies may be dropped
+            for item in batch {
+                std::mem::forget(item);
+            }
         }
-        batch
-        
     }
-}
 
+    //only for the free slot at write_idx, which no consumer looks at until write_idx moves past it
+    fn write_slot(&self, index: usize, item: T) {
+        //happens before publishng write index
+        self.buffer[index].with_mut(|slot| unsafe { (*slot).write(item) });
+    }
 
+    //bitwise copy out of the slot, whoever wins the read_idx CAS keeps it, the other forgets it
+    fn read_slot(&self, index: usize) -> T {
+        self.buffer[index].with(|slot| unsafe { (*slot).assume_init_read() })
+    }
+}
 
+impl<T> Drop for RingBuffer<T> {
+    //whatever was pushed and never read still owns resources
+    fn drop(&mut self) {
+        let write = self.write_idx.0.load(Ordering::Acquire);
+        let mut read = self.read_idx.0.load(Ordering::Acquire);
+        while read != write {
+            self.buffer[read].with_mut(|slot| unsafe { (*slot).assume_init_drop() });
+            read = (read + 1) & self.mask;
+        }
+    }
+}
@@ -0,0 +1,105 @@
+// Vec backed arena with a free list, entries stay put until removed and freed slots are reused,
+// so live values sit in contiguous memory and are addressed by a small index instead of a hash lookup.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SlabKey(u32);
+
+impl SlabKey {
+    pub fn index(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+enum Entry<T> {
+    Occupied(T),
+    Vacant(Option<u32>), //next free slot
+}
+
+pub struct Slab<T> {
+    entries: Vec<Entry<T>>,
+    free_head: Option<u32>,
+    len: usize,
+}
+
+impl<T> Default for Slab<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Slab<T> {
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: Vec::with_capacity(capacity),
+            free_head: None,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn insert(&mut self, value: T) -> SlabKey {
+        let key = match self.free_head {
+            Some(idx) => {
+                let slot = &mut self.entries[idx as usize];
+                self.free_head = match slot {
+                    Entry::Vacant(next) => *next,
+                    Entry::Occupied(_) => unreachable!("free list points at an occupied slot"),
+                };
+                *slot = Entry::Occupied(value);
+                SlabKey(idx)
+            }
+            None => {
+                let idx = u32::try_from(self.entries.len()).expect("slab is full");
+                self.entries.push(Entry::Occupied(value));
+                SlabKey(idx)
+            }
+        };
+        self.len += 1;
+        key
+    }
+
+    pub fn remove(&mut self, key: SlabKey) -> Option<T> {
+        let slot = self.entries.get_mut(key.index())?;
+        if let Entry::Vacant(_) = slot {
+            return None;
+        }
+        let Entry::Occupied(value) = std::mem::replace(slot, Entry::Vacant(self.free_head)) else {
+            unreachable!()
+        };
+        self.free_head = Some(key.0);
+        self.len -= 1;
+        Some(value)
+    }
+
+    pub fn get(&self, key: SlabKey) -> Option<&T> {
+        match self.entries.get(key.index())? {
+            Entry::Occupied(value) => Some(value),
+            Entry::Vacant(_) => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, key: SlabKey) -> Option<&mut T> {
+        match self.entries.get_mut(key.index())? {
+            Entry::Occupied(value) => Some(value),
+            Entry::Vacant(_) => None,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (SlabKey, &T)> {
+        self.entries.iter().enumerate().filter_map(|(idx, entry)| match entry {
+            Entry::Occupied(value) => Some((SlabKey(idx as u32), value)),
+            Entry::Vacant(_) => None,
+        })
+    }
+}
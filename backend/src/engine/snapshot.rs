@@ -0,0 +1,174 @@
+// Point in time copies of the engine state so recovery only replays the journal tail.
+//
+// A snapshot stores every level with its orders in time priority, which is all that is needed to
+// rebuild the slab, the uuid index and user_orders by re-inserting them in that order. The hash of
+// the live book is stored alongside and checked against the rebuilt one.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{Instrument, Order, OrderBook, Price, PriceLevel, Ticks, types::RiskLimits};
+
+#[derive(Serialize, Deserialize)]
+pub struct LevelSnapshot {
+    pub price: Ticks,
+    pub orders: Vec<Order>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BookSnapshot {
+    pub bids: Vec<LevelSnapshot>,
+    pub asks: Vec<LevelSnapshot>,
+    pub fill_seq: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EngineSnapshot {
+    //last journal seq applied to this state
+    pub journal_seq: u64,
+    pub event_seq: u64,
+    pub instrument: Instrument,
+    pub mark_price: Option<Price>,
+    //set by admins, snapshots from before these existed restore as an open market with default limits
+    #[serde(default)]
+    pub market_halt: Option<String>,
+    #[serde(default)]
+    pub limits: RiskLimits,
+    pub book: BookSnapshot,
+    pub state_hash: String,
+}
+
+impl OrderBook {
+    pub fn snapshot(&self) -> BookSnapshot {
+        let levels = |book: &BTreeMap<Ticks, PriceLevel>| {
+            book.values()
+                .map(|level| LevelSnapshot {
+                    price: level.price,
+                    orders: self.iter_level(level).cloned().collect(),
+                })
+                .collect()
+        };
+        BookSnapshot {
+            bids: levels(&self.bids),
+            asks: levels(&self.asks),
+            fill_seq: self.fill_seq,
+        }
+    }
+
+    pub fn restore(snapshot: BookSnapshot) -> Self {
+        let mut book = OrderBook::new();
+        for level in snapshot.bids.into_iter().chain(snapshot.asks) {
+            for order in level.orders {
+                book.insert_order(order);
+            }
+        }
+        book.fill_seq = snapshot.fill_seq;
+        book
+    }
+
+    // Sha256 over the logical content of the book, independent of slab slots and hash map order
+    pub fn state_hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.fill_seq.to_le_bytes());
+        for (tag, book) in [(b'B', &self.bids), (b'A', &self.asks)] {
+            for level in book.values() {
+                hasher.update([tag]);
+                hasher.update(level.price.to_le_bytes());
+                hasher.update(level.total_qty.to_le_bytes());
+                hasher.update((level.len as u64).to_le_bytes());
+                for order in self.iter_level(level) {
+                    hasher.update(order.order_id.as_bytes());
+                    hasher.update(order.user_id.as_bytes());
+                    hasher.update(order.quantity.to_le_bytes());
+                    hasher.update(order.filled.to_le_bytes());
+                    hasher.update(order.leverage.serialize());
+                }
+            }
+        }
+        let mut users: Vec<_> = self
+            .user_orders
+            .iter()
+            .map(|(user_id, handles)| {
+                let mut ids: Vec<_> = handles
+                    .iter()
+                    .filter_map(|handle| self.orders.get(*handle))
+                    .map(|resting| resting.order.order_id)
+                    .collect();
+                ids.sort();
+                (*user_id, ids)
+            })
+            .collect();
+        users.sort();
+        for (user_id, ids) in users {
+            hasher.update([b'U']);
+            hasher.update(user_id.as_bytes());
+            for id in ids {
+                hasher.update(id.as_bytes());
+            }
+        }
+        hasher.finalize().iter().map(|b| format!("{b:02x}")).collect()
+    }
+}
+
+// Directory of snapshot-<journal seq>.json files, written via a temp file and rename so a
+// crash never leaves a half written snapshot under the final name.
+pub struct SnapshotStore {
+    dir: PathBuf,
+    keep: usize,
+}
+
+impl SnapshotStore {
+    pub fn new(dir: impl AsRef<Path>, keep: usize) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir, keep: keep.max(1) })
+    }
+
+    pub fn write(&self, snapshot: &EngineSnapshot) -> io::Result<PathBuf> {
+        let path = self.dir.join(format!("snapshot-{:020}.json", snapshot.journal_seq));
+        let tmp = path.with_extension("json.tmp");
+        {
+            let mut writer = BufWriter::new(File::create(&tmp)?);
+            serde_json::to_writer(&mut writer, snapshot)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+        }
+        fs::rename(&tmp, &path)?;
+        File::open(&self.dir)?.sync_all()?;
+
+        for (_, old) in self.list()?.into_iter().skip(self.keep) {
+            fs::remove_file(old)?;
+        }
+        Ok(path)
+    }
+
+    // snapshot files, newest first
+    pub fn list(&self) -> io::Result<Vec<(u64, PathBuf)>> {
+        let mut snapshots = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let seq = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix("snapshot-")?.strip_suffix(".json"))
+                .and_then(|seq| seq.parse::<u64>().ok());
+            if let Some(seq) = seq {
+                snapshots.push((seq, path));
+            }
+        }
+        snapshots.sort_by_key(|(seq, _)| std::cmp::Reverse(*seq));
+        Ok(snapshots)
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> io::Result<EngineSnapshot> {
+        let reader = BufReader::new(File::open(path)?);
+        serde_json::from_reader(reader).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+    }
+}
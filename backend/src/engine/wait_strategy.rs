@@ -0,0 +1,165 @@
+// How a consumer waits for the next item when its ring is empty.
+//
+// wait_until returns as soon as `ready` is true, or false once the strategy's budget is used up so
+// the caller can look at its stop flag and call it again. Producers call notify after publishing;
+// only the parking strategies do anything with it.
+
+use std::{
+    sync::{
+        Condvar, Mutex,
+        atomic::{AtomicBool, Ordering, fence},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+pub trait WaitStrategy: Send + Sync {
+    fn wait_until(&self, ready: &mut dyn FnMut() -> bool) -> bool;
+
+    fn notify(&self) {}
+}
+
+// lowest latency, keeps a core at 100% while idle
+pub struct BusySpin;
+
+impl WaitStrategy for BusySpin {
+    fn wait_until(&self, ready: &mut dyn FnMut() -> bool) -> bool {
+        for _ in 0..100_000 {
+            if ready() {
+                return true;
+            }
+            std::hint::spin_loop();
+        }
+        ready()
+    }
+}
+
+// what pop_spin does: a few tight spins, then give the core to the scheduler between checks
+pub struct SpinThenYield {
+    spins: u32,
+    yields: u32,
+}
+
+impl SpinThenYield {
+    pub fn new(spins: u32, yields: u32) -> Self {
+        Self { spins, yields }
+    }
+}
+
+impl Default for SpinThenYield {
+    fn default() -> Self {
+        Self::new(100, 1_000)
+    }
+}
+
+impl WaitStrategy for SpinThenYield {
+    fn wait_until(&self, ready: &mut dyn FnMut() -> bool) -> bool {
+        for i in 0..self.spins + self.yields {
+            if ready() {
+                return true;
+            }
+            if i < self.spins {
+                std::hint::spin_loop();
+            } else {
+                thread::yield_now();
+            }
+        }
+        ready()
+    }
+}
+
+// Sleeps on a condvar until notified. `waiting` keeps notify to an atomic load while nobody is
+// parked; the fences make sure a producer either sees the flag or the consumer sees the item.
+struct Parker {
+    lock: Mutex<()>,
+    cond: Condvar,
+    waiting: AtomicBool,
+}
+
+impl Parker {
+    fn new() -> Self {
+        Self {
+            lock: Mutex::new(()),
+            cond: Condvar::new(),
+            waiting: AtomicBool::new(false),
+        }
+    }
+
+    fn park(&self, ready: &mut dyn FnMut() -> bool, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut guard = self.lock.lock().unwrap();
+        self.waiting.store(true, Ordering::SeqCst);
+        fence(Ordering::SeqCst);
+        let ready = loop {
+            if ready() {
+                break true;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                break false;
+            }
+            guard = self.cond.wait_timeout(guard, deadline - now).unwrap().0;
+        };
+        self.waiting.store(false, Ordering::Relaxed);
+        ready
+    }
+
+    fn unpark(&self) {
+        fence(Ordering::SeqCst);
+        if self.waiting.load(Ordering::SeqCst) {
+            let _guard = self.lock.lock().unwrap();
+            self.cond.notify_all();
+        }
+    }
+}
+
+// spins briefly for bursts, then sleeps until the producer wakes it
+pub struct SpinThenPark {
+    spins: u32,
+    parker: Parker,
+}
+
+impl SpinThenPark {
+    pub fn new(spins: u32) -> Self {
+        Self { spins, parker: Parker::new() }
+    }
+}
+
+impl WaitStrategy for SpinThenPark {
+    fn wait_until(&self, ready: &mut dyn FnMut() -> bool) -> bool {
+        for _ in 0..self.spins {
+            if ready() {
+                return true;
+            }
+            std::hint::spin_loop();
+        }
+        //woken by notify, the timeout only bounds how long a stop request can go unnoticed
+        self.parker.park(ready, Duration::from_millis(50))
+    }
+
+    fn notify(&self) {
+        self.parker.unpark();
+    }
+}
+
+// blocks straight away for at most `timeout`, for consumers that do not care about latency
+pub struct TimeoutBlocking {
+    timeout: Duration,
+    parker: Parker,
+}
+
+impl TimeoutBlocking {
+    pub fn new(timeout: Duration) -> Self {
+        Self { timeout, parker: Parker::new() }
+    }
+}
+
+impl WaitStrategy for TimeoutBlocking {
+    fn wait_until(&self, ready: &mut dyn FnMut() -> bool) -> bool {
+        self.parker.park(ready, self.timeout)
+    }
+
+    fn notify(&self) {
+        self.parker.unpark();
+    }
+}
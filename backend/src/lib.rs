@@ -0,0 +1,11 @@
+pub mod auth;
+pub use auth::*;
+pub mod models;
+pub mod types;
+pub mod engine;
+pub use engine::*;
+pub mod pipeline;
+pub use pipeline::*;
+pub mod state;
+pub mod routes;
+pub use routes::*;
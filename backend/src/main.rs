@@ -1,37 +1,63 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use actix_web::{App, HttpServer, web};
+use backend::{ApiKeyCipher, BroadcastRingBuilder, EventDispatcher, Instrument, Journal, LoggingSink, MarketDataPublisher, KeySet, LoginLimiter, MatchingEngine, NonceCache, OrderRateLimiter, OverflowPolicy, RingBuffer, SnapshotStore, SpinThenPark, TimeoutBlocking, TradePersister, routes, spawn_consumer, state::AppState, types::*};
 use db::Db;
-pub mod auth;
-pub use auth::*;
-pub mod models;
-pub use models::*;
-pub mod types;
+use rust_decimal_macros::dec;
 use std::sync::mpsc;
-use types::*;
-pub mod engine;
-pub use engine::*;
-
-use crate::state::AppState;
-pub mod state;
 
 
 #[actix_web::main]
 async fn main() {
     let (book_tx, book_rx) = mpsc::sync_channel::<OrderBookMessage>(1000);
 
-    let ring_buffer = Arc::new(RingBuffer::<Event>::new(256));
+    let ring_buffer = Arc::new(RingBuffer::<EventEnvelope>::new(1 << 14).with_wait_strategy(SpinThenPark::new(1_000)));
     let engine_ring = Arc::clone(&ring_buffer);
+    let instrument = Instrument::new(dec!(0.01), dec!(0.001));
 
     dotenvy::dotenv().ok();
     let db = Db::new().await.expect("db init failed");
 
-    std::thread::Builder::new()
+    let journal_path = std::env::var("ENGINE_JOURNAL").unwrap_or_else(|_| "data/engine.journal".to_string());
+    let journal = Journal::open(&journal_path).expect("failed to open engine journal");
+    let snapshot_dir = std::env::var("ENGINE_SNAPSHOT_DIR").unwrap_or_else(|_| "data/snapshots".to_string());
+    let snapshot_every = std::env::var("ENGINE_SNAPSHOT_EVERY").ok().and_then(|v| v.parse().ok()).unwrap_or(10_000);
+    let overflow: OverflowPolicy = std::env::var("ENGINE_EVENT_OVERFLOW")
+        .map(|v| v.parse().expect("invalid ENGINE_EVENT_OVERFLOW"))
+        .unwrap_or(OverflowPolicy::Block);
+    let snapshots = SnapshotStore::new(&snapshot_dir, 3).expect("failed to open snapshot dir");
+
+    //persistence first, market data only publishes what is already stored
+    let mut broadcast = BroadcastRingBuilder::new(1 << 14)
+        .consumer_with_wait("persistence", &[], TimeoutBlocking::new(Duration::from_millis(100)))
+        .consumer_with_wait("market-data", &["persistence"], SpinThenPark::new(1_000));
+    let log_events = std::env::var("ENGINE_LOG_EVENTS").is_ok();
+    if log_events {
+        broadcast = broadcast.consumer_with_wait("logger", &[], TimeoutBlocking::new(Duration::from_millis(100)));
+    }
+    let (producer, mut consumers) = broadcast.build::<EventEnvelope>();
+    let publisher = MarketDataPublisher::new(4096);
+    let market_data = publisher.feed();
+    let mut consumer_handles = vec![
+        spawn_consumer(consumers.remove(0), TradePersister::new()),
+        spawn_consumer(consumers.remove(0), publisher),
+    ];
+    if log_events {
+        consumer_handles.push(spawn_consumer(consumers.remove(0), LoggingSink));
+    }
+    let dispatcher = EventDispatcher::new(Arc::clone(&ring_buffer)).with_sink(producer).spawn();
+    println!("[MAIN] Event dispatcher spawned");
+
+    let engine = std::thread::Builder::new()
         .name("matching-engine".to_string())
         .spawn(move || {
             println!("[ENGINE] Matching engine thread started");
 
-            let mut engine = MatchingEngine::new(engine_ring);
+            let mut engine = MatchingEngine::new(engine_ring, instrument).with_journal(journal)
+                .with_snapshots(snapshots, snapshot_every)
+                .with_overflow_policy(overflow);
+            let replayed = engine.recover().expect("journal replay failed");
+            println!("[ENGINE] Recovered {} journaled commands", replayed);
             engine.run(book_rx);
 
             println!("[ENGINE] Matching engine stopped");
@@ -41,19 +67,36 @@ async fn main() {
     println!("[MAIN] Matching engine spawned");
 
     // THEN START HTTP SERVER
+    let keys = Arc::new(KeySet::from_env().expect("failed to load jwt keys"));
+    let api_key_cipher = Arc::new(ApiKeyCipher::from_env().expect("failed to load the api key encryption key"));
+    let nonces = Arc::new(NonceCache::new());
+    let login_limiter = Arc::new(LoginLimiter::from_env(&db));
+    let order_limiter = Arc::new(OrderRateLimiter::from_env().expect("invalid order rate limit"));
     let _ = HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(AppState {
                 book_tx: book_tx.clone(),
                 db: db.clone(),
+                instrument,
+                event_ring: Arc::clone(&ring_buffer),
+                keys: Arc::clone(&keys),
+                api_key_cipher: Arc::clone(&api_key_cipher),
+                nonces: Arc::clone(&nonces),
+                login_limiter: Arc::clone(&login_limiter),
+                order_limiter: Arc::clone(&order_limiter),
+                market_data: market_data.clone(),
             }))
-            .service(web::resource("/signin").route(web::post().to(create_user)))
-            .service(web::resource("/signin").route(web::post().to(signin)))
-            .service(web::resource("/place_order").route(web::post().to(place_order)))
-            .service(web::resource("/cancel_order").route(web::post().to(cancel_order)))
+            .configure(routes)
     })
     .bind("0.0.0.0:3000")
     .unwrap()
     .run()
     .await;
+
+    //the server held the last senders, so the engine drains its channel and stops, then the dispatcher flushes
+    let _ = engine.join();
+    dispatcher.shutdown();
+    for consumer in consumer_handles {
+        consumer.shutdown();
+    }
 }
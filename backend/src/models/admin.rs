@@ -0,0 +1,114 @@
+use actix_web::{HttpResponse, web::{self, Data, Json}};
+use rust_decimal::{Decimal, prelude::FromPrimitive};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+use crate::{AuthError, AuthUser, MfaVerified, state::AppState, types::{ForceCancelResponse, HaltRequest, MAX_LEVERAGE, MarketStatusResponse, OrderBookMessage, OrderResponse, Response, RiskLimits, RiskLimitsRequest, Role, RoleRequest}};
+
+type Responder = Option<oneshot::Sender<Result<OrderResponse, String>>>;
+
+//everything here sits behind RequireRole(Role::ADMIN) and also wants a 2FA session, so neither a
+//stolen password nor an API key is enough to stop the market
+
+pub async fn halt_market(state:Data<AppState>,AuthUser(admin):AuthUser,_:MfaVerified,body:Json<HaltRequest>)->HttpResponse{
+    let reason = body.into_inner().reason.trim().to_string();
+    if reason.is_empty() {
+        return bad_request("A halt needs a reason");
+    }
+    println!("[ADMIN] {admin} halts the market: {reason}");
+    market_status(&state, ask_engine(&state, |responder| OrderBookMessage::HaltMarket { reason, responder }).await)
+}
+
+pub async fn resume_market(state:Data<AppState>,AuthUser(admin):AuthUser,_:MfaVerified)->HttpResponse{
+    println!("[ADMIN] {admin} resumes the market");
+    market_status(&state, ask_engine(&state, |responder| OrderBookMessage::ResumeMarket { responder }).await)
+}
+
+pub async fn set_risk_limits(state:Data<AppState>,AuthUser(admin):AuthUser,_:MfaVerified,body:Json<RiskLimitsRequest>)->HttpResponse{
+    let req = body.into_inner();
+    let max_leverage = Decimal::from(req.max_leverage);
+    if max_leverage < Decimal::ONE || max_leverage > MAX_LEVERAGE {
+        return bad_request(&format!("max_leverage must be between 1 and {MAX_LEVERAGE}"));
+    }
+    let max_order_lots = match req.max_order_quantity {
+        None => None,
+        Some(quantity) => match Decimal::from_f64(quantity).and_then(|q| state.instrument.to_lots(q)) {
+            Some(lots) if lots > 0 => Some(lots),
+            _ => return bad_request(&format!("max_order_quantity must be a positive multiple of the lot size {}", state.instrument.lot_size)),
+        },
+    };
+    let max_open_orders = req.max_open_orders();
+    if max_open_orders == Some(0) {
+        return bad_request("max_open_orders must be positive, null removes the cap");
+    }
+    let limits = RiskLimits { max_leverage, max_order_lots, max_open_orders };
+    println!("[ADMIN] {admin} sets risk limits {limits:?}");
+    market_status(&state, ask_engine(&state, |responder| OrderBookMessage::SetRiskLimits { limits, responder }).await)
+}
+
+//cancels every resting order of the user, they can still place new ones
+pub async fn force_cancel(state:Data<AppState>,AuthUser(admin):AuthUser,_:MfaVerified,path:web::Path<Uuid>)->HttpResponse{
+    let user_id = path.into_inner();
+    println!("[ADMIN] {admin} cancels all orders of {user_id}");
+    match ask_engine(&state, |responder| OrderBookMessage::ForceCancel { user_id, responder }).await {
+        Ok(OrderResponse::CancelledAll { user_id, order_ids }) => HttpResponse::Ok().json(ForceCancelResponse { user_id, cancelled: order_ids }),
+        Ok(_) => json_error(HttpResponse::InternalServerError(), "Unexpected engine response"),
+        Err(resp) => resp,
+    }
+}
+
+//tokens keep the old role until their next refresh, API keys pick it up at once
+pub async fn set_user_role(state:Data<AppState>,AuthUser(admin):AuthUser,_:MfaVerified,path:web::Path<Uuid>,body:Json<RoleRequest>)->Result<HttpResponse,AuthError>{
+    let user_id = path.into_inner();
+    let role = body.role;
+    //the last admin could otherwise lock everyone out
+    if user_id == admin && role != Role::Admin {
+        return Err(AuthError::InvalidInput("Admins cannot change their own role"));
+    }
+    let updated = state.db.set_user_role(user_id, role.as_str())
+        .await
+        .map_err(|e| AuthError::Internal(e.to_string()))?;
+    if !updated {
+        return Err(AuthError::UserNotFound);
+    }
+    println!("[ADMIN] {admin} makes {user_id} {}", role.as_str());
+    Ok(HttpResponse::NoContent().finish())
+}
+
+//sends an admin command and waits for the engine's answer
+async fn ask_engine(state:&AppState,command:impl FnOnce(Responder)->OrderBookMessage)->Result<OrderResponse,HttpResponse>{
+    let (tx, rx) = oneshot::channel();
+    if state.book_tx.send(command(Some(tx))).is_err() {
+        return Err(json_error(HttpResponse::ServiceUnavailable(), "Engine unavailable"));
+    }
+    match rx.await {
+        Ok(Ok(response)) => Ok(response),
+        //the engine itself is halted, it takes no commands at all
+        Ok(Err(error)) => Err(json_error(HttpResponse::ServiceUnavailable(), &error)),
+        Err(_) => Err(json_error(HttpResponse::InternalServerError(), "Engine response dropped")),
+    }
+}
+
+fn market_status(state:&AppState,reply:Result<OrderResponse,HttpResponse>)->HttpResponse{
+    match reply {
+        Ok(OrderResponse::MarketStatus { halted, limits }) => HttpResponse::Ok().json(MarketStatusResponse {
+            halted,
+            max_leverage: limits.max_leverage,
+            max_order_quantity: limits.max_order_lots.map(|lots| state.instrument.quantity(lots)),
+            max_open_orders: limits.max_open_orders,
+        }),
+        Ok(_) => json_error(HttpResponse::InternalServerError(), "Unexpected engine response"),
+        Err(resp) => resp,
+    }
+}
+
+fn bad_request(error:&str)->HttpResponse{
+    json_error(HttpResponse::BadRequest(), error)
+}
+
+fn json_error(mut builder:actix_web::HttpResponseBuilder,error:&str)->HttpResponse{
+    builder.json(Response{
+        message: String::new(),
+        error: error.to_string(),
+    })
+}
@@ -0,0 +1,51 @@
+use actix_web::{HttpResponse, web::{self, Data, Json}};
+
+use uuid::Uuid;
+
+use crate::{AuthError, AuthSession, AuthUser, MfaVerified, generate_api_secret, state::AppState, types::{ApiKeyRequest, ApiKeyResponse, NewApiKeyResponse}};
+
+const MAX_LABEL_LEN: usize = 64;
+
+//managing keys takes a signed-in session, AuthSession is never set for requests signed with a key.
+//a key can trade without a password, so creating one takes a 2FA verified session
+
+pub async fn create_api_key(state:Data<AppState>,AuthUser(user_id):AuthUser,_:MfaVerified,body:Json<ApiKeyRequest>)->Result<Json<NewApiKeyResponse>,AuthError>{
+    let ApiKeyRequest { label, scopes, allowed_ips } = body.into_inner();
+    if label.trim().is_empty() || label.chars().count() > MAX_LABEL_LEN {
+        return Err(AuthError::InvalidInput("Label must be 1 to 64 characters"));
+    }
+    if scopes.is_empty() {
+        return Err(AuthError::InvalidInput("An API key needs at least one scope"));
+    }
+    let mut scope_names: Vec<String> = Vec::new();
+    for scope in scopes {
+        if !scope_names.iter().any(|s| s == scope.as_str()) {
+            scope_names.push(scope.as_str().to_string());
+        }
+    }
+
+    let id = Uuid::new_v4();
+    let secret = generate_api_secret();
+    let ips: Vec<String> = allowed_ips.iter().map(|ip| ip.to_string()).collect();
+    let key = state.db.create_api_key(id, user_id, &label, &state.api_key_cipher.encrypt(id, &secret), &scope_names, &ips)
+        .await
+        .map_err(|e| AuthError::Internal(e.to_string()))?;
+    Ok(Json(NewApiKeyResponse { key: key.into(), secret }))
+}
+
+pub async fn list_api_keys(state:Data<AppState>,AuthUser(user_id):AuthUser,_:AuthSession)->Result<Json<Vec<ApiKeyResponse>>,AuthError>{
+    let keys = state.db.list_api_keys(user_id)
+        .await
+        .map_err(|e| AuthError::Internal(e.to_string()))?;
+    Ok(Json(keys.into_iter().map(ApiKeyResponse::from).collect()))
+}
+
+pub async fn revoke_api_key(state:Data<AppState>,AuthUser(user_id):AuthUser,_:AuthSession,path:web::Path<Uuid>)->Result<HttpResponse,AuthError>{
+    let revoked = state.db.revoke_api_key(path.into_inner(), user_id)
+        .await
+        .map_err(|e| AuthError::Internal(e.to_string()))?;
+    if !revoked {
+        return Err(AuthError::ApiKeyNotFound);
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
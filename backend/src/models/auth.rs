@@ -1,37 +1,124 @@
-use actix_web::{HttpRequest, HttpResponse, error::{ErrorConflict, ErrorUnauthorized}, web::{Data, Json}};
-use db::{Db};
-use actix_web::HttpMessage;
+use actix_web::{HttpRequest, HttpResponse, web::{self, Data, Json}};
 
-use crate::{create_jwt, types::{SinginResponse, UserRequest, UserResponse}};
+use chrono::Utc;
+use jsonwebtoken::jwk::JwkSet;
+use uuid::Uuid;
 
+use crate::{AuthError, AuthSession, AuthUser, PasswordCheck, UserId, hash_password, open_mfa_challenge, open_session, refresh_session, state::AppState, verify_password, verify_unknown_user, types::{RefreshRequest, SessionResponse, SigninResponse, TokenResponse, UserRequest, UserResponse}};
 
 
 
-pub async  fn create_user(db:Data<Db>,body:Json<UserRequest>)->Result<Json<UserResponse>,actix_web::error::Error>{
-    let user = db.create_user(&body.email,&body.password)
+pub async fn signup(state:Data<AppState>,body:Json<UserRequest>)->Result<Json<UserResponse>,AuthError>{
+    let UserRequest { email, password } = body.into_inner();
+    if !email.contains('@') {
+        return Err(AuthError::InvalidInput("Invalid email"));
+    }
+    if password.chars().count() < 8 {
+        return Err(AuthError::InvalidInput("Password must be at least 8 characters"));
+    }
+    let password_hash = web::block(move || hash_password(&password))
+        .await
+        .map_err(|e| AuthError::Internal(e.to_string()))?
+        .map_err(|e| AuthError::Internal(e.to_string()))?;
+    let user = state.db.create_user(&email,&password_hash)
         .await
-        .map_err(|e|ErrorConflict(e.to_string()))?;
+        .map_err(|e| AuthError::Internal(e.to_string()))?
+        .ok_or(AuthError::DuplicateEmail)?;
 
     Ok(Json(UserResponse { id: user.id}))
 }
 
 
 
-pub async fn signin(db:Data<Db>,body:Json<UserRequest>)->Result<Json<SinginResponse>,actix_web::error::Error>{
-    let user = db.get_user(&body.email)
+pub async fn signin(req:HttpRequest,state:Data<AppState>,body:Json<UserRequest>)->Result<Json<SigninResponse>,AuthError>{
+    let UserRequest { email, password } = body.into_inner();
+    //the socket address, as for API keys a forwarded-for header would let callers pick their own
+    let ip = req.peer_addr().map(|addr| addr.ip());
+    if let Err(e) = state.login_limiter.check(&email, ip, Utc::now()).await {
+        state.login_limiter.refused(&state.db, &email, ip, &e).await?;
+        return Err(e);
+    }
+    let user = state.db.get_user(&email)
         .await
-        .map_err(|e|ErrorConflict(e.to_string()))?;
+        .map_err(|e| AuthError::Internal(e.to_string()))?;
+
+    //argon2 takes tens of milliseconds on purpose, keep it off the async workers
+    let checked = web::block(move || {
+        let Some(user) = user else {
+            verify_unknown_user(&password);
+            return None;
+        };
+        let check = verify_password(&user, &password);
+        let rehash = match check {
+            PasswordCheck::ValidNeedsRehash => hash_password(&password).ok(),
+            _ => None,
+        };
+        Some((user, check, rehash))
+    })
+    .await
+    .map_err(|e| AuthError::Internal(e.to_string()))?;
 
-    if user.password != body.password {
-        return Err(ErrorUnauthorized("Invalid email or password"));
+    let failure = match &checked {
+        None => Some(AuthError::UnknownUser),
+        Some((_, PasswordCheck::Invalid, _)) => Some(AuthError::WrongPassword),
+        Some(_) => None,
+    };
+    if let Some(e) = failure {
+        state.login_limiter.failed(&state.db, &email, ip, &e, Utc::now()).await?;
+        return Err(e);
+    }
+    let (user, _, rehash) = checked.expect("failures returned above");
+    //legacy plaintext or outdated parameters, the sign-in still succeeds if the upgrade fails
+    if let Some(hash) = rehash && let Err(e) = state.db.set_password_hash(user.id, &hash).await {
+        eprintln!("[AUTH] could not upgrade the password hash of {}: {e}", user.id);
     }
-    let token = create_jwt(user.id);
-    Ok(Json(SinginResponse{
-        token
-    }))
+    //with an authenticator the password only gets as far as the second step, and the account's
+    //failures are only forgotten once signin_second_factor opens the session
+    let totp = state.db.get_totp(user.id)
+        .await
+        .map_err(|e| AuthError::Internal(e.to_string()))?;
+    if totp.is_some_and(|c| c.confirmed_at.is_some()) {
+        return Ok(Json(SigninResponse::MfaRequired(open_mfa_challenge(&state.db, user.id).await?)));
+    }
+    state.login_limiter.succeeded(&email).await?;
+    Ok(Json(SigninResponse::Tokens(open_session(&state.db, &state.keys, user.id, false).await?)))
+}
+
+pub async fn refresh(state:Data<AppState>,body:Json<RefreshRequest>)->Result<Json<TokenResponse>,AuthError>{
+    Ok(Json(refresh_session(&state.db, &state.keys, &body.refresh_token).await?))
+}
+
+//ends the session of the token used for this request, its refresh token stops working
+pub async fn logout(state:Data<AppState>,AuthUser(user_id):AuthUser,AuthSession(session_id):AuthSession)->Result<HttpResponse,AuthError>{
+    revoke(&state, user_id, session_id).await
+}
+
+pub async fn list_sessions(state:Data<AppState>,AuthUser(user_id):AuthUser,AuthSession(current):AuthSession)->Result<Json<Vec<SessionResponse>>,AuthError>{
+    let sessions = state.db.list_sessions(user_id)
+        .await
+        .map_err(|e| AuthError::Internal(e.to_string()))?;
+    Ok(Json(sessions.into_iter().map(|session| SessionResponse { current: session.id == current, session }).collect()))
+}
+
+pub async fn revoke_session(state:Data<AppState>,AuthUser(user_id):AuthUser,_:AuthSession,path:web::Path<Uuid>)->Result<HttpResponse,AuthError>{
+    revoke(&state, user_id, path.into_inner()).await
+}
+
+async fn revoke(state:&AppState,user_id:UserId,session_id:Uuid)->Result<HttpResponse,AuthError>{
+    let revoked = state.db.revoke_session(session_id, user_id)
+        .await
+        .map_err(|e| AuthError::Internal(e.to_string()))?;
+    if !revoked {
+        return Err(AuthError::SessionNotFound);
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub async fn me_handler(user: AuthUser) -> HttpResponse {
+    HttpResponse::Ok().body(format!("User = {}", user.0))
 }
 
-pub async fn me_handler(req: HttpRequest) -> HttpResponse {
-    let user_id = req.extensions().get::<i32>().cloned();
-    HttpResponse::Ok().body(format!("User = {:?}", user_id))
+//public keys other services verify our access tokens with
+pub async fn jwks(state:Data<AppState>) -> Json<JwkSet> {
+    Json(state.keys.jwks().clone())
 }
@@ -0,0 +1,9 @@
+use actix_web::{Responder, web::{self, Json}};
+
+use crate::state::AppState;
+
+//occupancy, high-water mark and drop counters of the engine's event ring, admins only since it
+//tells how loaded the engine is, and for their API keys only with the read scope
+pub async fn event_ring_stats(state: web::Data<AppState>) -> impl Responder {
+    Json(state.event_ring.stats())
+}
@@ -1,4 +1,12 @@
 pub mod auth;
 pub use auth::*;
 pub mod order;
-pub use order::*;
\ No newline at end of file
+pub use order::*;
+pub mod engine;
+pub use engine::*;
+pub mod api_key;
+pub use api_key::*;
+pub mod totp;
+pub use totp::*;
+pub mod admin;
+pub use admin::*;
@@ -1,91 +1,80 @@
-use actix_web::{ Responder, Result, http::StatusCode, web::{self, Json}};
+use std::time::Instant;
+
+use actix_web::{ HttpMessage, HttpRequest, HttpResponse, Responder, ResponseError, Result, http::StatusCode, web::{self, Json}};
 use rust_decimal::{Decimal, prelude::{FromPrimitive}};
 use rust_decimal_macros::dec;
 use tokio::sync::oneshot;
 
-use crate::{LimitOrder, MarketOrder, Order, OrderResponse, state::AppState, types::{ CanceledOrderRequest, OrderBookMessage, OrderRequest, OrderType, Response}};
-
-
-pub async fn place_order(
-    body: Json<OrderRequest>,
-    state:web::Data<AppState>
-)->impl Responder{
-    let req = body.into_inner();
-    let (tx, rx) = oneshot::channel::<Result<OrderResponse,String>>();
+use crate::{AuthUser, Instrument, LimitOrder, MarketOrder, Order, state::AppState, UserId, types::{ BatchOrderItem, BatchOrderRequest, BatchOrderResponse, BatchOrderResult, CanceledOrderRequest, MAX_BATCH_ORDERS, OrderBookMessage, OrderRequest, OrderResponse, OrderType, Priority, Response}};
 
-    let quantity = match Decimal::from_f64(req.quantity){
-        Some(q) if q > dec!(0) =>q,
-        _ => {
-            return (
-                Json(Response{
-                    message: String::new(),
-                    error: "Invalid quantity".to_string(),
-                }),
-                StatusCode::BAD_REQUEST
-            );
-        }
+//checks the request against the instrument and converts it to ticks and lots
+fn build_order(user_id: UserId, req: &OrderRequest, instrument: &Instrument) -> Result<Order, String> {
+    let quantity = match Decimal::from_f64(req.quantity).and_then(|q| instrument.to_lots(q)){
+        Some(q) if q > 0 =>q,
+        _ => return Err(format!("Invalid quantity, must be a positive multiple of the lot size {}", instrument.lot_size)),
     };
 
     let leverage = Decimal::from_u32(req.leverage).unwrap_or(dec!(1));
-    let order = match req.type_{
+    match req.type_{
         OrderType::Limit =>{
-            let price_f64 = match req.price{
-                Some(p)=>p,
-                None=> {
-                    return (
-                        Json(Response{
-                            message : String::new(),
-                            error:"you should have to give the price".to_string()
-                        }),
-                        StatusCode::BAD_REQUEST
-                    );
-                }
+            let Some(price_f64) = req.price else {
+                return Err("you should have to give the price".to_string());
             };
             let price = match Decimal::from_f64(price_f64){
                 Some(p)if p >dec!(0)=>p,
-                _ =>{
-                    return (
-                        Json(
-                            Response{
-                                message : String::new(),
-                                error : "error while converting ".to_string()
-                            }
-                        ),
-                        StatusCode::BAD_REQUEST
-                    );
-                }
+                _ => return Err("error while converting ".to_string()),
             };
-            Order::limit_order(LimitOrder {
-                user_id: req.user_id,
+            let Some(price) = instrument.to_ticks(price) else {
+                return Err(format!("price must be a multiple of the tick size {}", instrument.tick_size));
+            };
+            Ok(Order::limit_order(LimitOrder {
+                user_id,
                 side: req.side,
                 price,
                 quantity,
                 leverage,
-            })
+            }))
         }
         OrderType::Market =>{
-             if req.price.is_some() {
-                return (
-                    Json(Response {
-                        message: String::new(),
-                        error: "Market order must not include price".to_string(),
-                    }),
-                    StatusCode::BAD_REQUEST,
-                );
+            if req.price.is_some() {
+                return Err("Market order must not include price".to_string());
             }
-            Order::market_order(MarketOrder {
-                user_id: req.user_id,
+            Ok(Order::market_order(MarketOrder {
+                user_id,
                 side: req.side,
                 quantity,
                 leverage,
-            })
+            }))
+        }
+    }
+}
+
+
+pub async fn place_order(
+    AuthUser(user_id): AuthUser,
+    body: Json<OrderRequest>,
+    state:web::Data<AppState>
+)->impl Responder{
+    let req = body.into_inner();
+    let (tx, rx) = oneshot::channel::<Result<OrderResponse,String>>();
+
+    let order = match build_order(user_id, &req, &state.instrument) {
+        Ok(order) => order,
+        Err(error) => {
+            return (
+                Json(Response{
+                    message: String::new(),
+                    error,
+                }),
+                StatusCode::BAD_REQUEST
+            );
         }
     };
-    if let Err(_) = state.book_tx.send(OrderBookMessage::PlaceOrder { 
+    if state.book_tx.send(OrderBookMessage::PlaceOrder { 
         order,
-        priority: crate::types::Priority::Normal,
+        priority: Priority::Normal,
         responder: Some(tx)
-    }){
+    }).is_err(){
         return (
             Json(Response{
                 message:String::new(),
@@ -110,6 +99,14 @@ pub async fn place_order(
             }),
             StatusCode::OK,
         ),
+        //rejected by the engine, e.g. over the risk limits or while the market is halted
+        Ok(Err(error)) => (
+            Json(Response {
+                message: String::new(),
+                error,
+            }),
+            StatusCode::BAD_REQUEST,
+        ),
 
         _ => (
             Json(Response {
@@ -124,6 +121,7 @@ pub async fn place_order(
 
 
 pub async fn cancel_order(
+    AuthUser(user_id): AuthUser,
     body: Json<CanceledOrderRequest>,
     state : web::Data<AppState>
 )-> impl Responder{
@@ -131,19 +129,18 @@ pub async fn cancel_order(
     let (tx, rx) = oneshot::channel::<Result<OrderResponse,String>>();
 
     let order_id = req.order_id;
-    let user_id = req.user_id;
 
-    if let Err(_) = state.book_tx.send(OrderBookMessage::CancelOrder { 
+    if state.book_tx.send(OrderBookMessage::CancelOrder { 
         user_id,
         order_id,
         responder:Some(tx)
-     }){
+     }).is_err(){
         return (
             Json(Response{
                 message : String::new(),
-                error : "Erro while sending throug mpsc".to_string()
+                error : "Engine unavailable".to_string()
             }),
-            StatusCode::BAD_REQUEST 
+            StatusCode::SERVICE_UNAVAILABLE
         );
     };
 
@@ -163,13 +160,102 @@ pub async fn cancel_order(
                 }),
                 StatusCode::OK
         ),
-        _ => (
+        //unknown order or someone else's, the engine answers those with a message
+        Ok(Ok(OrderResponse::Message { message: error })) | Ok(Err(error)) => (
+            Json(Response{
+                message : String::new(),
+                error
+            }),
+            StatusCode::BAD_REQUEST,
+        ),
+        Ok(Ok(_)) => (
             Json(Response{
                 message : String::new(),
-                error : "eningne respone".to_string()
+                error : "Unexpected engine response".to_string()
+            }),
+            StatusCode::INTERNAL_SERVER_ERROR,
+        ),
+        Err(_) => (
+            Json(Response{
+                message : String::new(),
+                error : "Engine response dropped".to_string()
             }),
             StatusCode::INTERNAL_SERVER_ERROR,
         ),
     }
     
-}
\ No newline at end of file
+}
+
+
+//places and cancels up to MAX_BATCH_ORDERS orders as one unit: the engine runs them back to back in
+//request order, items that fail validation here are reported without reaching the engine
+pub async fn place_batch(
+    AuthUser(user_id): AuthUser,
+    http: HttpRequest,
+    body: Json<BatchOrderRequest>,
+    state: web::Data<AppState>
+) -> HttpResponse {
+    let req = body.into_inner();
+    if req.orders.is_empty() || req.orders.len() > MAX_BATCH_ORDERS {
+        return HttpResponse::BadRequest().json(Response{
+            message: String::new(),
+            error: format!("a batch must hold between 1 and {MAX_BATCH_ORDERS} orders"),
+        });
+    }
+
+    let mut results: Vec<BatchOrderResult> = Vec::with_capacity(req.orders.len());
+    let mut pending = Vec::new();
+    let mut commands = Vec::new();
+    for (index, item) in req.orders.into_iter().enumerate() {
+        let (tx, rx) = oneshot::channel::<Result<OrderResponse,String>>();
+        let command = match item {
+            BatchOrderItem::Place(order) => build_order(user_id, &order, &state.instrument)
+                .map(|order| OrderBookMessage::PlaceOrder { order, priority: Priority::Normal, responder: Some(tx) }),
+            BatchOrderItem::Cancel(CanceledOrderRequest { order_id }) => {
+                Ok(OrderBookMessage::CancelOrder { order_id, user_id, responder: Some(tx) })
+            }
+        };
+        match command {
+            Ok(command) => {
+                commands.push(command);
+                pending.push(rx);
+                results.push(BatchOrderResult { index, ..Default::default() });
+            }
+            Err(error) => results.push(BatchOrderResult { index, error: Some(error), ..Default::default() }),
+        }
+    }
+
+    //every command sent counts against the rate limit like a single order would, a batch is not a
+    //way around it. Items refused above never reach the engine and cost nothing
+    if let Err(e) = state.order_limiter.charge(&http.extensions(), commands.len() as u32, Instant::now()) {
+        return e.error_response();
+    }
+    if !commands.is_empty() && state.book_tx.send(OrderBookMessage::Batch { commands }).is_err() {
+        return HttpResponse::ServiceUnavailable().json(Response{
+            message: String::new(),
+            error: "Engine unavailable".to_string(),
+        });
+    }
+
+    //the engine answers every command of the unit, in the order they were sent
+    let accepted = results.iter_mut().filter(|result| result.error.is_none());
+    for (result, rx) in accepted.zip(pending) {
+        match rx.await {
+            Ok(Ok(OrderResponse::PlacedOrder { order_id, status, filled, remaining })) => {
+                result.order_id = Some(order_id);
+                result.status = Some(status.to_string());
+                result.filled = Some(filled);
+                result.remaining = Some(remaining);
+            }
+            Ok(Ok(OrderResponse::CanceledOrder { order_id, status, .. })) => {
+                result.order_id = Some(order_id);
+                result.status = Some(status.to_string());
+            }
+            Ok(Ok(OrderResponse::Message { message })) | Ok(Err(message)) => result.error = Some(message),
+            //only admin commands get these
+            Ok(Ok(OrderResponse::MarketStatus { .. } | OrderResponse::CancelledAll { .. })) => result.error = Some("Unexpected engine response".to_string()),
+            Err(_) => result.error = Some("Engine response dropped".to_string()),
+        }
+    }
+    HttpResponse::Ok().json(BatchOrderResponse { results })
+}
@@ -0,0 +1,76 @@
+use actix_web::{HttpRequest, HttpResponse, web::{Data, Json}};
+
+use chrono::Utc;
+
+use crate::{AuthError, AuthSession, AuthUser, MfaVerified, check_totp, complete_mfa_challenge, generate_totp_secret, mfa_challenge_email, new_recovery_codes, open_session, otpauth_uri, state::AppState, verify_second_factor, types::{RecoveryCodesResponse, SecondFactorRequest, TokenResponse, TotpCodeRequest, TotpEnrollmentResponse}};
+
+//second step of signing in for users with 2FA, the session it opens is 2FA verified. Wrong codes
+//count against the account and the address like wrong passwords, otherwise signing in again for
+//a fresh challenge would give unlimited guesses at six digits
+pub async fn signin_second_factor(req:HttpRequest,state:Data<AppState>,body:Json<SecondFactorRequest>)->Result<Json<TokenResponse>,AuthError>{
+    let SecondFactorRequest { mfa_token, code, recovery_code } = body.into_inner();
+    let ip = req.peer_addr().map(|addr| addr.ip());
+    let email = mfa_challenge_email(&state.db, &mfa_token).await?;
+    if let Err(e) = state.login_limiter.check(&email, ip, Utc::now()).await {
+        state.login_limiter.refused(&state.db, &email, ip, &e).await?;
+        return Err(e);
+    }
+    let user_id = match complete_mfa_challenge(&state.db, &mfa_token, code.as_deref(), recovery_code.as_deref()).await {
+        Ok(user_id) => user_id,
+        Err(e @ AuthError::InvalidSecondFactor) => {
+            state.login_limiter.failed(&state.db, &email, ip, &e, Utc::now()).await?;
+            return Err(e);
+        }
+        Err(e) => return Err(e),
+    };
+    state.login_limiter.succeeded(&email).await?;
+    Ok(Json(open_session(&state.db, &state.keys, user_id, true).await?))
+}
+
+//starts over on every call until a code confirms it
+pub async fn enroll_totp(state:Data<AppState>,AuthUser(user_id):AuthUser,_:AuthSession)->Result<Json<TotpEnrollmentResponse>,AuthError>{
+    let user = state.db.get_user_by_id(user_id)
+        .await
+        .map_err(|e| AuthError::Internal(e.to_string()))?
+        .ok_or(AuthError::UnknownUser)?;
+    let secret = generate_totp_secret();
+    let pending = state.db.set_pending_totp(user_id, &secret)
+        .await
+        .map_err(|e| AuthError::Internal(e.to_string()))?;
+    if !pending {
+        return Err(AuthError::TotpAlreadyEnabled);
+    }
+    Ok(Json(TotpEnrollmentResponse { otpauth_uri: otpauth_uri(&user.email, &secret), secret }))
+}
+
+//a first code turns 2FA on and counts as the second factor of the current session, whose
+//tokens carry the claim from the next refresh on
+pub async fn confirm_totp(state:Data<AppState>,AuthUser(user_id):AuthUser,AuthSession(session_id):AuthSession,body:Json<TotpCodeRequest>)->Result<Json<RecoveryCodesResponse>,AuthError>{
+    let pending = state.db.get_totp(user_id)
+        .await
+        .map_err(|e| AuthError::Internal(e.to_string()))?
+        .filter(|c| c.confirmed_at.is_none())
+        .ok_or(AuthError::TotpNotPending)?;
+    let step = check_totp(&pending.secret, &body.code, Utc::now().timestamp()).ok_or(AuthError::InvalidSecondFactor)?;
+
+    let (recovery_codes, hashes) = new_recovery_codes();
+    let confirmed = state.db.confirm_totp(user_id, step, &hashes)
+        .await
+        .map_err(|e| AuthError::Internal(e.to_string()))?;
+    if !confirmed {
+        return Err(AuthError::TotpNotPending);
+    }
+    state.db.set_session_mfa(session_id, user_id)
+        .await
+        .map_err(|e| AuthError::Internal(e.to_string()))?;
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
+//needs a verified session and a fresh code, a stolen token alone cannot turn 2FA off
+pub async fn disable_totp(state:Data<AppState>,AuthUser(user_id):AuthUser,_:MfaVerified,body:Json<TotpCodeRequest>)->Result<HttpResponse,AuthError>{
+    verify_second_factor(&state.db, user_id, Some(&body.code), None).await?;
+    state.db.delete_totp(user_id)
+        .await
+        .map_err(|e| AuthError::Internal(e.to_string()))?;
+    Ok(HttpResponse::NoContent().finish())
+}
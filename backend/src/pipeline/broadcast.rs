@@ -0,0 +1,44 @@
+// Fan-out through a BroadcastRingBuffer instead of calling every sink on the dispatcher thread:
+// the dispatcher publishes into the ring and each sink runs on its own thread behind its own
+// consumer, in the order set up when the ring was built.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{BroadcastConsumer, BroadcastProducer, DispatcherHandle, EventSink, deliver, types::EventEnvelope};
+
+impl EventSink for BroadcastProducer<EventEnvelope> {
+    fn name(&self) -> &str {
+        "broadcast"
+    }
+
+    fn handle(&mut self, events: &[EventEnvelope]) -> Result<(), String> {
+        for envelope in events {
+            self.publish(envelope.clone());
+        }
+        Ok(())
+    }
+}
+
+pub fn spawn_consumer(consumer: BroadcastConsumer<EventEnvelope>, sink: impl EventSink + 'static) -> DispatcherHandle {
+    let name = format!("consumer-{}", consumer.name());
+    DispatcherHandle::spawn(&name, move |stop| run_consumer(consumer, sink, stop))
+}
+
+// returns once stop is set and everything published has been handled
+fn run_consumer(mut consumer: BroadcastConsumer<EventEnvelope>, mut sink: impl EventSink, stop: &AtomicBool) {
+    loop {
+        let stopping = stop.load(Ordering::Acquire);
+        let batch = consumer.peek_batch(256);
+        if batch.is_empty() {
+            //a consumer behind a dependency may see nothing yet while events are still pending
+            if stopping && consumer.is_caught_up() {
+                break;
+            }
+            consumer.wait_for_items();
+            continue;
+        }
+        deliver(&mut sink, &batch);
+        //only now may dependents see these events
+        consumer.commit(batch.len());
+    }
+}
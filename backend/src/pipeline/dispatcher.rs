@@ -0,0 +1,120 @@
+// Consumer side of the engine's event ring.
+//
+// A single thread drains the ring in batches and hands every batch to each sink in turn. A sink
+// that fails gets the same batch again after a backoff, so a slow or broken sink holds the
+// dispatcher back, the ring fills up and the engine waits (see MatchingEngine::emit_event).
+// Nothing is dropped on the way; sinks that must not apply an event twice dedupe on its seq.
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{RingBuffer, types::EventEnvelope};
+
+pub trait EventSink: Send {
+    fn name(&self) -> &str;
+
+    // the batch is in seq order, Err means nothing of it should be considered handled
+    fn handle(&mut self, events: &[EventEnvelope]) -> Result<(), String>;
+}
+
+pub struct EventDispatcher {
+    ring: Arc<RingBuffer<EventEnvelope>>,
+    sinks: Vec<Box<dyn EventSink>>,
+    batch_size: usize,
+    last_seq: Option<u64>,
+}
+
+impl EventDispatcher {
+    pub fn new(ring: Arc<RingBuffer<EventEnvelope>>) -> Self {
+        Self {
+            ring,
+            sinks: Vec::new(),
+            batch_size: 256,
+            last_seq: None,
+        }
+    }
+
+    pub fn with_sink(mut self, sink: impl EventSink + 'static) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn spawn(self) -> DispatcherHandle {
+        DispatcherHandle::spawn("event-dispatcher", move |stop| self.run(stop))
+    }
+
+    // returns once stop is set and the ring is empty
+    pub fn run(mut self, stop: &AtomicBool) {
+        loop {
+            //read before draining, so an empty drain after stop means everything published was seen
+            let stopping = stop.load(Ordering::Acquire);
+            let batch = self.ring.drain_batch(self.batch_size);
+            if batch.is_empty() {
+                if stopping {
+                    break;
+                }
+                //the ring's wait strategy decides between spinning and sleeping
+                self.ring.wait_for_items();
+                continue;
+            }
+            self.check_gaps(&batch);
+            for sink in self.sinks.iter_mut() {
+                deliver(sink.as_mut(), &batch);
+            }
+        }
+    }
+
+    fn check_gaps(&mut self, batch: &[EventEnvelope]) {
+        for envelope in batch {
+            if let Some(last) = self.last_seq
+                && envelope.seq != last + 1
+            {
+                eprintln!("[DISPATCHER] event seq jumped from {last} to {}", envelope.seq);
+            }
+            self.last_seq = Some(envelope.seq);
+        }
+    }
+}
+
+pub(crate) fn deliver(sink: &mut dyn EventSink, batch: &[EventEnvelope]) {
+    let mut backoff = Duration::from_millis(1);
+    while let Err(e) = sink.handle(batch) {
+        eprintln!("[DISPATCHER] sink {} failed, retrying in {backoff:?}: {e}", sink.name());
+        thread::sleep(backoff);
+        backoff = (backoff * 2).min(Duration::from_secs(1));
+    }
+}
+
+pub struct DispatcherHandle {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl DispatcherHandle {
+    pub(crate) fn spawn(name: &str, run: impl FnOnce(&AtomicBool) + Send + 'static) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
+        let thread = thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || run(&thread_stop))
+            .unwrap_or_else(|e| panic!("failed to spawn {name}: {e}"));
+        Self { stop, thread }
+    }
+
+    // stop the engine first, everything it already published is still delivered
+    pub fn shutdown(self) {
+        self.stop.store(true, Ordering::Release);
+        let _ = self.thread.join();
+    }
+}
@@ -0,0 +1,6 @@
+pub mod dispatcher;
+pub use dispatcher::*;
+pub mod sinks;
+pub use sinks::*;
+pub mod broadcast;
+pub use broadcast::*;
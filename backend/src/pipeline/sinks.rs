@@ -0,0 +1,139 @@
+use db::{Db, TradeRecord};
+use tokio::{runtime::{Builder, Runtime}, sync::broadcast};
+
+use crate::{EventSink, types::{Event, EventEnvelope, Side}};
+
+pub struct LoggingSink;
+
+impl EventSink for LoggingSink {
+    fn name(&self) -> &str {
+        "logger"
+    }
+
+    fn handle(&mut self, events: &[EventEnvelope]) -> Result<(), String> {
+        for envelope in events {
+            println!("[EVENT] #{} (cmd {}) {:?}", envelope.seq, envelope.command_seq, envelope.event);
+        }
+        Ok(())
+    }
+}
+
+// Public feed for market data subscribers. A subscriber that falls behind misses messages
+// (it sees RecvError::Lagged and can resync on seq), it never slows the engine down.
+pub struct MarketDataPublisher {
+    tx: broadcast::Sender<EventEnvelope>,
+}
+
+impl MarketDataPublisher {
+    pub fn new(capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity);
+        Self { tx }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<EventEnvelope> {
+        self.tx.subscribe()
+    }
+
+    // take this before the publisher moves onto its consumer thread, it is how anyone subscribes
+    // afterwards
+    pub fn feed(&self) -> MarketDataFeed {
+        MarketDataFeed { tx: self.tx.clone() }
+    }
+}
+
+// Where subscribers get their receivers once the publisher runs on its own thread. It can only
+// subscribe, publishing stays with the MarketDataPublisher.
+#[derive(Clone)]
+pub struct MarketDataFeed {
+    tx: broadcast::Sender<EventEnvelope>,
+}
+
+impl MarketDataFeed {
+    pub fn subscribe(&self) -> broadcast::Receiver<EventEnvelope> {
+        self.tx.subscribe()
+    }
+
+    pub fn subscribers(&self) -> usize {
+        self.tx.receiver_count()
+    }
+}
+
+impl EventSink for MarketDataPublisher {
+    fn name(&self) -> &str {
+        "market-data"
+    }
+
+    fn handle(&mut self, events: &[EventEnvelope]) -> Result<(), String> {
+        for envelope in events {
+            //rejections are only of interest to the user who sent the order
+            if matches!(envelope.event, Event::OrderRejected { .. }) {
+                continue;
+            }
+            //no subscribers is not an error
+            let _ = self.tx.send(envelope.clone());
+        }
+        Ok(())
+    }
+}
+
+// Writes fills to the trades table. Runs on its own broadcast consumer thread, see spawn_consumer,
+// with its own small runtime and pool opened on first use so the connections belong to that
+// thread; failures are retried by deliver and the insert ignores seqs already stored.
+#[derive(Default)]
+pub struct TradePersister {
+    conn: Option<(Runtime, Db)>,
+}
+
+impl TradePersister {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn conn(&mut self) -> anyhow::Result<&(Runtime, Db)> {
+        if self.conn.is_none() {
+            let runtime = Builder::new_current_thread().enable_all().build()?;
+            let db = runtime.block_on(Db::new())?;
+            self.conn = Some((runtime, db));
+        }
+        Ok(self.conn.as_ref().unwrap())
+    }
+}
+
+impl EventSink for TradePersister {
+    fn name(&self) -> &str {
+        "trade-persister"
+    }
+
+    fn handle(&mut self, events: &[EventEnvelope]) -> Result<(), String> {
+        let trades: Vec<TradeRecord> = events
+            .iter()
+            .filter_map(|envelope| match &envelope.event {
+                Event::Fill(trade) => Some(TradeRecord {
+                    seq: envelope.seq as i64,
+                    command_seq: envelope.command_seq as i64,
+                    fill_seq: trade.seq_no as i64,
+                    maker_order_id: trade.maker_order_id,
+                    taker_order_id: trade.taker_order_id,
+                    maker_user_id: trade.maker_user_id,
+                    taker_user_id: trade.taker_user_id,
+                    maker_side: match trade.maker_side {
+                        Side::Buy => "buy".to_string(),
+                        Side::Sell => "sell".to_string(),
+                    },
+                    price: trade.price,
+                    quantity: trade.quantity,
+                    executed_at_ns: trade.timestamp as i64,
+                }),
+                _ => None,
+            })
+            .collect();
+        if trades.is_empty() {
+            return Ok(());
+        }
+        let (runtime, db) = self.conn().map_err(|e| e.to_string())?;
+        runtime
+            .block_on(db.insert_trades(&trades))
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,43 @@
+use actix_web::web;
+
+use crate::{ApiKeyMiddleware, JwtMiddleware, OrderRateLimit, RequireRole, RequireScope, models::*, types::{ApiScope, Role}};
+
+// every route of the api, main and the integration tests build their App from this
+pub fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/signup").route(web::post().to(signup)))
+        .service(web::resource("/signin").route(web::post().to(signin)))
+        .service(web::resource("/signin/2fa").route(web::post().to(signin_second_factor)))
+        .service(web::resource("/token/refresh").route(web::post().to(refresh)))
+        .service(web::resource("/.well-known/jwks.json").route(web::get().to(jwks)))
+        //everything below acts for the user in the token or the signed API key, the empty scope
+        //matches every path so it has to stay last. Session and key management need a session,
+        //API keys only reach the routes with a RequireScope. RequireRole applies to both, OrderRateLimit
+        //goes innermost so refused requests cost nothing. place_batch charges for itself, one per
+        //command it sends, which is only known once it has parsed and checked the items
+        .service(
+            web::scope("")
+                .wrap(JwtMiddleware)
+                .wrap(ApiKeyMiddleware)
+                .service(web::resource("/me").wrap(RequireScope(ApiScope::Read)).route(web::get().to(me_handler)))
+                .service(web::resource("/logout").route(web::post().to(logout)))
+                .service(web::resource("/sessions").route(web::get().to(list_sessions)))
+                .service(web::resource("/sessions/{id}").route(web::delete().to(revoke_session)))
+                .service(web::resource("/2fa/totp").route(web::post().to(enroll_totp)).route(web::delete().to(disable_totp)))
+                .service(web::resource("/2fa/totp/confirm").route(web::post().to(confirm_totp)))
+                .service(web::resource("/api_keys").route(web::get().to(list_api_keys)).route(web::post().to(create_api_key)))
+                .service(web::resource("/api_keys/{id}").route(web::delete().to(revoke_api_key)))
+                .service(web::resource("/place_order").wrap(OrderRateLimit).wrap(RequireScope(ApiScope::Trade)).wrap(RequireRole(Role::TRADING)).route(web::post().to(place_order)))
+                .service(web::resource("/cancel_order").wrap(OrderRateLimit).wrap(RequireScope(ApiScope::Trade)).wrap(RequireRole(Role::TRADING)).route(web::post().to(cancel_order)))
+                .service(web::resource("/batch_order").wrap(RequireScope(ApiScope::Trade)).wrap(RequireRole(Role::TRADING)).route(web::post().to(place_batch)))
+                .service(
+                    web::scope("/admin")
+                        .wrap(RequireRole(Role::ADMIN))
+                        .service(web::resource("/engine/event_ring").wrap(RequireScope(ApiScope::Read)).route(web::get().to(event_ring_stats)))
+                        .service(web::resource("/market/halt").route(web::post().to(halt_market)))
+                        .service(web::resource("/market/resume").route(web::post().to(resume_market)))
+                        .service(web::resource("/risk_limits").route(web::put().to(set_risk_limits)))
+                        .service(web::resource("/users/{id}/cancel_orders").route(web::post().to(force_cancel)))
+                        .service(web::resource("/users/{id}/role").route(web::put().to(set_user_role))),
+                ),
+        );
+}
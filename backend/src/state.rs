@@ -1,9 +1,17 @@
 use db::Db;
-use std::sync::mpsc;
+use std::sync::{Arc, mpsc};
 
-use crate::types::OrderBookMessage;
+use crate::{ApiKeyCipher, Instrument, KeySet, LoginLimiter, MarketDataFeed, NonceCache, OrderRateLimiter, RingBuffer, types::{EventEnvelope, OrderBookMessage}};
 
 pub struct AppState{
     pub book_tx : mpsc::SyncSender<OrderBookMessage>,
-    pub db: Db
-}
\ No newline at end of file
+    pub db: Db,
+    pub instrument: Instrument,
+    pub event_ring: Arc<RingBuffer<EventEnvelope>>,
+    pub keys: Arc<KeySet>, //loaded once at startup, see KeySet::from_env
+    pub api_key_cipher: Arc<ApiKeyCipher>, //loaded once at startup, see ApiKeyCipher::from_env
+    pub nonces: Arc<NonceCache>, //shared by all workers, a nonce must not be replayable against another one
+    pub login_limiter: Arc<LoginLimiter>, //shared for the same reason, see LoginLimiter::from_env
+    pub order_limiter: Arc<OrderRateLimiter>, //one bucket per user across workers, see OrderRateLimiter::from_env
+    pub market_data: MarketDataFeed //subscribe here for the public event feed
+}
@@ -0,0 +1,48 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{OrderId, Quantity, UserId, types::DEFAULT_MAX_OPEN_ORDERS};
+
+//the reason is what traders get back with their rejected orders
+#[derive(Serialize,Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HaltRequest{
+    pub reason: String
+}
+
+//replaces the current limits, leaving out max_order_quantity removes that cap. max_open_orders
+//is only removed by an explicit null, left out it is the default cap as for clients that predate it
+#[derive(Serialize,Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RiskLimitsRequest{
+    pub max_leverage: u32,
+    #[serde(default)]
+    pub max_order_quantity: Option<f64>,
+    #[serde(default, deserialize_with = "present")]
+    pub max_open_orders: Option<Option<u32>>
+}
+
+impl RiskLimitsRequest{
+    pub fn max_open_orders(&self) -> Option<u32> {
+        self.max_open_orders.unwrap_or(Some(DEFAULT_MAX_OPEN_ORDERS))
+    }
+}
+
+//a field that is there, even as null, is Some so it can be told apart from a missing one
+fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Option<u32>>, D::Error> {
+    Option::deserialize(deserializer).map(Some)
+}
+
+#[derive(Serialize,Deserialize)]
+pub struct MarketStatusResponse{
+    pub halted: Option<String>, //the halt reason, None while trading
+    pub max_leverage: Decimal,
+    pub max_order_quantity: Option<Quantity>,
+    pub max_open_orders: Option<u32>
+}
+
+#[derive(Serialize,Deserialize)]
+pub struct ForceCancelResponse{
+    pub user_id: UserId,
+    pub cancelled: Vec<OrderId>
+}
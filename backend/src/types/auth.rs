@@ -1,3 +1,6 @@
+use std::{net::IpAddr, str::FromStr};
+
+use chrono::{DateTime, Utc};
 use serde::{Deserialize,Serialize};
 use uuid::Uuid;
 
@@ -11,7 +14,181 @@ pub struct UserRequest{
 pub struct UserResponse{
     pub id : Uuid
 }
+
+//what signin and /token/refresh hand out: a short lived access token and the refresh token that replaces it
+#[derive(Serialize,Deserialize)]
+pub struct TokenResponse{
+    pub token: String,
+    pub refresh_token: String,
+    pub expires_in: i64 //seconds the access token is valid
+}
+
+//a user with 2FA gets a challenge from /signin and trades it for tokens at /signin/2fa
+#[derive(Serialize,Deserialize)]
+pub struct MfaChallengeResponse{
+    pub mfa_token: String,
+    pub expires_in: i64
+}
+
+#[derive(Serialize,Deserialize)]
+#[serde(untagged)]
+pub enum SigninResponse{
+    Tokens(TokenResponse),
+    MfaRequired(MfaChallengeResponse)
+}
+
+//one of code and recovery_code
+#[derive(Serialize,Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SecondFactorRequest{
+    pub mfa_token: String,
+    #[serde(default)]
+    pub code: Option<String>,
+    #[serde(default)]
+    pub recovery_code: Option<String>
+}
+
+#[derive(Serialize,Deserialize)]
+pub struct TotpEnrollmentResponse{
+    pub secret: String,
+    pub otpauth_uri: String
+}
+
+#[derive(Serialize,Deserialize)]
+pub struct TotpCodeRequest{
+    pub code: String
+}
+
+//shown once, only hashes are kept
+#[derive(Serialize,Deserialize)]
+pub struct RecoveryCodesResponse{
+    pub recovery_codes: Vec<String>
+}
+
+#[derive(Serialize,Deserialize)]
+pub struct RefreshRequest{
+    pub refresh_token: String
+}
+
+#[derive(Serialize)]
+pub struct SessionResponse{
+    #[serde(flatten)]
+    pub session: db::Session,
+    pub current: bool //the session of the token that asked
+}
+
+//what an API key may be used for, a signed-in user can do everything
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiScope {
+    Read,
+    Trade,
+    Withdraw,
+}
+
+impl ApiScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::Read => "read",
+            ApiScope::Trade => "trade",
+            ApiScope::Withdraw => "withdraw",
+        }
+    }
+}
+
+impl FromStr for ApiScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(ApiScope::Read),
+            "trade" => Ok(ApiScope::Trade),
+            "withdraw" => Ok(ApiScope::Withdraw),
+            other => Err(format!("unknown scope {other}")),
+        }
+    }
+}
+
+//what a user may do, stored on the user and carried in the access token. API keys act with the
+//role of their owner
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    #[default]
+    Trader,
+    MarketMaker,
+    Admin,
+    Auditor, //reads everything a trader can, places nothing
+}
+
+impl Role {
+    pub const TRADING: &'static [Role] = &[Role::Trader, Role::MarketMaker];
+    pub const ADMIN: &'static [Role] = &[Role::Admin];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Trader => "trader",
+            Role::MarketMaker => "market_maker",
+            Role::Admin => "admin",
+            Role::Auditor => "auditor",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "trader" => Ok(Role::Trader),
+            "market_maker" => Ok(Role::MarketMaker),
+            "admin" => Ok(Role::Admin),
+            "auditor" => Ok(Role::Auditor),
+            other => Err(format!("unknown role {other}")),
+        }
+    }
+}
+
+#[derive(Serialize,Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoleRequest{
+    pub role: Role
+}
+
+#[derive(Serialize,Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKeyRequest{
+    pub label: String,
+    pub scopes: Vec<ApiScope>,
+    #[serde(default)]
+    pub allowed_ips: Vec<IpAddr> //empty allows any address
+}
+
+#[derive(Serialize,Deserialize)]
+pub struct ApiKeyResponse{
+    pub id: Uuid,
+    pub label: String,
+    pub scopes: Vec<ApiScope>,
+    pub allowed_ips: Vec<IpAddr>,
+    pub created_at: DateTime<Utc>
+}
+
+impl From<db::ApiKey> for ApiKeyResponse {
+    fn from(key: db::ApiKey) -> Self {
+        ApiKeyResponse {
+            id: key.id,
+            scopes: key.scopes.iter().filter_map(|s| s.parse().ok()).collect(),
+            allowed_ips: key.allowed_ips.iter().filter_map(|ip| ip.parse().ok()).collect(),
+            label: key.label,
+            created_at: key.created_at,
+        }
+    }
+}
+
+//the secret is only ever shown here, it cannot be looked up again
 #[derive(Serialize,Deserialize)]
-pub struct SinginResponse{
-    pub token: String
+pub struct NewApiKeyResponse{
+    #[serde(flatten)]
+    pub key: ApiKeyResponse,
+    pub secret: String
 }
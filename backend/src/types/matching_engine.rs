@@ -1,6 +1,37 @@
-use crate::{Fill, OrderId, Price, Quantity, UserId, types::Side};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone)]
+use crate::{Fill, Instrument, Lots, OrderId, Price, Quantity, UserId, types::Side};
+
+//the most leverage the engine ever accepts, admins can only lower the limit below it
+pub const MAX_LEVERAGE: Decimal = dec!(125);
+
+//resting orders one user may have unless an admin sets another limit
+pub const DEFAULT_MAX_OPEN_ORDERS: u32 = 500;
+
+//per order checks on top of the instrument's, set by admins through SetRiskLimits
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RiskLimits {
+    pub max_leverage: Decimal,
+    pub max_order_lots: Option<Lots>, //None leaves order size uncapped
+    //limit orders are refused while the user has this many resting, None leaves it uncapped.
+    //Journal entries and snapshots from before the field get the default
+    #[serde(default = "default_max_open_orders")]
+    pub max_open_orders: Option<u32>,
+}
+
+impl Default for RiskLimits {
+    fn default() -> Self {
+        Self { max_leverage: MAX_LEVERAGE, max_order_lots: None, max_open_orders: default_max_open_orders() }
+    }
+}
+
+fn default_max_open_orders() -> Option<u32> {
+    Some(DEFAULT_MAX_OPEN_ORDERS)
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Event {
     OrderPlaced {
         order_id : OrderId,
@@ -10,7 +41,7 @@ pub enum Event {
         quantity : Quantity,
         timestamp : u128 
     },
-    Fill(Fill),
+    Fill(Trade),
     OrderCancelled {
         order_id : OrderId,
         user_id : UserId,
@@ -22,4 +53,49 @@ pub enum Event {
         reason : String,
         timestamp : u128
     }
-}
\ No newline at end of file
+}
+
+//what actually goes on the ring: seq is the engine wide event sequence (no gaps, survives restarts),
+//command_seq the journal seq of the command that produced the event
+#[derive(Clone, Debug, PartialEq)]
+pub struct EventEnvelope {
+    pub seq : u64,
+    pub command_seq : u64,
+    pub event : Event
+}
+
+//a book Fill converted back from ticks/lots for consumers of the event stream
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Trade {
+    pub seq_no : u64,
+    pub maker_order_id : OrderId,
+    pub taker_order_id : OrderId,
+    pub maker_user_id : UserId,
+    pub taker_user_id : UserId,
+    pub price : Price,
+    pub quantity : Quantity,
+    pub taker_leverage : Decimal,
+    pub maker_leverage : Decimal,
+    pub maker_side : Side,
+    pub taker_side : Side,
+    pub timestamp : u128
+}
+
+impl Trade {
+    pub fn new(fill: &Fill, instrument: &Instrument) -> Self {
+        Self {
+            seq_no: fill.seq_no,
+            maker_order_id: fill.maker_order_id,
+            taker_order_id: fill.taker_order_id,
+            maker_user_id: fill.maker_user_id,
+            taker_user_id: fill.taker_user_id,
+            price: instrument.price(fill.price),
+            quantity: instrument.quantity(fill.quantity),
+            taker_leverage: fill.taker_leverage,
+            maker_leverage: fill.maker_leverage,
+            maker_side: fill.maker_side,
+            taker_side: fill.taker_side,
+            timestamp: fill.timestamp_,
+        }
+    }
+}
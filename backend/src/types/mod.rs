@@ -4,3 +4,5 @@ pub mod order;
 pub use order::*;
 pub mod matching_engine;
 pub use matching_engine::*;
+pub mod admin;
+pub use admin::*;
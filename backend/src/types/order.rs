@@ -1,27 +1,58 @@
 pub use serde::{Serialize,Deserialize};
 use tokio::sync::oneshot;
-use uuid::Uuid;
 use std::fmt;
 
 
-use crate::{Order, OrderId, Price, Quantity, UserId};
+use crate::{Order, OrderId, Price, Quantity, UserId, types::RiskLimits};
 
+//the user always comes from the token, a body that names one is refused
 #[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct OrderRequest {
     #[serde(rename = "type")]
     pub type_: OrderType,
-    pub user_id : Uuid,
     pub side: Side,
     pub quantity: f64,
     pub price: Option<f64>,
     pub leverage: u32,
 }
 #[derive(Deserialize,Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct CanceledOrderRequest{
-    pub user_id : UserId,
     pub order_id : OrderId
 }
 
+pub const MAX_BATCH_ORDERS: usize = 100;
+
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum BatchOrderItem {
+    Place(OrderRequest),
+    Cancel(CanceledOrderRequest),
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchOrderRequest {
+    pub orders: Vec<BatchOrderItem>,
+}
+
+//one per request item, in request order; error is set when that item was rejected
+#[derive(Deserialize, Serialize, Default)]
+pub struct BatchOrderResult {
+    pub index: usize,
+    pub order_id: Option<OrderId>,
+    pub status: Option<String>,
+    pub filled: Option<Quantity>,
+    pub remaining: Option<Quantity>,
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct BatchOrderResponse {
+    pub results: Vec<BatchOrderResult>,
+}
+
 #[derive(Deserialize, Serialize,PartialEq,Clone,Copy)]
 #[serde(rename_all = "lowercase")]
 pub enum OrderType {
@@ -29,7 +60,7 @@ pub enum OrderType {
     Limit,
 }
 
-#[derive(Deserialize, Serialize,Clone, Copy)]
+#[derive(Deserialize, Serialize,Clone, Copy,PartialEq,Eq,Debug)]
 #[serde(rename_all = "lowercase")]
 pub enum Side {
     Buy,
@@ -43,7 +74,7 @@ pub struct Response {
 }
 
 #[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum Priority {
     Critical = 0,
     High     = 1,
@@ -88,23 +119,62 @@ pub enum OrderResponse{
     },
     Message{
         message : String
+    },
+    //answer to the admin commands that change or read how the market trades
+    MarketStatus{
+        halted : Option<String>,
+        limits : RiskLimits
+    },
+    CancelledAll{
+        user_id : UserId,
+        order_ids : Vec<OrderId>
     }
 }
+//journaled as is, responders only live for the current process so they are skipped
+#[derive(Serialize, Deserialize)]
 pub enum OrderBookMessage {
     PlaceOrder {
         order: Order,
         priority: Priority,  //configurable
+        #[serde(skip)]
         responder: Option<oneshot::Sender<Result<OrderResponse, String>>>,
     },
     //prioruty for all message is fixed
     CancelOrder {
         order_id: OrderId,
         user_id: UserId,
+        #[serde(skip)]
         responder: Option<oneshot::Sender<Result<OrderResponse, String>>>,
     },
     UpdateMarkPrice {
         price: Price,
     },
+    //admin commands. While halted new orders are rejected, cancels still go through
+    HaltMarket {
+        reason: String,
+        #[serde(skip)]
+        responder: Option<oneshot::Sender<Result<OrderResponse, String>>>,
+    },
+    ResumeMarket {
+        #[serde(skip)]
+        responder: Option<oneshot::Sender<Result<OrderResponse, String>>>,
+    },
+    //cancels every resting order of the user
+    ForceCancel {
+        user_id: UserId,
+        #[serde(skip)]
+        responder: Option<oneshot::Sender<Result<OrderResponse, String>>>,
+    },
+    SetRiskLimits {
+        limits: RiskLimits,
+        #[serde(skip)]
+        responder: Option<oneshot::Sender<Result<OrderResponse, String>>>,
+    },
+    //submitted as one unit, the engine runs these back to back in this order within one batch.
+    //never journaled itself, the engine journals the commands inside
+    Batch {
+        commands: Vec<OrderBookMessage>,
+    },
 }
 
 impl OrderBookMessage {
@@ -113,6 +183,27 @@ impl OrderBookMessage {
             OrderBookMessage::PlaceOrder { priority, .. } => *priority,
             OrderBookMessage::CancelOrder { .. } => Priority::Critical,
             OrderBookMessage::UpdateMarkPrice { .. } => Priority::Critical,
+            //a halt should not wait behind the orders it is meant to stop
+            OrderBookMessage::HaltMarket { .. } | OrderBookMessage::ResumeMarket { .. } => Priority::Critical,
+            OrderBookMessage::ForceCancel { .. } | OrderBookMessage::SetRiskLimits { .. } => Priority::Critical,
+            //a batch queues like the orders it places, a cancel riding along must not lift them
+            //ahead of other users' earlier orders
+            OrderBookMessage::Batch { commands } => commands
+                .iter()
+                .filter_map(|cmd| match cmd {
+                    OrderBookMessage::PlaceOrder { priority, .. } => Some(*priority),
+                    _ => None,
+                })
+                .max()
+                .unwrap_or(Priority::Normal),
+        }
+    }
+
+    //a Batch turned into the commands it carries, anything else as it is
+    pub fn flatten(self) -> Vec<OrderBookMessage> {
+        match self {
+            OrderBookMessage::Batch { commands } => commands.into_iter().flat_map(OrderBookMessage::flatten).collect(),
+            cmd => vec![cmd],
         }
     }
 }
@@ -0,0 +1,253 @@
+// API key management and HMAC signed requests through the real routes and database.
+
+mod common;
+
+use std::sync::Arc;
+
+use actix_web::{App, dev::ServiceResponse, http::{Method, StatusCode}, test, web};
+use backend::{
+    API_KEY_HEADER, API_NONCE_HEADER, API_SIGNATURE_HEADER, API_TIMESTAMP_HEADER, ApiKeyCipher, NonceCache, SIGNATURE_WINDOW_MS, routes, sign_request, totp_code, totp_step,
+    state::AppState, types::{ApiKeyResponse, NewApiKeyResponse, Role, TokenResponse, TotpEnrollmentResponse},
+};
+use chrono::Utc;
+use serde_json::{Value, json};
+use uuid::Uuid;
+
+async fn state() -> web::Data<AppState> {
+    state_with(ApiKeyCipher::generate()).await
+}
+
+async fn state_with(api_key_cipher: ApiKeyCipher) -> web::Data<AppState> {
+    web::Data::new(AppState { api_key_cipher: Arc::new(api_key_cipher), ..common::state(common::database().await) })
+}
+
+async fn forget(state: &AppState, email: &str) {
+    sqlx::query("DELETE FROM users WHERE email = $1").bind(email).execute(&state.db.pool).await.unwrap();
+}
+
+fn signed_at(method: Method, uri: &str, key_id: Uuid, secret: &str, body: &str, timestamp: i64, nonce: &str) -> test::TestRequest {
+    let signature = sign_request(secret, timestamp, nonce, method.as_str(), uri, body.as_bytes());
+    test::TestRequest::default()
+        .method(method)
+        .uri(uri)
+        .insert_header((API_KEY_HEADER, key_id.to_string()))
+        .insert_header((API_TIMESTAMP_HEADER, timestamp.to_string()))
+        .insert_header((API_NONCE_HEADER, nonce))
+        .insert_header((API_SIGNATURE_HEADER, signature))
+        .insert_header(("Content-Type", "application/json"))
+        .set_payload(body.to_string())
+}
+
+fn signed(method: Method, uri: &str, key: &NewApiKeyResponse, body: &str) -> test::TestRequest {
+    signed_at(method, uri, key.key.id, &key.secret, body, Utc::now().timestamp_millis(), &Uuid::new_v4().to_string())
+}
+
+macro_rules! app {
+    ($state:expr) => {
+        test::init_service(App::new().app_data($state.clone()).configure(routes)).await
+    };
+}
+
+macro_rules! call {
+    ($app:expr, $req:expr) => {{
+        let res: ServiceResponse = test::call_service(&$app, $req.to_request()).await;
+        res
+    }};
+}
+
+// a fresh user with 2FA turned on, returns its email and a 2FA verified access token
+macro_rules! user {
+    ($app:expr) => {{
+        let email = format!("{}@api-keys.test", Uuid::new_v4());
+        let credentials = json!({ "email": email, "password": "correct horse" });
+        assert_eq!(call!($app, test::TestRequest::post().uri("/signup").set_json(&credentials)).status(), StatusCode::OK);
+        let res = call!($app, test::TestRequest::post().uri("/signin").set_json(&credentials));
+        let tokens: TokenResponse = test::read_body_json(res).await;
+        let bearer = format!("Bearer {}", tokens.token);
+
+        let res = call!($app, test::TestRequest::post().uri("/2fa/totp").insert_header(("Authorization", bearer.as_str())));
+        let enrollment: TotpEnrollmentResponse = test::read_body_json(res).await;
+        let code = totp_code(&enrollment.secret, totp_step(Utc::now().timestamp())).unwrap();
+        let confirm = test::TestRequest::post().uri("/2fa/totp/confirm").insert_header(("Authorization", bearer.as_str())).set_json(json!({ "code": code }));
+        assert_eq!(call!($app, confirm).status(), StatusCode::OK);
+        let res = call!($app, test::TestRequest::post().uri("/token/refresh").set_json(json!({ "refresh_token": tokens.refresh_token })));
+        let tokens: TokenResponse = test::read_body_json(res).await;
+        (email, format!("Bearer {}", tokens.token))
+    }};
+}
+
+macro_rules! new_key {
+    ($app:expr, $bearer:expr, $body:expr) => {{
+        let res = call!($app, test::TestRequest::post().uri("/api_keys").insert_header(("Authorization", $bearer.as_str())).set_json($body));
+        assert_eq!(res.status(), StatusCode::OK);
+        let key: NewApiKeyResponse = test::read_body_json(res).await;
+        key
+    }};
+}
+
+const ORDER: &str = r#"{"type":"limit","side":"buy","quantity":1,"price":100,"leverage":1}"#;
+
+#[actix_web::test]
+async fn keys_are_created_listed_and_revoked() {
+    let state = state().await;
+    let app = app!(state);
+    let (email, bearer) = user!(app);
+
+    let key = new_key!(app, bearer, json!({ "label": "market maker", "scopes": ["read", "trade", "read"] }));
+    assert_eq!(key.secret.len(), 64);
+    assert_eq!(key.key.scopes.len(), 2);
+
+    let res = call!(app, test::TestRequest::get().uri("/api_keys").insert_header(("Authorization", bearer.as_str())));
+    let listed: Vec<Value> = test::read_body_json(res).await;
+    assert_eq!(listed.len(), 1);
+    assert!(listed[0].get("secret").is_none());
+    let stored = state.db.get_api_key(key.key.id).await.unwrap().unwrap();
+    assert!(!stored.secret_encrypted.contains(&key.secret));
+    assert_eq!(state.api_key_cipher.decrypt(key.key.id, &stored.secret_encrypted), Some(key.secret.clone()));
+
+    let res = call!(app, signed(Method::GET, "/me", &key, ""));
+    assert_eq!(res.status(), StatusCode::OK);
+    let user = state.db.get_user(&email).await.unwrap().unwrap();
+    assert_eq!(test::read_body(res).await, format!("User = {}", user.id));
+
+    let uri = format!("/api_keys/{}", key.key.id);
+    assert_eq!(call!(app, test::TestRequest::delete().uri(&uri).insert_header(("Authorization", bearer.as_str()))).status(), StatusCode::NO_CONTENT);
+    assert_eq!(call!(app, signed(Method::GET, "/me", &key, "")).status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(call!(app, test::TestRequest::delete().uri(&uri).insert_header(("Authorization", bearer.as_str()))).status(), StatusCode::NOT_FOUND);
+    let res = call!(app, test::TestRequest::get().uri("/api_keys").insert_header(("Authorization", bearer.as_str())));
+    let listed: Vec<ApiKeyResponse> = test::read_body_json(res).await;
+    assert!(listed.is_empty());
+    forget(&state, &email).await;
+}
+
+#[actix_web::test]
+async fn tampered_stale_and_replayed_requests_are_refused() {
+    let state = state().await;
+    let app = app!(state);
+    let (email, bearer) = user!(app);
+    let key = new_key!(app, bearer, json!({ "label": "bot", "scopes": ["read"] }));
+
+    // signed for another body
+    let tampered = signed(Method::GET, "/me", &key, "{}").set_payload("{ }");
+    assert_eq!(call!(app, tampered).status(), StatusCode::UNAUTHORIZED);
+    // signed for another path
+    let moved = signed(Method::GET, "/sessions", &key, "").uri("/me");
+    assert_eq!(call!(app, moved).status(), StatusCode::UNAUTHORIZED);
+    let now = Utc::now().timestamp_millis();
+    let forged = signed_at(Method::GET, "/me", key.key.id, &"0".repeat(64), "", now, "n-0");
+    assert_eq!(call!(app, forged).status(), StatusCode::UNAUTHORIZED);
+
+    let stale = now - SIGNATURE_WINDOW_MS - 1_000;
+    assert_eq!(call!(app, signed_at(Method::GET, "/me", key.key.id, &key.secret, "", stale, "n-1")).status(), StatusCode::UNAUTHORIZED);
+
+    assert_eq!(call!(app, signed_at(Method::GET, "/me", key.key.id, &key.secret, "", now, "n-2")).status(), StatusCode::OK);
+    let replay = call!(app, signed_at(Method::GET, "/me", key.key.id, &key.secret, "", now, "n-2"));
+    assert_eq!(replay.status(), StatusCode::UNAUTHORIZED);
+    let body: Value = test::read_body_json(replay).await;
+    assert_eq!(body["error"], "Nonce already used");
+    forget(&state, &email).await;
+}
+
+#[actix_web::test]
+async fn keys_only_reach_the_routes_of_their_scopes() {
+    let state = state().await;
+    let app = app!(state);
+    let (email, bearer) = user!(app);
+    let reader = new_key!(app, bearer, json!({ "label": "dashboard", "scopes": ["read"] }));
+    let trader = new_key!(app, bearer, json!({ "label": "bot", "scopes": ["trade"] }));
+
+    assert_eq!(call!(app, signed(Method::GET, "/me", &reader, "")).status(), StatusCode::OK);
+    assert_eq!(call!(app, signed(Method::POST, "/place_order", &reader, ORDER)).status(), StatusCode::FORBIDDEN);
+    assert_eq!(call!(app, signed(Method::GET, "/me", &trader, "")).status(), StatusCode::FORBIDDEN);
+    // past authentication the handler still gets the body, and finds no engine behind it
+    assert_eq!(call!(app, signed(Method::POST, "/place_order", &trader, ORDER)).status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    // keys cannot manage keys or sessions
+    let everything = new_key!(app, bearer, json!({ "label": "all", "scopes": ["read", "trade", "withdraw"] }));
+    let body = json!({ "label": "escalated", "scopes": ["withdraw"] }).to_string();
+    assert_eq!(call!(app, signed(Method::POST, "/api_keys", &everything, &body)).status(), StatusCode::FORBIDDEN);
+    assert_eq!(call!(app, signed(Method::GET, "/api_keys", &everything, "")).status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(call!(app, signed(Method::GET, "/sessions", &everything, "")).status(), StatusCode::UNAUTHORIZED);
+    forget(&state, &email).await;
+}
+
+#[actix_web::test]
+async fn admin_keys_need_the_read_scope_for_the_event_ring() {
+    let state = state().await;
+    let app = app!(state);
+    let (email, bearer) = user!(app);
+    let user = state.db.get_user(&email).await.unwrap().unwrap();
+    assert!(state.db.set_user_role(user.id, Role::Admin.as_str()).await.unwrap());
+    let reader = new_key!(app, bearer, json!({ "label": "dashboard", "scopes": ["read"] }));
+    let trader = new_key!(app, bearer, json!({ "label": "bot", "scopes": ["trade"] }));
+
+    assert_eq!(call!(app, signed(Method::GET, "/admin/engine/event_ring", &trader, "")).status(), StatusCode::FORBIDDEN);
+    assert_eq!(call!(app, signed(Method::GET, "/admin/engine/event_ring", &reader, "")).status(), StatusCode::OK);
+    forget(&state, &email).await;
+}
+
+#[actix_web::test]
+async fn allowlisted_keys_only_work_from_their_addresses() {
+    let state = state().await;
+    let app = app!(state);
+    let (email, bearer) = user!(app);
+    let key = new_key!(app, bearer, json!({ "label": "colo", "scopes": ["read"], "allowed_ips": ["10.0.0.1", "::1"] }));
+    assert_eq!(key.key.allowed_ips.len(), 2);
+
+    let from = |addr: &str| signed(Method::GET, "/me", &key, "").peer_addr(addr.parse().unwrap());
+    assert_eq!(call!(app, from("10.0.0.1:4000")).status(), StatusCode::OK);
+    assert_eq!(call!(app, from("[::1]:4000")).status(), StatusCode::OK);
+    assert_eq!(call!(app, from("10.0.0.2:4000")).status(), StatusCode::FORBIDDEN);
+    // a forwarded header does not count
+    let spoofed = from("10.0.0.2:4000").insert_header(("X-Forwarded-For", "10.0.0.1"));
+    assert_eq!(call!(app, spoofed).status(), StatusCode::FORBIDDEN);
+
+    let res = call!(app, test::TestRequest::post().uri("/api_keys").insert_header(("Authorization", bearer.as_str())).set_json(json!({ "label": "x", "scopes": ["read"], "allowed_ips": ["not an ip"] })));
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let res = call!(app, test::TestRequest::post().uri("/api_keys").insert_header(("Authorization", bearer.as_str())).set_json(json!({ "label": "x", "scopes": [] })));
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    forget(&state, &email).await;
+}
+
+#[actix_web::test]
+async fn nonces_are_forgotten_once_they_could_not_pass_the_window() {
+    let cache = NonceCache::new();
+    let key = Uuid::new_v4();
+    assert!(cache.insert(key, "a", 0));
+    assert!(!cache.insert(key, "a", SIGNATURE_WINDOW_MS));
+    // per key
+    assert!(cache.insert(Uuid::new_v4(), "a", SIGNATURE_WINDOW_MS));
+    assert!(cache.insert(key, "a", 2 * SIGNATURE_WINDOW_MS));
+    assert_eq!(cache.len(), 2);
+}
+
+#[actix_web::test]
+async fn secrets_only_open_with_a_server_key_and_for_their_own_key_id() {
+    let (old, new) = ([1u8; 32], [2u8; 32]);
+    let key_id = Uuid::new_v4();
+    let sealed = ApiKeyCipher::new(&old, &[]).encrypt(key_id, "secret");
+    assert_ne!(ApiKeyCipher::new(&old, &[]).encrypt(key_id, "secret"), sealed);
+
+    // after a rotation the old key still opens what it sealed
+    let rotated = ApiKeyCipher::new(&new, &[old]);
+    assert_eq!(rotated.decrypt(key_id, &sealed).as_deref(), Some("secret"));
+    assert_eq!(ApiKeyCipher::new(&new, &[]).decrypt(key_id, &sealed), None);
+    // copied onto another key's row
+    assert_eq!(rotated.decrypt(Uuid::new_v4(), &sealed), None);
+    assert_eq!(rotated.decrypt(key_id, "not hex"), None);
+}
+
+#[actix_web::test]
+async fn keys_stop_working_under_another_server_key() {
+    let state = state().await;
+    let app = app!(state);
+    let (email, bearer) = user!(app);
+    let key = new_key!(app, bearer, json!({ "label": "bot", "scopes": ["read"] }));
+
+    let other = state_with(ApiKeyCipher::generate()).await;
+    let other_app = app!(other);
+    assert_eq!(call!(other_app, signed(Method::GET, "/me", &key, "")).status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(call!(app, signed(Method::GET, "/me", &key, "")).status(), StatusCode::OK);
+
+    forget(&state, &email).await;
+}
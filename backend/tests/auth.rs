@@ -0,0 +1,77 @@
+mod common;
+
+use actix_web::{App, HttpResponse, http::StatusCode, test, web};
+use backend::{AuthUser, JwtMiddleware, KeySet, create_jwt, state::AppState, types::{BatchOrderRequest, CanceledOrderRequest, OrderRequest, Role}};
+use db::Db;
+use sqlx::postgres::PgPoolOptions;
+use uuid::Uuid;
+
+// the middleware only needs the keys, the pool never connects
+fn state() -> web::Data<AppState> {
+    web::Data::new(common::state(Db { pool: PgPoolOptions::new().connect_lazy("postgres://localhost/unused").unwrap() }))
+}
+
+async fn whoami(user: AuthUser) -> HttpResponse {
+    HttpResponse::Ok().body(user.0.to_string())
+}
+
+macro_rules! app {
+    ($state:expr) => {
+        test::init_service(
+            App::new()
+                .app_data($state.clone())
+                .service(web::resource("/open").route(web::get().to(whoami)))
+                .service(web::scope("").wrap(JwtMiddleware).service(web::resource("/whoami").route(web::get().to(whoami)))),
+        )
+        .await
+    };
+}
+
+#[actix_web::test]
+async fn token_subject_is_the_authenticated_user() {
+    let state = state();
+    let app = app!(state);
+    let user = Uuid::new_v4();
+    let req = test::TestRequest::get().uri("/whoami").insert_header(("Authorization", format!("Bearer {}", create_jwt(&state.keys, user, Uuid::new_v4(), false, Role::Trader)))).to_request();
+    let body = test::call_and_read_body(&app, req).await;
+    assert_eq!(body, user.to_string());
+}
+
+#[actix_web::test]
+async fn missing_or_forged_tokens_are_refused() {
+    let app = app!(state());
+    let missing = test::TestRequest::get().uri("/whoami").to_request();
+    assert_eq!(test::call_service(&app, missing).await.status(), StatusCode::UNAUTHORIZED);
+
+    let forged = test::TestRequest::get().uri("/whoami").insert_header(("Authorization", "Bearer not.a.token")).to_request();
+    assert_eq!(test::call_service(&app, forged).await.status(), StatusCode::UNAUTHORIZED);
+
+    // well formed, but signed by keys this server does not have
+    let foreign = create_jwt(&KeySet::generate(), Uuid::new_v4(), Uuid::new_v4(), false, Role::Trader);
+    let foreign = test::TestRequest::get().uri("/whoami").insert_header(("Authorization", format!("Bearer {foreign}"))).to_request();
+    assert_eq!(test::call_service(&app, foreign).await.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn auth_user_outside_the_middleware_is_refused() {
+    let state = state();
+    let app = app!(state);
+    let req = test::TestRequest::get().uri("/open").insert_header(("Authorization", format!("Bearer {}", create_jwt(&state.keys, Uuid::new_v4(), Uuid::new_v4(), false, Role::Trader)))).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn order_bodies_cannot_name_a_user() {
+    let user = Uuid::new_v4();
+    let order = format!(r#"{{"type":"limit","user_id":"{user}","side":"buy","quantity":1,"price":10,"leverage":1}}"#);
+    assert!(serde_json::from_str::<OrderRequest>(&order).is_err());
+    assert!(serde_json::from_str::<OrderRequest>(r#"{"type":"limit","side":"buy","quantity":1,"price":10,"leverage":1}"#).is_ok());
+
+    let cancel = format!(r#"{{"user_id":"{user}","order_id":"{user}"}}"#);
+    assert!(serde_json::from_str::<CanceledOrderRequest>(&cancel).is_err());
+
+    let batch = format!(r#"{{"orders":[{{"action":"cancel","user_id":"{user}","order_id":"{user}"}}]}}"#);
+    assert!(serde_json::from_str::<BatchOrderRequest>(&batch).is_err());
+    let batch = format!(r#"{{"orders":[{{"action":"cancel","order_id":"{user}"}}]}}"#);
+    assert!(serde_json::from_str::<BatchOrderRequest>(&batch).is_ok());
+}
@@ -0,0 +1,250 @@
+// End to end through the real routes and the database Db::new connects to.
+// Every test signs up its own random email and deletes it afterwards.
+
+mod common;
+
+use actix_web::{App, dev::ServiceResponse, http::{Method, StatusCode}, test, web};
+use backend::{Claims, routes, state::AppState, types::{Response, TokenResponse, UserResponse}};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header, jwk::JwkSet};
+use serde_json::{Value, json};
+use uuid::Uuid;
+
+async fn state() -> web::Data<AppState> {
+    web::Data::new(common::state(common::database().await))
+}
+
+fn random_email() -> String {
+    format!("{}@auth-routes.test", Uuid::new_v4())
+}
+
+async fn forget(state: &AppState, email: &str) {
+    sqlx::query("DELETE FROM users WHERE email = $1").bind(email).execute(&state.db.pool).await.unwrap();
+}
+
+macro_rules! app {
+    ($state:expr) => {
+        test::init_service(App::new().app_data($state.clone()).configure(routes)).await
+    };
+}
+
+macro_rules! post {
+    ($app:expr, $uri:expr, $email:expr, $password:expr) => {{
+        let req = test::TestRequest::post().uri($uri).set_json(json!({ "email": $email, "password": $password })).to_request();
+        let res: ServiceResponse = test::call_service(&$app, req).await;
+        res
+    }};
+}
+
+macro_rules! sign_in {
+    ($app:expr, $email:expr) => {{
+        let res = post!($app, "/signin", $email, "correct horse");
+        assert_eq!(res.status(), StatusCode::OK);
+        let tokens: TokenResponse = test::read_body_json(res).await;
+        tokens
+    }};
+}
+
+macro_rules! refresh {
+    ($app:expr, $refresh_token:expr) => {{
+        let req = test::TestRequest::post().uri("/token/refresh").set_json(json!({ "refresh_token": $refresh_token })).to_request();
+        let res: ServiceResponse = test::call_service(&$app, req).await;
+        res
+    }};
+}
+
+macro_rules! authed {
+    ($app:expr, $method:expr, $uri:expr, $tokens:expr) => {{
+        let req = test::TestRequest::default()
+            .method($method)
+            .uri($uri)
+            .insert_header(("Authorization", format!("Bearer {}", $tokens.token)))
+            .to_request();
+        let res: ServiceResponse = test::call_service(&$app, req).await;
+        res
+    }};
+}
+
+#[actix_web::test]
+async fn signup_then_signin_gives_a_working_token() {
+    let state = state().await;
+    let app = app!(state);
+    let email = random_email();
+
+    let res = post!(app, "/signup", &email, "correct horse");
+    assert_eq!(res.status(), StatusCode::OK);
+    let UserResponse { id } = test::read_body_json(res).await;
+
+    let res = post!(app, "/signin", &email, "correct horse");
+    assert_eq!(res.status(), StatusCode::OK);
+    let TokenResponse { token, .. } = test::read_body_json(res).await;
+
+    let req = test::TestRequest::get().uri("/me").insert_header(("Authorization", format!("Bearer {token}"))).to_request();
+    assert_eq!(test::call_and_read_body(&app, req).await, format!("User = {id}"));
+
+    let stored = state.db.get_user(&email).await.unwrap().unwrap();
+    assert!(stored.password.is_none());
+    assert!(stored.password_hash.unwrap().starts_with("$argon2id$"));
+    forget(&state, &email).await;
+}
+
+#[actix_web::test]
+async fn duplicate_signup_is_refused() {
+    let state = state().await;
+    let app = app!(state);
+    let email = random_email();
+
+    assert_eq!(post!(app, "/signup", &email, "correct horse").status(), StatusCode::OK);
+    let res = post!(app, "/signup", &email, "another password");
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+    let body: Response = test::read_body_json(res).await;
+    assert!(!body.error.contains(&email) && !body.error.contains("registered"));
+
+    // the first password still works
+    assert_eq!(post!(app, "/signin", &email, "correct horse").status(), StatusCode::OK);
+    forget(&state, &email).await;
+}
+
+#[actix_web::test]
+async fn unknown_user_and_wrong_password_look_the_same() {
+    let state = state().await;
+    let app = app!(state);
+    let email = random_email();
+    assert_eq!(post!(app, "/signup", &email, "correct horse").status(), StatusCode::OK);
+
+    let wrong = post!(app, "/signin", &email, "wrong horse");
+    let unknown = post!(app, "/signin", random_email(), "correct horse");
+    assert_eq!(wrong.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(unknown.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(test::read_body(wrong).await, test::read_body(unknown).await);
+    forget(&state, &email).await;
+}
+
+#[actix_web::test]
+async fn signup_checks_its_input() {
+    let state = state().await;
+    let app = app!(state);
+    assert_eq!(post!(app, "/signup", "not-an-email", "correct horse").status(), StatusCode::BAD_REQUEST);
+    let email = random_email();
+    assert_eq!(post!(app, "/signup", &email, "short").status(), StatusCode::BAD_REQUEST);
+    assert!(state.db.get_user(&email).await.unwrap().is_none());
+}
+
+#[actix_web::test]
+async fn legacy_plaintext_rows_are_rehashed_on_signin() {
+    let state = state().await;
+    let app = app!(state);
+    let email = random_email();
+    sqlx::query("INSERT INTO users (email, password) VALUES ($1, 'hunter2')").bind(&email).execute(&state.db.pool).await.unwrap();
+
+    assert_eq!(post!(app, "/signin", &email, "hunter3").status(), StatusCode::UNAUTHORIZED);
+    assert!(state.db.get_user(&email).await.unwrap().unwrap().password_hash.is_none());
+
+    assert_eq!(post!(app, "/signin", &email, "hunter2").status(), StatusCode::OK);
+    let upgraded = state.db.get_user(&email).await.unwrap().unwrap();
+    assert!(upgraded.password.is_none());
+    assert!(upgraded.password_hash.is_some());
+    assert_eq!(post!(app, "/signin", &email, "hunter2").status(), StatusCode::OK);
+    forget(&state, &email).await;
+}
+
+#[actix_web::test]
+async fn refresh_rotates_the_refresh_token() {
+    let state = state().await;
+    let app = app!(state);
+    let email = random_email();
+    assert_eq!(post!(app, "/signup", &email, "correct horse").status(), StatusCode::OK);
+    let first = sign_in!(app, &email);
+
+    let res = refresh!(app, &first.refresh_token);
+    assert_eq!(res.status(), StatusCode::OK);
+    let second: TokenResponse = test::read_body_json(res).await;
+    assert_ne!(second.refresh_token, first.refresh_token);
+    assert_eq!(authed!(app, Method::GET, "/me", second).status(), StatusCode::OK);
+
+    // the rotated token keeps working in turn
+    assert_eq!(refresh!(app, &second.refresh_token).status(), StatusCode::OK);
+    assert_eq!(refresh!(app, "made up").status(), StatusCode::UNAUTHORIZED);
+    forget(&state, &email).await;
+}
+
+#[actix_web::test]
+async fn reusing_a_refresh_token_kills_the_session() {
+    let state = state().await;
+    let app = app!(state);
+    let email = random_email();
+    assert_eq!(post!(app, "/signup", &email, "correct horse").status(), StatusCode::OK);
+    let stolen = sign_in!(app, &email);
+    let other = sign_in!(app, &email);
+
+    let res = refresh!(app, &stolen.refresh_token);
+    let rotated: TokenResponse = test::read_body_json(res).await;
+    assert_eq!(refresh!(app, &stolen.refresh_token).status(), StatusCode::UNAUTHORIZED);
+    // the legitimate holder of the family is logged out too
+    assert_eq!(refresh!(app, &rotated.refresh_token).status(), StatusCode::UNAUTHORIZED);
+
+    // other sessions of the user are untouched
+    assert_eq!(refresh!(app, &other.refresh_token).status(), StatusCode::OK);
+    let user = state.db.get_user(&email).await.unwrap().unwrap();
+    assert_eq!(state.db.list_sessions(user.id).await.unwrap().len(), 1);
+    forget(&state, &email).await;
+}
+
+#[actix_web::test]
+async fn logout_revokes_the_current_session() {
+    let state = state().await;
+    let app = app!(state);
+    let email = random_email();
+    assert_eq!(post!(app, "/signup", &email, "correct horse").status(), StatusCode::OK);
+    let tokens = sign_in!(app, &email);
+
+    assert_eq!(authed!(app, Method::POST, "/logout", tokens).status(), StatusCode::NO_CONTENT);
+    assert_eq!(refresh!(app, &tokens.refresh_token).status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(authed!(app, Method::POST, "/logout", tokens).status(), StatusCode::NOT_FOUND);
+    forget(&state, &email).await;
+}
+
+#[actix_web::test]
+async fn sessions_can_be_listed_and_revoked_by_their_owner_only() {
+    let state = state().await;
+    let app = app!(state);
+    let (email, intruder) = (random_email(), random_email());
+    assert_eq!(post!(app, "/signup", &email, "correct horse").status(), StatusCode::OK);
+    assert_eq!(post!(app, "/signup", &intruder, "correct horse").status(), StatusCode::OK);
+    let laptop = sign_in!(app, &email);
+    let phone = sign_in!(app, &email);
+    let theirs = sign_in!(app, &intruder);
+
+    let res = authed!(app, Method::GET, "/sessions", laptop);
+    assert_eq!(res.status(), StatusCode::OK);
+    let sessions: Vec<Value> = test::read_body_json(res).await;
+    assert_eq!(sessions.len(), 2);
+    assert_eq!(sessions.iter().filter(|s| s["current"] == json!(true)).count(), 1);
+    let phone_id = sessions.iter().find(|s| s["current"] == json!(false)).unwrap()["id"].as_str().unwrap().to_string();
+
+    let uri = format!("/sessions/{phone_id}");
+    assert_eq!(authed!(app, Method::DELETE, &uri, theirs).status(), StatusCode::NOT_FOUND);
+    assert_eq!(refresh!(app, &phone.refresh_token).status(), StatusCode::OK);
+
+    assert_eq!(authed!(app, Method::DELETE, &uri, laptop).status(), StatusCode::NO_CONTENT);
+    let sessions: Vec<Value> = test::read_body_json(authed!(app, Method::GET, "/sessions", laptop)).await;
+    assert_eq!(sessions.len(), 1);
+    forget(&state, &email).await;
+    forget(&state, &intruder).await;
+}
+
+#[actix_web::test]
+async fn issued_tokens_verify_against_the_published_jwks() {
+    let state = state().await;
+    let app = app!(state);
+    let email = random_email();
+    assert_eq!(post!(app, "/signup", &email, "correct horse").status(), StatusCode::OK);
+    let tokens = sign_in!(app, &email);
+
+    let req = test::TestRequest::get().uri("/.well-known/jwks.json").to_request();
+    let jwks: JwkSet = test::call_and_read_body_json(&app, req).await;
+    let kid = decode_header(&tokens.token).unwrap().kid.unwrap();
+    let key = DecodingKey::from_jwk(jwks.find(&kid).unwrap()).unwrap();
+    let claims = decode::<Claims>(&tokens.token, &key, &Validation::new(Algorithm::EdDSA)).unwrap().claims;
+    assert_eq!(claims.sub, state.db.get_user(&email).await.unwrap().unwrap().id);
+    forget(&state, &email).await;
+}
@@ -0,0 +1,110 @@
+use std::{
+    fs,
+    sync::{Arc, mpsc},
+};
+
+use backend::{Instrument, Journal, LimitOrder, MatchingEngine, Order, OrderId, RingBuffer, SequentialIdGenerator, UserId, types::{Event, EventEnvelope, OrderBookMessage, OrderResponse, Priority, Side}};
+use rust_decimal_macros::dec;
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+const SEED: u64 = 11;
+
+type Reply = oneshot::Receiver<Result<OrderResponse, String>>;
+
+fn id(n: u64) -> OrderId {
+    Uuid::from_u64_pair(SEED, n)
+}
+
+fn engine(ring: &Arc<RingBuffer<EventEnvelope>>) -> MatchingEngine {
+    MatchingEngine::new(Arc::clone(ring), Instrument::new(dec!(0.01), dec!(0.001))).with_id_generator(SequentialIdGenerator::new(SEED))
+}
+
+fn place(user_id: UserId, side: Side, price: i64) -> (OrderBookMessage, Reply) {
+    let (tx, rx) = oneshot::channel();
+    let order = Order::limit_order(LimitOrder { user_id, side, price, quantity: 1, leverage: dec!(1) });
+    (OrderBookMessage::PlaceOrder { order, priority: Priority::Normal, responder: Some(tx) }, rx)
+}
+
+fn cancel(user_id: UserId, order_id: OrderId) -> (OrderBookMessage, Reply) {
+    let (tx, rx) = oneshot::channel();
+    (OrderBookMessage::CancelOrder { order_id, user_id, responder: Some(tx) }, rx)
+}
+
+fn run(engine: &mut MatchingEngine, messages: Vec<OrderBookMessage>) {
+    let (tx, rx) = mpsc::sync_channel(messages.len());
+    for message in messages {
+        tx.send(message).unwrap();
+    }
+    drop(tx);
+    engine.run(rx);
+}
+
+// a sell sent ahead of a batch that buys through its price, cancels its own buy and quotes a sell
+fn session(engine: &mut MatchingEngine, user: UserId) -> Vec<Reply> {
+    let (sell, sell_reply) = place(Uuid::new_v4(), Side::Sell, 100);
+    let (buy, buy_reply) = place(user, Side::Buy, 101);
+    // ids are handed out as commands are admitted, the sell takes the first and the buy the second
+    let (undo, undo_reply) = cancel(user, id(2));
+    let (quote, quote_reply) = place(user, Side::Sell, 105);
+    run(engine, vec![sell, OrderBookMessage::Batch { commands: vec![buy, undo, quote] }]);
+    vec![sell_reply, buy_reply, undo_reply, quote_reply]
+}
+
+#[test]
+fn batch_runs_back_to_back_in_request_order() {
+    let ring = Arc::new(RingBuffer::<EventEnvelope>::new(64));
+    let mut engine = engine(&ring);
+    let user = Uuid::new_v4();
+    let replies = session(&mut engine, user);
+
+    // the batch queues like its orders, so the other user's earlier sell goes first and the buy
+    // trades against it, leaving the cancel nothing to cancel
+    let events: Vec<(u64, Event)> = ring.drain_batch(64).into_iter().map(|e| (e.command_seq, e.event)).collect();
+    assert!(matches!(&events[..], [
+        (1, Event::OrderPlaced { order_id: a, side: Side::Sell, .. }),
+        (2, Event::Fill(trade)),
+        (4, Event::OrderPlaced { order_id: c, side: Side::Sell, .. }),
+    ] if *a == id(1) && trade.maker_order_id == id(1) && trade.taker_order_id == id(2) && *c == id(3)));
+
+    let replies: Vec<_> = replies.into_iter().map(|rx| rx.blocking_recv().unwrap()).collect();
+    assert!(matches!(replies[0], Ok(OrderResponse::PlacedOrder { order_id, .. }) if order_id == id(1)));
+    assert!(matches!(replies[1], Ok(OrderResponse::PlacedOrder { order_id, .. }) if order_id == id(2)));
+    assert!(matches!(replies[2], Ok(OrderResponse::Message { .. })));
+    assert!(matches!(replies[3], Ok(OrderResponse::PlacedOrder { order_id, .. }) if order_id == id(3)));
+}
+
+#[test]
+fn batch_gets_individual_answers_for_rejected_commands() {
+    let ring = Arc::new(RingBuffer::<EventEnvelope>::new(64));
+    let mut engine = engine(&ring);
+    let user = Uuid::new_v4();
+    let (buy, buy_reply) = place(user, Side::Buy, 100);
+    let (unknown, unknown_reply) = cancel(user, Uuid::new_v4());
+    let (tx, overleveraged_reply) = oneshot::channel();
+    let order = Order::limit_order(LimitOrder { user_id: user, side: Side::Sell, price: 110, quantity: 1, leverage: dec!(500) });
+    let overleveraged = OrderBookMessage::PlaceOrder { order, priority: Priority::Normal, responder: Some(tx) };
+    run(&mut engine, vec![OrderBookMessage::Batch { commands: vec![buy, unknown, overleveraged] }]);
+
+    assert!(matches!(buy_reply.blocking_recv().unwrap(), Ok(OrderResponse::PlacedOrder { .. })));
+    assert!(matches!(unknown_reply.blocking_recv().unwrap(), Ok(OrderResponse::Message { .. })));
+    assert!(overleveraged_reply.blocking_recv().unwrap().is_err());
+}
+
+#[test]
+fn batch_is_journaled_command_by_command() {
+    let path = std::env::temp_dir().join(format!("perp-batch-{}", Uuid::new_v4())).join("engine.journal");
+    let ring = Arc::new(RingBuffer::<EventEnvelope>::new(64));
+    let mut live = engine(&ring).with_journal(Journal::open(&path).unwrap());
+    session(&mut live, Uuid::new_v4());
+
+    let entries = Journal::read_from(&path, 0).unwrap();
+    assert_eq!(entries.iter().map(|e| e.seq).collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+    assert!(entries.iter().all(|e| !matches!(e.message, OrderBookMessage::Batch { .. })));
+
+    let mut recovered = engine(&Arc::new(RingBuffer::new(16))).with_journal(Journal::open(&path).unwrap());
+    assert_eq!(recovered.recover().unwrap(), 4);
+    assert_eq!(recovered.order_book().state_hash(), live.order_book().state_hash());
+
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
@@ -0,0 +1,124 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    thread,
+};
+
+use backend::{BroadcastConsumer, BroadcastRingBuilder};
+
+const ITEMS: u64 = 20_000;
+
+fn consume(mut consumer: BroadcastConsumer<u64>, mut check: impl FnMut(u64)) -> u64 {
+    let mut expected = 0;
+    while expected < ITEMS {
+        let batch = consumer.drain_batch(64);
+        if batch.is_empty() {
+            thread::yield_now();
+        }
+        for item in batch {
+            assert_eq!(item, expected, "{} saw items out of order", consumer.name());
+            check(item);
+            expected += 1;
+        }
+    }
+    expected
+}
+
+#[test]
+fn every_consumer_sees_every_item_in_order() {
+    let (mut producer, consumers) = BroadcastRingBuilder::new(64)
+        .consumer("a", &[])
+        .consumer("b", &[])
+        .consumer("c", &[])
+        .build::<u64>();
+
+    let readers: Vec<_> = consumers.into_iter().map(|consumer| thread::spawn(move || consume(consumer, |_| {}))).collect();
+    for item in 0..ITEMS {
+        producer.publish(item);
+    }
+    for reader in readers {
+        assert_eq!(reader.join().unwrap(), ITEMS);
+    }
+}
+
+#[test]
+fn dependent_consumer_never_overtakes_its_dependency() {
+    let (mut producer, mut consumers) = BroadcastRingBuilder::new(32)
+        .consumer("persistence", &[])
+        .consumer("market-data", &["persistence"])
+        .build::<u64>();
+    let market_data = consumers.pop().unwrap();
+    let mut persistence = consumers.pop().unwrap();
+
+    let persisted = Arc::new(AtomicU64::new(0));
+    let done = Arc::clone(&persisted);
+    let first = thread::spawn(move || {
+        let mut handled = 0;
+        while handled < ITEMS {
+            let batch = persistence.peek_batch(16);
+            if batch.is_empty() {
+                thread::yield_now();
+            }
+            for item in &batch {
+                // pretend to write it somewhere before letting it go
+                done.store(item + 1, Ordering::Release);
+            }
+            handled += batch.len() as u64;
+            persistence.commit(batch.len());
+        }
+    });
+    let second = thread::spawn(move || consume(market_data, |item| assert!(item < persisted.load(Ordering::Acquire))));
+
+    for item in 0..ITEMS {
+        producer.publish(item);
+    }
+    first.join().unwrap();
+    assert_eq!(second.join().unwrap(), ITEMS);
+}
+
+#[test]
+fn producer_waits_for_the_slowest_consumer() {
+    let (mut producer, mut consumers) = BroadcastRingBuilder::new(4).consumer("fast", &[]).consumer("slow", &[]).build::<u64>();
+    let mut slow = consumers.pop().unwrap();
+    let mut fast = consumers.pop().unwrap();
+
+    for item in 0..4 {
+        producer.try_publish(item).unwrap();
+    }
+    assert_eq!(producer.try_publish(4), Err(4));
+
+    assert_eq!(fast.drain_batch(10), vec![0, 1, 2, 3]);
+    assert_eq!(producer.try_publish(4), Err(4), "a fast consumer alone must not free slots");
+
+    // peeking does not free anything either
+    assert_eq!(slow.peek_batch(2), vec![0, 1]);
+    assert_eq!(producer.try_publish(4), Err(4));
+    slow.commit(2);
+    producer.try_publish(4).unwrap();
+    producer.try_publish(5).unwrap();
+    assert_eq!(producer.try_publish(6), Err(6));
+    assert_eq!(slow.drain_batch(10), vec![2, 3, 4, 5]);
+    assert!(slow.is_caught_up());
+    assert!(!fast.is_caught_up());
+}
+
+#[test]
+fn items_left_in_the_ring_are_dropped_with_it() {
+    let item = Arc::new(());
+    let (mut producer, consumers) = BroadcastRingBuilder::new(8).consumer("a", &[]).build::<Arc<()>>();
+    for _ in 0..5 {
+        producer.publish(Arc::clone(&item));
+    }
+    assert_eq!(Arc::strong_count(&item), 6);
+    drop(producer);
+    drop(consumers);
+    assert_eq!(Arc::strong_count(&item), 1);
+}
+
+#[test]
+#[should_panic(expected = "unknown consumer")]
+fn dependency_on_an_unknown_consumer_is_rejected() {
+    BroadcastRingBuilder::new(8).consumer("market-data", &["persistence"]);
+}
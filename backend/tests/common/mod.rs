@@ -0,0 +1,41 @@
+// The AppState the route tests start from. A test that is about one part of it swaps that in with
+// struct update syntax, AppState { login_limiter, ..common::state(db) }, so a new field only has to
+// be added here.
+#![allow(dead_code)]
+
+use std::{sync::{Arc, mpsc}, thread};
+
+use backend::{ApiKeyCipher, Instrument, KeySet, LoginLimiter, MarketDataPublisher, MatchingEngine, NonceCache, OrderRateLimiter, RingBuffer, state::AppState};
+use db::Db;
+use rust_decimal_macros::dec;
+
+pub async fn database() -> Db {
+    Db::new().await.expect("tests need the database")
+}
+
+// no engine behind book_tx, orders that get past the handler's checks answer 503
+pub fn state(db: Db) -> AppState {
+    let (book_tx, _) = mpsc::sync_channel(1);
+    AppState {
+        book_tx,
+        db,
+        instrument: Instrument::new(dec!(0.01), dec!(0.001)),
+        event_ring: Arc::new(RingBuffer::new(2)),
+        keys: Arc::new(KeySet::generate()),
+        api_key_cipher: Arc::new(ApiKeyCipher::generate()),
+        nonces: Arc::new(NonceCache::new()),
+        login_limiter: Arc::new(LoginLimiter::in_memory()),
+        order_limiter: Arc::new(OrderRateLimiter::new()),
+        market_data: MarketDataPublisher::new(16).feed(),
+    }
+}
+
+// a running engine, its thread stops once the state and with it the sender is dropped
+pub fn state_with_engine(db: Db) -> AppState {
+    let (book_tx, book_rx) = mpsc::sync_channel(64);
+    let base = state(db);
+    let event_ring = Arc::new(RingBuffer::new(1024));
+    let mut engine = MatchingEngine::new(Arc::clone(&event_ring), base.instrument);
+    thread::spawn(move || engine.run(book_rx));
+    AppState { book_tx, event_ring, ..base }
+}
@@ -0,0 +1,137 @@
+use std::{
+    fs,
+    sync::{Arc, mpsc},
+};
+
+use backend::{Journal, Instrument, LimitOrder, Lots, ManualClock, MarketOrder, MatchingEngine, Order, OrderId, RingBuffer, SequentialIdGenerator, Ticks, UserId, types::{Event, EventEnvelope, OrderBookMessage, Priority, Side}};
+use rust_decimal_macros::dec;
+use uuid::Uuid;
+
+const SEED: u64 = 7;
+
+fn engine(events: &Arc<RingBuffer<EventEnvelope>>, clock: &ManualClock) -> MatchingEngine {
+    MatchingEngine::new(Arc::clone(events), Instrument::new(dec!(0.01), dec!(0.001)))
+        .with_clock(clock.clone())
+        .with_id_generator(SequentialIdGenerator::new(SEED))
+}
+
+fn place(order: Order) -> OrderBookMessage {
+    OrderBookMessage::PlaceOrder { order, priority: Priority::Normal, responder: None }
+}
+
+fn limit(user_id: UserId, side: Side, price: Ticks, quantity: Lots) -> OrderBookMessage {
+    place(Order::limit_order(LimitOrder { user_id, side, price, quantity, leverage: dec!(3) }))
+}
+
+fn run(engine: &mut MatchingEngine, messages: Vec<OrderBookMessage>) {
+    let (tx, rx) = mpsc::sync_channel(messages.len().max(1));
+    for message in messages {
+        tx.send(message).unwrap();
+    }
+    drop(tx);
+    engine.run(rx);
+}
+
+// orders get ids in arrival order, so the n-th placed order has the n-th id of the sequence
+fn id(n: u64) -> OrderId {
+    Uuid::from_u64_pair(SEED, n)
+}
+
+// three rounds with the clock moving in between, the later ones cancel and trade against the first
+fn session(engine: &mut MatchingEngine, clock: &ManualClock, users: &[UserId]) {
+    run(engine, (0..20).map(|i| limit(users[i % 2], if i % 2 == 0 { Side::Buy } else { Side::Sell }, 100 + (i as i64 % 5) * if i % 2 == 0 { -1 } else { 1 }, 1 + i as u64)).collect());
+
+    clock.advance(1_500);
+    run(engine, vec![
+        OrderBookMessage::CancelOrder { order_id: id(3), user_id: users[0], responder: None },
+        OrderBookMessage::CancelOrder { order_id: id(4), user_id: users[1], responder: None },
+        limit(users[2], Side::Buy, 103, 25),
+    ]);
+
+    clock.advance(250);
+    run(engine, vec![
+        place(Order::market_order(MarketOrder { user_id: users[2], side: Side::Sell, quantity: 30, leverage: dec!(1) })),
+        limit(users[0], Side::Sell, 99, 4),
+    ]);
+}
+
+fn events(ring: &RingBuffer<EventEnvelope>) -> Vec<EventEnvelope> {
+    ring.drain_batch(1 << 12)
+}
+
+#[test]
+fn same_commands_give_identical_events() {
+    let users: Vec<UserId> = (0..3).map(|_| Uuid::new_v4()).collect();
+    let mut runs = Vec::new();
+    for _ in 0..2 {
+        let ring = Arc::new(RingBuffer::<EventEnvelope>::new(1 << 12));
+        let clock = ManualClock::new(1_000_000);
+        let mut engine = engine(&ring, &clock);
+        session(&mut engine, &clock, &users);
+        runs.push((events(&ring), engine.order_book().state_hash()));
+    }
+
+    let (events, hash) = &runs[0];
+    assert!(events.iter().any(|e| matches!(e.event, Event::Fill(_))));
+    assert!(events.iter().any(|e| matches!(e.event, Event::OrderCancelled { order_id, timestamp: 1_001_500, .. } if order_id == id(3))));
+    assert!(events.iter().any(|e| matches!(e.event, Event::Fill(t) if t.taker_order_id == id(22) && t.timestamp == 1_001_750)));
+    assert_eq!(&runs[1].0, events);
+    assert_eq!(&runs[1].1, hash);
+}
+
+#[test]
+fn journal_keeps_the_assigned_ids_and_times() {
+    let users: Vec<UserId> = (0..3).map(|_| Uuid::new_v4()).collect();
+    let path = std::env::temp_dir().join(format!("perp-determinism-{}", Uuid::new_v4())).join("engine.journal");
+
+    let ring = Arc::new(RingBuffer::<EventEnvelope>::new(1 << 12));
+    let clock = ManualClock::new(42);
+    let mut live = engine(&ring, &clock).with_journal(Journal::open(&path).unwrap());
+    session(&mut live, &clock, &users);
+
+    let entries = Journal::read_from(&path, 0).unwrap();
+    assert_eq!(entries[0].timestamp, 42);
+    assert_eq!(entries.last().unwrap().timestamp, 42 + 1_750);
+    assert!(matches!(&entries[0].message, OrderBookMessage::PlaceOrder { order, .. } if order.order_id == id(1)));
+
+    // a different clock and id source on recovery must not matter
+    let mut recovered = MatchingEngine::new(Arc::new(RingBuffer::new(16)), Instrument::new(dec!(0.01), dec!(0.001)))
+        .with_journal(Journal::open(&path).unwrap());
+    recovered.recover().unwrap();
+    assert_eq!(recovered.order_book().state_hash(), live.order_book().state_hash());
+
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn event_seqs_are_gapless_and_continue_after_recovery() {
+    let users: Vec<UserId> = (0..3).map(|_| Uuid::new_v4()).collect();
+    let path = std::env::temp_dir().join(format!("perp-determinism-{}", Uuid::new_v4())).join("engine.journal");
+
+    let ring = Arc::new(RingBuffer::<EventEnvelope>::new(1 << 12));
+    let clock = ManualClock::new(0);
+    let mut live = engine(&ring, &clock).with_journal(Journal::open(&path).unwrap());
+    session(&mut live, &clock, &users);
+
+    let first = events(&ring);
+    assert_eq!(first.iter().map(|e| e.seq).collect::<Vec<_>>(), (1..=first.len() as u64).collect::<Vec<_>>());
+    assert!(first.windows(2).all(|w| w[0].command_seq <= w[1].command_seq));
+    // the cancels are the first two commands of the second round
+    let cancelled: Vec<u64> = first.iter().filter(|e| matches!(e.event, Event::OrderCancelled { .. })).map(|e| e.command_seq).collect();
+    assert_eq!(cancelled, vec![21, 22]);
+    assert_eq!(live.event_seq(), first.len() as u64);
+
+    // events of replayed commands are not sent again, but numbering picks up where it left off
+    let ring = Arc::new(RingBuffer::<EventEnvelope>::new(1 << 12));
+    let mut recovered = engine(&ring, &clock).with_journal(Journal::open(&path).unwrap());
+    recovered.recover().unwrap();
+    assert!(events(&ring).is_empty());
+    assert_eq!(recovered.event_seq(), live.event_seq());
+
+    run(&mut recovered, vec![limit(users[1], Side::Sell, 150, 1)]);
+    let next = events(&ring);
+    assert_eq!(next.len(), 1);
+    assert_eq!((next[0].seq, next[0].command_seq), (live.event_seq() + 1, live.command_seq() + 1));
+
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
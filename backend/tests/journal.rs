@@ -0,0 +1,167 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::PathBuf,
+    sync::{Arc, mpsc},
+};
+
+use backend::{Instrument, Journal, LimitOrder, Lots, MarketOrder, MatchingEngine, Order, OrderBook, OrderId, RingBuffer, Ticks, UserId, types::{EventEnvelope, OrderBookMessage, Priority, Side}};
+use rust_decimal_macros::dec;
+use uuid::Uuid;
+
+fn journal_path() -> PathBuf {
+    std::env::temp_dir().join(format!("perp-journal-{}", Uuid::new_v4())).join("engine.journal")
+}
+
+fn engine() -> MatchingEngine {
+    MatchingEngine::new(Arc::new(RingBuffer::<EventEnvelope>::new(1 << 16)), Instrument::new(dec!(0.01), dec!(0.001)))
+}
+
+fn place(order: Order) -> OrderBookMessage {
+    OrderBookMessage::PlaceOrder { order, priority: Priority::Normal, responder: None }
+}
+
+fn limit(user_id: UserId, side: Side, price: Ticks, quantity: Lots) -> Order {
+    Order::limit_order(LimitOrder { user_id, side, price, quantity, leverage: dec!(2) }).with_id(Uuid::new_v4())
+}
+
+// runs the messages through the engine loop, which returns once the channel is drained and closed
+fn run(engine: &mut MatchingEngine, messages: Vec<OrderBookMessage>) {
+    let (tx, rx) = mpsc::sync_channel(messages.len().max(1));
+    for message in messages {
+        tx.send(message).unwrap();
+    }
+    drop(tx);
+    engine.run(rx);
+}
+
+// levels with their orders in time priority, fill_seq and the number of users with resting orders
+type BookState = (Vec<(Ticks, Vec<(OrderId, UserId, Lots)>, Lots)>, u64, usize);
+
+fn state(book: &OrderBook) -> BookState {
+    let levels = book
+        .bids
+        .iter()
+        .chain(book.asks.iter())
+        .map(|(price, level)| {
+            let orders = book.iter_level(level).map(|o| (o.order_id, o.user_id, o.remaining())).collect();
+            (*price, orders, level.total_qty)
+        })
+        .collect();
+    (levels, book.fill_seq, book.user_orders.len())
+}
+
+fn workload() -> Vec<OrderBookMessage> {
+    let users: Vec<UserId> = (0..3).map(|_| Uuid::new_v4()).collect();
+    let mut messages = Vec::new();
+    let mut resting = Vec::new();
+    for i in 0..200i64 {
+        let user = users[i as usize % users.len()];
+        let side = if i % 2 == 0 { Side::Buy } else { Side::Sell };
+        let order = limit(user, side, 100 + (i * 7) % 9 - 4, 1 + (i as u64 * 13) % 17);
+        resting.push((order.order_id, user));
+        messages.push(place(order));
+        if i % 5 == 0 {
+            let (order_id, user_id) = resting[(i as usize * 31) % resting.len()];
+            messages.push(OrderBookMessage::CancelOrder { order_id, user_id, responder: None });
+        }
+        if i % 11 == 0 {
+            messages.push(place(Order::market_order(MarketOrder { user_id: user, side, quantity: 5, leverage: dec!(1) })));
+        }
+    }
+    messages
+}
+
+#[test]
+fn replay_rebuilds_identical_book() {
+    let path = journal_path();
+    let messages = workload();
+    let count = messages.len();
+
+    let mut live = engine().with_journal(Journal::open(&path).unwrap());
+    run(&mut live, messages);
+    assert!(!live.order_book().orders.is_empty());
+
+    let mut recovered = engine().with_journal(Journal::open(&path).unwrap());
+    assert_eq!(recovered.recover().unwrap(), count);
+    assert_eq!(state(recovered.order_book()), state(live.order_book()));
+
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn recovered_engine_keeps_appending_after_the_last_seq() {
+    let path = journal_path();
+    let user = Uuid::new_v4();
+
+    let mut first = engine().with_journal(Journal::open(&path).unwrap());
+    run(&mut first, vec![place(limit(user, Side::Buy, 100, 5)), place(limit(user, Side::Buy, 99, 5))]);
+
+    let journal = Journal::open(&path).unwrap();
+    assert_eq!(journal.last_seq(), 2);
+    let mut second = engine().with_journal(journal);
+    second.recover().unwrap();
+    run(&mut second, vec![place(limit(Uuid::new_v4(), Side::Sell, 100, 5))]);
+
+    let entries = Journal::read_from(&path, 0).unwrap();
+    assert_eq!(entries.iter().map(|e| e.seq).collect::<Vec<_>>(), vec![1, 2, 3]);
+    assert_eq!(Journal::read_from(&path, 2).unwrap().len(), 1);
+
+    let mut third = engine().with_journal(Journal::open(&path).unwrap());
+    third.recover().unwrap();
+    assert_eq!(state(third.order_book()), state(second.order_book()));
+    assert_eq!(third.order_book().best_bid, Some(99));
+
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn torn_tail_is_truncated_on_open() {
+    let path = journal_path();
+    let user = Uuid::new_v4();
+    {
+        let mut journal = Journal::open(&path).unwrap();
+        journal.append(0, &place(limit(user, Side::Buy, 100, 1))).unwrap();
+        journal.append(0, &place(limit(user, Side::Buy, 101, 1))).unwrap();
+        journal.sync().unwrap();
+    }
+    let intact = fs::metadata(&path).unwrap().len();
+
+    // a crash half way through the third frame
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&[42, 0, 0, 0, 1, 2, 3]).unwrap();
+    drop(file);
+
+    let mut journal = Journal::open(&path).unwrap();
+    assert_eq!(journal.last_seq(), 2);
+    assert_eq!(fs::metadata(&path).unwrap().len(), intact);
+
+    assert_eq!(journal.append(0, &place(limit(user, Side::Buy, 102, 1))).unwrap(), 3);
+    journal.sync().unwrap();
+    assert_eq!(Journal::read_from(&path, 0).unwrap().len(), 3);
+
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn corruption_before_the_tail_is_an_error() {
+    let path = journal_path();
+    let user = Uuid::new_v4();
+    {
+        let mut journal = Journal::open(&path).unwrap();
+        for price in 100..103 {
+            journal.append(0, &place(limit(user, Side::Sell, price, 1))).unwrap();
+        }
+        journal.sync().unwrap();
+    }
+
+    // flip a payload byte in the first frame
+    let mut bytes = fs::read(&path).unwrap();
+    bytes[20] ^= 0xff;
+    fs::write(&path, &bytes).unwrap();
+
+    assert_eq!(Journal::open(&path).err().unwrap().kind(), ErrorKind::InvalidData);
+    assert_eq!(Journal::read_from(&path, 0).err().unwrap().kind(), ErrorKind::InvalidData);
+
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
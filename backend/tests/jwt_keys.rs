@@ -0,0 +1,103 @@
+use std::fs;
+
+use backend::{Claims, KeySet, types::Role};
+use chrono::Utc;
+use ed25519_dalek::{SigningKey, pkcs8::{EncodePrivateKey, EncodePublicKey, spki::der::pem::LineEnding}};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, decode_header, encode, jwk::AlgorithmParameters};
+use uuid::Uuid;
+
+fn key(seed: u8) -> SigningKey {
+    SigningKey::from_bytes(&[seed; 32])
+}
+
+fn claims() -> Claims {
+    Claims { sub: Uuid::new_v4(), sid: Uuid::new_v4(), mfa: false, role: Role::Trader, exp: Utc::now().timestamp() as usize + 60 }
+}
+
+#[test]
+fn rotated_out_keys_keep_verifying_until_dropped() {
+    let before = KeySet::new(&key(1), &[]);
+    let old_token = before.sign(&claims());
+
+    let during = KeySet::new(&key(2), &[key(1).verifying_key()]);
+    assert!(during.verify(&old_token).is_ok());
+    let new_token = during.sign(&claims());
+    assert_ne!(decode_header(&new_token).unwrap().kid, decode_header(&old_token).unwrap().kid);
+    assert_eq!(decode_header(&new_token).unwrap().kid.as_deref(), Some(during.active_kid()));
+    assert!(before.verify(&new_token).is_err());
+
+    let after = KeySet::new(&key(2), &[]);
+    assert!(after.verify(&new_token).is_ok());
+    assert!(after.verify(&old_token).is_err());
+}
+
+#[test]
+fn kids_do_not_depend_on_the_instance() {
+    assert_eq!(KeySet::new(&key(1), &[]).active_kid(), KeySet::new(&key(1), &[key(2).verifying_key()]).active_kid());
+    assert_ne!(KeySet::generate().active_kid(), KeySet::generate().active_kid());
+}
+
+#[test]
+fn other_services_verify_with_the_jwks() {
+    let keys = KeySet::new(&key(2), &[key(1).verifying_key(), key(2).verifying_key()]);
+    let jwks = keys.jwks();
+    assert_eq!(jwks.keys.len(), 2);
+    let json = serde_json::to_value(jwks).unwrap();
+    assert_eq!(json["keys"][0]["kty"], "OKP");
+    assert_eq!(json["keys"][0]["crv"], "Ed25519");
+    assert_eq!(json["keys"][0]["alg"], "EdDSA");
+    assert!(matches!(jwks.keys[0].algorithm, AlgorithmParameters::OctetKeyPair(_)));
+
+    let issued = claims();
+    let token = keys.sign(&issued);
+    let jwk = jwks.find(&decode_header(&token).unwrap().kid.unwrap()).unwrap();
+    let verified = decode::<Claims>(&token, &DecodingKey::from_jwk(jwk).unwrap(), &Validation::new(Algorithm::EdDSA)).unwrap();
+    assert_eq!(verified.claims.sub, issued.sub);
+}
+
+#[test]
+fn only_eddsa_tokens_naming_a_known_key_pass() {
+    let keys = KeySet::new(&key(1), &[]);
+    // the public key used as an HMAC secret, under the right kid
+    let mut header = Header::new(Algorithm::HS256);
+    header.kid = Some(keys.active_kid().to_string());
+    let public = key(1).verifying_key().to_bytes();
+    let confused = encode(&header, &claims(), &EncodingKey::from_secret(&public)).unwrap();
+    assert!(keys.verify(&confused).is_err());
+
+    let der = key(1).to_pkcs8_der().unwrap();
+    let unnamed = encode(&Header::new(Algorithm::EdDSA), &claims(), &EncodingKey::from_ed_der(der.as_bytes())).unwrap();
+    assert!(keys.verify(&unnamed).is_err());
+
+    let expired = keys.sign(&Claims { exp: Utc::now().timestamp() as usize - 3600, ..claims() });
+    assert!(keys.verify(&expired).is_err());
+}
+
+#[test]
+fn keys_load_from_pem_files() {
+    let dir = std::env::temp_dir().join(format!("perp-keys-{}", Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    let active = dir.join("active.pem");
+    let previous = dir.join("previous.pub.pem");
+    fs::write(&active, key(2).to_pkcs8_pem(LineEnding::LF).unwrap().as_bytes()).unwrap();
+    fs::write(&previous, key(1).verifying_key().to_public_key_pem(LineEnding::LF).unwrap()).unwrap();
+
+    // the only test touching these variables
+    unsafe {
+        std::env::set_var("JWT_SIGNING_KEY", &active);
+        std::env::set_var("JWT_VERIFY_KEYS", format!("{}, ", previous.display()));
+    }
+    let loaded = KeySet::from_env().unwrap();
+    assert_eq!(loaded.active_kid(), KeySet::new(&key(2), &[]).active_kid());
+    assert!(loaded.verify(&KeySet::new(&key(1), &[]).sign(&claims())).is_ok());
+
+    unsafe { std::env::set_var("JWT_VERIFY_KEYS", dir.join("missing.pem")) };
+    assert!(KeySet::from_env().is_err());
+    unsafe {
+        std::env::remove_var("JWT_SIGNING_KEY");
+        std::env::remove_var("JWT_VERIFY_KEYS");
+    }
+    // no signing key is an error, not a key that dies with the process
+    assert!(KeySet::from_env().is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
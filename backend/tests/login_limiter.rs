@@ -0,0 +1,122 @@
+mod common;
+
+use std::{net::IpAddr, sync::Arc};
+
+use actix_web::{App, dev::ServiceResponse, http::{StatusCode, header::RETRY_AFTER}, test, web};
+use backend::{AuthError, LoginLimiter, LoginPolicy, PgAttemptStore, routes, state::AppState, types::Response};
+use chrono::{Duration, Utc};
+use db::Db;
+use serde_json::json;
+use uuid::Uuid;
+
+fn policy() -> LoginPolicy {
+    LoginPolicy {
+        free_attempts: 2,
+        base_delay: Duration::seconds(1),
+        max_delay: Duration::seconds(4),
+        lockout_after: 6,
+        lockout: Duration::minutes(15),
+        forget_after: Duration::minutes(10),
+    }
+}
+
+fn random_email() -> String {
+    format!("{}@login-limiter.test", Uuid::new_v4())
+}
+
+async fn forget(db: &Db, email: &str) {
+    sqlx::query("DELETE FROM users WHERE email = $1").bind(email).execute(&db.pool).await.unwrap();
+    sqlx::query("DELETE FROM login_failures WHERE email = $1").bind(email).execute(&db.pool).await.unwrap();
+    sqlx::query("DELETE FROM login_throttle WHERE key = $1").bind(format!("account:{email}")).execute(&db.pool).await.unwrap();
+}
+
+#[actix_web::test]
+async fn waits_double_after_the_free_attempts_up_to_a_lockout() {
+    let policy = policy();
+    let waits: Vec<_> = (1..=7).map(|failures| policy.lock_for(failures).map(|wait| wait.num_seconds())).collect();
+    assert_eq!(waits, vec![None, None, Some(1), Some(2), Some(4), Some(15 * 60), Some(15 * 60)]);
+    assert_eq!(LoginPolicy::per_account().lock_for(u32::MAX), Some(Duration::minutes(15)));
+    assert_eq!(LoginPolicy::per_ip().lock_for(LoginPolicy::per_account().lockout_after), None);
+}
+
+// the same run against either store
+async fn locks_and_releases(limiter: LoginLimiter, db: &Db) {
+    let (email, ip): (String, IpAddr) = (random_email(), "203.0.113.7".parse().unwrap());
+    let mut now = Utc::now();
+    for _ in 0..2 {
+        limiter.check(&email, Some(ip), now).await.unwrap();
+        limiter.failed(db, &email, Some(ip), &AuthError::WrongPassword, now).await.unwrap();
+    }
+    limiter.check(&email, Some(ip), now).await.unwrap();
+    limiter.failed(db, &email, Some(ip), &AuthError::WrongPassword, now).await.unwrap();
+    assert_eq!(limiter.check(&email, Some(ip), now).await, Err(AuthError::TooManyAttempts(1)));
+    // the email is matched without case, another address does not help
+    assert!(limiter.check(&email.to_uppercase(), None, now).await.is_err());
+
+    now += Duration::seconds(1);
+    limiter.check(&email, Some(ip), now).await.unwrap();
+    limiter.failed(db, &email, Some(ip), &AuthError::WrongPassword, now).await.unwrap();
+    assert_eq!(limiter.check(&email, Some(ip), now).await, Err(AuthError::TooManyAttempts(2)));
+
+    // a success starts the account over, but the address keeps its count
+    now += Duration::seconds(2);
+    limiter.succeeded(&email).await.unwrap();
+    let other = random_email();
+    limiter.failed(db, &other, Some(ip), &AuthError::UnknownUser, now).await.unwrap();
+    assert_eq!(limiter.check(&random_email(), Some(ip), now).await, Err(AuthError::TooManyAttempts(4)));
+    limiter.check(&email, None, now).await.unwrap();
+
+    // a quiet spell forgets the run
+    now += Duration::minutes(11);
+    limiter.failed(db, &other, Some(ip), &AuthError::UnknownUser, now).await.unwrap();
+    limiter.check(&other, Some(ip), now).await.unwrap();
+
+    let audited = db.login_failures(&email).await.unwrap();
+    assert_eq!(audited.iter().filter(|f| f.ip == Some(ip.to_string()) && f.reason == "wrong password").count(), 4);
+    sqlx::query("DELETE FROM login_throttle WHERE key = $1").bind(format!("ip:{ip}")).execute(&db.pool).await.unwrap();
+    forget(db, &email).await;
+    forget(db, &other).await;
+}
+
+#[actix_web::test]
+async fn memory_store_backs_off_and_forgets() {
+    let db = Db::new().await.expect("tests need the database");
+    locks_and_releases(LoginLimiter::in_memory().with_policies(policy(), policy()), &db).await;
+}
+
+#[actix_web::test]
+async fn postgres_store_backs_off_and_forgets() {
+    let db = Db::new().await.expect("tests need the database");
+    locks_and_releases(LoginLimiter::new(PgAttemptStore::new(db.clone())).with_policies(policy(), policy()), &db).await;
+}
+
+#[actix_web::test]
+async fn signin_locks_the_account_even_for_the_right_password() {
+    let per_account = LoginPolicy { free_attempts: 3, lockout_after: 3, ..policy() };
+    let login_limiter = Arc::new(LoginLimiter::in_memory().with_policies(per_account, LoginPolicy::per_ip()));
+    let state = web::Data::new(AppState { login_limiter, ..common::state(common::database().await) });
+    let app = test::init_service(App::new().app_data(state.clone()).configure(routes)).await;
+    let email = random_email();
+    let signin = |password: &str| {
+        test::TestRequest::post()
+            .uri("/signin")
+            .peer_addr("198.51.100.1:4000".parse().unwrap())
+            .set_json(json!({ "email": email, "password": password }))
+            .to_request()
+    };
+    let req = test::TestRequest::post().uri("/signup").set_json(json!({ "email": email, "password": "correct horse" })).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+    for _ in 0..3 {
+        assert_eq!(test::call_service(&app, signin("wrong horse")).await.status(), StatusCode::UNAUTHORIZED);
+    }
+    let res: ServiceResponse = test::call_service(&app, signin("correct horse")).await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.headers().get(RETRY_AFTER).unwrap(), "900");
+    let body: Response = test::read_body_json(res).await;
+    assert_eq!(body.error, "Too many sign-in attempts, try again later");
+
+    let reasons: Vec<String> = state.db.login_failures(&email).await.unwrap().into_iter().map(|f| f.reason).collect();
+    assert_eq!(reasons, vec!["wrong password", "wrong password", "wrong password", "too many attempts, retry in 900s"]);
+    forget(&state.db, &email).await;
+}
@@ -0,0 +1,119 @@
+// Model-checked interleavings of RingBuffer, only built with the loom cfg:
+//
+//   RUSTFLAGS="--cfg backend_loom" cargo test -p backend --release --test loom_ring_buffer
+//
+// Loom runs every test body under all thread schedules (bounded by LOOM_MAX_PREEMPTIONS) and
+// fails on data races on the slots, leaked loom Arcs and deadlocks. Keep the rings tiny, the
+// number of schedules grows very quickly with every extra operation.
+#![cfg(backend_loom)]
+
+use backend::RingBuffer;
+use loom::{sync::Arc, thread};
+
+fn push(ring: &RingBuffer<u64>, item: u64) {
+    let mut item = item;
+    while let Err(back) = ring.try_push(item) {
+        item = back;
+        thread::yield_now();
+    }
+}
+
+#[test]
+fn push_and_pop_hand_over_items_in_order() {
+    loom::model(|| {
+        let ring = Arc::new(RingBuffer::<u64>::new(2));
+        let producer = Arc::clone(&ring);
+        let writer = thread::spawn(move || {
+            push(&producer, 1);
+            push(&producer, 2);
+        });
+
+        let mut seen = Vec::new();
+        while seen.len() < 2 {
+            match ring.try_pop() {
+                Some(item) => seen.push(item),
+                None => thread::yield_now(),
+            }
+        }
+        writer.join().unwrap();
+        assert_eq!(seen, vec![1, 2]);
+        assert!(ring.is_empty());
+    });
+}
+
+#[test]
+fn drain_batch_sees_a_prefix_of_what_was_pushed() {
+    loom::model(|| {
+        let ring = Arc::new(RingBuffer::<u64>::new(4));
+        let producer = Arc::clone(&ring);
+        let writer = thread::spawn(move || {
+            for item in 0..3 {
+                push(&producer, item);
+            }
+        });
+
+        let first = ring.drain_batch(4);
+        writer.join().unwrap();
+        let rest = ring.drain_batch(4);
+        let all: Vec<u64> = first.into_iter().chain(rest).collect();
+        assert_eq!(all, vec![0, 1, 2]);
+    });
+}
+
+#[test]
+fn force_push_and_drain_batch_never_share_an_item() {
+    loom::model(|| {
+        let ring = Arc::new(RingBuffer::<Arc<u64>>::new(2));
+        ring.try_push(Arc::new(0)).unwrap();
+
+        let producer = Arc::clone(&ring);
+        let writer = thread::spawn(move || producer.force_push(Arc::new(1)).into_iter().map(|item| *item).collect::<Vec<_>>());
+
+        let read: Vec<u64> = ring.drain_batch(2).into_iter().map(|item| *item).collect();
+        let evicted = writer.join().unwrap();
+        let left: Vec<u64> = ring.drain_batch(2).into_iter().map(|item| *item).collect();
+
+        // 0 ends up with exactly one of consumer and producer, 1 is never lost
+        let mut all: Vec<u64> = read.iter().chain(&evicted).chain(&left).copied().collect();
+        all.sort();
+        assert_eq!(all, vec![0, 1]);
+        assert_eq!(ring.stats().dropped, evicted.len() as u64);
+    });
+}
+
+// the consumer may empty the ring between force_push's failed push and its eviction
+#[test]
+fn force_push_does_not_evict_from_a_ring_the_consumer_just_emptied() {
+    loom::model(|| {
+        let ring = Arc::new(RingBuffer::<u64>::new(2));
+        ring.try_push(0).unwrap();
+        let producer = Arc::clone(&ring);
+        let writer = thread::spawn(move || producer.force_push(1));
+
+        let read = ring.drain_batch(2);
+        let evicted = writer.join().unwrap();
+        let left = ring.drain_batch(2);
+        let mut all: Vec<u64> = read.iter().chain(&evicted).chain(&left).copied().collect();
+        all.sort();
+        assert_eq!(all, vec![0, 1]);
+    });
+}
+
+#[test]
+fn unread_items_are_dropped_with_the_ring() {
+    loom::model(|| {
+        let item = Arc::new(7u64);
+        let ring = Arc::new(RingBuffer::<Arc<u64>>::new(4));
+        let producer = Arc::clone(&ring);
+        let shared = Arc::clone(&item);
+        let writer = thread::spawn(move || {
+            producer.try_push(Arc::clone(&shared)).unwrap();
+            producer.try_push(shared).unwrap();
+        });
+
+        drop(ring.try_pop());
+        writer.join().unwrap();
+        drop(ring);
+        assert_eq!(Arc::strong_count(&item), 1);
+    });
+}
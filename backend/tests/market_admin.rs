@@ -0,0 +1,195 @@
+use std::{
+    fs,
+    sync::{Arc, mpsc},
+};
+
+use backend::{Instrument, Journal, LimitOrder, MatchingEngine, Order, OrderId, RingBuffer, SequentialIdGenerator, SnapshotStore, UserId, types::{DEFAULT_MAX_OPEN_ORDERS, Event, EventEnvelope, OrderBookMessage, OrderResponse, OrderStatus, Priority, RiskLimits, Side}};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+const SEED: u64 = 17;
+
+type Reply = oneshot::Receiver<Result<OrderResponse, String>>;
+
+fn id(n: u64) -> OrderId {
+    Uuid::from_u64_pair(SEED, n)
+}
+
+fn engine(ring: &Arc<RingBuffer<EventEnvelope>>) -> MatchingEngine {
+    MatchingEngine::new(Arc::clone(ring), Instrument::new(dec!(0.01), dec!(0.001))).with_id_generator(SequentialIdGenerator::new(SEED))
+}
+
+fn place(user_id: UserId, side: Side, price: i64, quantity: u64, leverage: Decimal) -> (OrderBookMessage, Reply) {
+    let (tx, rx) = oneshot::channel();
+    let order = Order::limit_order(LimitOrder { user_id, side, price, quantity, leverage });
+    (OrderBookMessage::PlaceOrder { order, priority: Priority::Normal, responder: Some(tx) }, rx)
+}
+
+fn admin(command: impl FnOnce(Option<oneshot::Sender<Result<OrderResponse, String>>>) -> OrderBookMessage) -> (OrderBookMessage, Reply) {
+    let (tx, rx) = oneshot::channel();
+    (command(Some(tx)), rx)
+}
+
+// one command per run, admin commands are critical and would otherwise jump the queue
+fn step(engine: &mut MatchingEngine, (message, reply): (OrderBookMessage, Reply)) -> Result<OrderResponse, String> {
+    let (tx, rx) = mpsc::sync_channel(1);
+    tx.send(message).unwrap();
+    drop(tx);
+    engine.run(rx);
+    reply.blocking_recv().unwrap()
+}
+
+#[test]
+fn halted_market_rejects_new_orders_but_still_cancels() {
+    let ring = Arc::new(RingBuffer::<EventEnvelope>::new(64));
+    let mut engine = engine(&ring);
+    let user = Uuid::new_v4();
+    assert!(step(&mut engine, place(user, Side::Buy, 100, 1, dec!(1))).is_ok());
+
+    let halted = step(&mut engine, admin(|responder| OrderBookMessage::HaltMarket { reason: "maintenance".to_string(), responder }));
+    assert!(matches!(halted, Ok(OrderResponse::MarketStatus { halted: Some(ref reason), .. }) if reason == "maintenance"));
+    assert_eq!(engine.market_halt(), Some("maintenance"));
+    assert_eq!(step(&mut engine, place(user, Side::Buy, 101, 1, dec!(1))).err().unwrap(), "market halted: maintenance");
+
+    let (tx, rx) = oneshot::channel();
+    let cancelled = step(&mut engine, (OrderBookMessage::CancelOrder { order_id: id(1), user_id: user, responder: Some(tx) }, rx));
+    assert!(matches!(cancelled, Ok(OrderResponse::CanceledOrder { .. })));
+
+    let resumed = step(&mut engine, admin(|responder| OrderBookMessage::ResumeMarket { responder }));
+    assert!(matches!(resumed, Ok(OrderResponse::MarketStatus { halted: None, .. })));
+    assert!(matches!(step(&mut engine, place(user, Side::Buy, 101, 1, dec!(1))), Ok(OrderResponse::PlacedOrder { .. })));
+}
+
+#[test]
+fn force_cancel_removes_every_order_of_one_user() {
+    let ring = Arc::new(RingBuffer::<EventEnvelope>::new(64));
+    let mut engine = engine(&ring);
+    let (target, other) = (Uuid::new_v4(), Uuid::new_v4());
+    step(&mut engine, place(target, Side::Buy, 100, 1, dec!(1))).unwrap();
+    step(&mut engine, place(other, Side::Buy, 99, 1, dec!(1))).unwrap();
+    step(&mut engine, place(target, Side::Sell, 110, 1, dec!(1))).unwrap();
+    ring.drain_batch(64);
+
+    let reply = step(&mut engine, admin(|responder| OrderBookMessage::ForceCancel { user_id: target, responder }));
+    assert!(matches!(reply, Ok(OrderResponse::CancelledAll { user_id, ref order_ids }) if user_id == target && *order_ids == vec![id(1), id(3)]));
+    assert!(engine.order_book().get_order(&id(1)).is_none());
+    assert!(engine.order_book().get_order(&id(2)).is_some());
+    assert!(engine.order_book().get_order(&id(3)).is_none());
+
+    let events: Vec<Event> = ring.drain_batch(64).into_iter().map(|e| e.event).collect();
+    assert!(matches!(&events[..], [
+        Event::OrderCancelled { order_id: a, .. },
+        Event::OrderCancelled { order_id: b, .. },
+    ] if *a == id(1) && *b == id(3)));
+
+    // nothing left to cancel is not an error
+    let again = step(&mut engine, admin(|responder| OrderBookMessage::ForceCancel { user_id: target, responder }));
+    assert!(matches!(again, Ok(OrderResponse::CancelledAll { ref order_ids, .. }) if order_ids.is_empty()));
+}
+
+#[test]
+fn risk_limits_apply_to_new_orders() {
+    let ring = Arc::new(RingBuffer::<EventEnvelope>::new(64));
+    let mut engine = engine(&ring);
+    let user = Uuid::new_v4();
+    assert!(step(&mut engine, place(user, Side::Buy, 100, 50, dec!(100))).is_ok());
+
+    let limits = RiskLimits { max_leverage: dec!(10), max_order_lots: Some(5), ..RiskLimits::default() };
+    let reply = step(&mut engine, admin(|responder| OrderBookMessage::SetRiskLimits { limits, responder }));
+    assert!(matches!(reply, Ok(OrderResponse::MarketStatus { limits: set, .. }) if set == limits));
+    assert_eq!(engine.risk_limits(), limits);
+
+    assert_eq!(step(&mut engine, place(user, Side::Buy, 100, 1, dec!(20))).err().unwrap(), "Invalid leverage (1-10x)");
+    assert!(step(&mut engine, place(user, Side::Buy, 100, 6, dec!(1))).is_err());
+    assert!(matches!(step(&mut engine, place(user, Side::Buy, 100, 5, dec!(10))), Ok(OrderResponse::PlacedOrder { .. })));
+    // resting orders from before are left alone
+    assert!(engine.order_book().get_order(&id(1)).is_some());
+}
+
+#[test]
+fn admin_state_survives_a_restart() {
+    let dir = std::env::temp_dir().join(format!("perp-admin-{}", Uuid::new_v4()));
+    let ring = Arc::new(RingBuffer::<EventEnvelope>::new(64));
+    let limits = RiskLimits { max_leverage: dec!(20), max_order_lots: None, max_open_orders: Some(3) };
+    {
+        let mut live = engine(&ring)
+            .with_journal(Journal::open(dir.join("engine.journal")).unwrap())
+            .with_snapshots(SnapshotStore::new(dir.join("snapshots"), 3).unwrap(), 2);
+        step(&mut live, admin(|responder| OrderBookMessage::SetRiskLimits { limits, responder })).unwrap();
+        step(&mut live, place(Uuid::new_v4(), Side::Buy, 100, 1, dec!(1))).unwrap();
+        step(&mut live, admin(|responder| OrderBookMessage::HaltMarket { reason: "incident".to_string(), responder })).unwrap();
+    }
+
+    // from the journal alone, and from the snapshot taken after the second command plus the tail
+    let mut replayed = engine(&Arc::new(RingBuffer::new(16))).with_journal(Journal::open(dir.join("engine.journal")).unwrap());
+    assert_eq!(replayed.recover().unwrap(), 3);
+    let mut restored = engine(&Arc::new(RingBuffer::new(16)))
+        .with_journal(Journal::open(dir.join("engine.journal")).unwrap())
+        .with_snapshots(SnapshotStore::new(dir.join("snapshots"), 3).unwrap(), 2);
+    assert_eq!(restored.recover().unwrap(), 1);
+    for engine in [&replayed, &restored] {
+        assert_eq!(engine.market_halt(), Some("incident"));
+        assert_eq!(engine.risk_limits(), limits);
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn open_orders_are_capped_per_user() {
+    let ring = Arc::new(RingBuffer::<EventEnvelope>::new(64));
+    let mut engine = engine(&ring);
+    let (user, other) = (Uuid::new_v4(), Uuid::new_v4());
+    assert_eq!(engine.risk_limits().max_open_orders, Some(DEFAULT_MAX_OPEN_ORDERS));
+    let limits = RiskLimits { max_open_orders: Some(2), ..RiskLimits::default() };
+    step(&mut engine, admin(|responder| OrderBookMessage::SetRiskLimits { limits, responder })).unwrap();
+
+    step(&mut engine, place(user, Side::Buy, 100, 1, dec!(1))).unwrap();
+    step(&mut engine, place(user, Side::Buy, 99, 1, dec!(1))).unwrap();
+    assert_eq!(step(&mut engine, place(user, Side::Buy, 98, 1, dec!(1))).err().unwrap(), "too many open orders (max 2)");
+    // the cap is per user
+    step(&mut engine, place(other, Side::Buy, 98, 1, dec!(1))).unwrap();
+
+    // a fill frees a slot
+    step(&mut engine, place(other, Side::Sell, 100, 1, dec!(1))).unwrap();
+    assert!(matches!(step(&mut engine, place(user, Side::Buy, 98, 1, dec!(1))), Ok(OrderResponse::PlacedOrder { .. })));
+}
+
+#[test]
+fn orders_at_the_open_order_cap_still_fill() {
+    let ring = Arc::new(RingBuffer::<EventEnvelope>::new(64));
+    let mut engine = engine(&ring);
+    let (user, other) = (Uuid::new_v4(), Uuid::new_v4());
+    let limits = RiskLimits { max_open_orders: Some(2), ..RiskLimits::default() };
+    step(&mut engine, admin(|responder| OrderBookMessage::SetRiskLimits { limits, responder })).unwrap();
+    step(&mut engine, place(user, Side::Buy, 100, 1, dec!(1))).unwrap();
+    step(&mut engine, place(user, Side::Buy, 99, 1, dec!(1))).unwrap();
+    step(&mut engine, place(other, Side::Sell, 101, 1, dec!(1))).unwrap();
+    step(&mut engine, place(other, Side::Sell, 102, 1, dec!(1))).unwrap();
+    ring.drain_batch(64);
+
+    // filled completely, nothing would rest
+    let filled = step(&mut engine, place(user, Side::Buy, 101, 1, dec!(1)));
+    assert!(matches!(filled, Ok(OrderResponse::PlacedOrder { status: OrderStatus::FullyFilled, .. })));
+
+    // filled in part, the rest is dropped rather than resting as a third order
+    let partly = step(&mut engine, place(user, Side::Buy, 102, 3, dec!(1)));
+    assert!(matches!(partly, Ok(OrderResponse::PlacedOrder { order_id, status: OrderStatus::Cancelled, filled, remaining })
+        if order_id == id(6) && filled == dec!(0.001) && remaining == dec!(0.002)));
+    assert!(engine.order_book().get_order(&id(6)).is_none());
+    assert_eq!(engine.order_book().user_orders[&user].len(), 2);
+
+    // nothing to fill against, the whole order is refused
+    assert_eq!(step(&mut engine, place(user, Side::Buy, 102, 1, dec!(1))).err().unwrap(), "too many open orders (max 2)");
+    // the dropped rest is cancelled on the event stream too, after the fill it did get
+    let events: Vec<Event> = ring.drain_batch(64).into_iter().map(|e| e.event).collect();
+    assert!(matches!(&events[..], [
+        Event::Fill(_),
+        Event::Fill(partly),
+        Event::OrderCancelled { order_id: dropped, user_id, .. },
+        Event::OrderRejected { order_id, reason, .. },
+    ] if partly.taker_order_id == id(6) && *dropped == id(6) && *user_id == user
+        && *order_id == id(7) && reason == "too many open orders (max 2)"));
+}
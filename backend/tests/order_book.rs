@@ -0,0 +1,314 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use backend::{LimitOrder, Lots, MarketOrder, Order, OrderBook, OrderHandle, OrderId, Ticks, UserId, types::Side};
+use rust_decimal_macros::dec;
+use uuid::Uuid;
+
+// (price, orders in time priority with their remaining quantity, level total)
+type Levels = Vec<(Ticks, Vec<(OrderId, Lots)>, Lots)>;
+
+// The previous VecDeque based book, kept as the reference the linked list levels must agree with.
+#[derive(Default)]
+struct ReferenceBook {
+    bids: BTreeMap<Ticks, VecDeque<OrderId>>,
+    asks: BTreeMap<Ticks, VecDeque<OrderId>>,
+    orders: HashMap<OrderId, (UserId, Side, Ticks, Lots)>,
+}
+
+impl ReferenceBook {
+    fn side(&mut self, side: Side) -> &mut BTreeMap<Ticks, VecDeque<OrderId>> {
+        match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        }
+    }
+
+    fn insert(&mut self, order_id: OrderId, user_id: UserId, side: Side, price: Ticks, remaining: Lots) {
+        self.side(side).entry(price).or_default().push_back(order_id);
+        self.orders.insert(order_id, (user_id, side, price, remaining));
+    }
+
+    fn cancel(&mut self, order_id: &OrderId, user_id: &UserId) -> Result<Lots, ()> {
+        let (owner, side, price, remaining) = *self.orders.get(order_id).ok_or(())?;
+        if &owner != user_id {
+            return Err(());
+        }
+        self.orders.remove(order_id);
+        let level = self.side(side).get_mut(&price).unwrap();
+        level.retain(|id| id != order_id);
+        if level.is_empty() {
+            self.side(side).remove(&price);
+        }
+        Ok(remaining)
+    }
+
+    // (maker, price, qty) per fill and the taker remainder
+    fn take(&mut self, side: Side, limit: Option<Ticks>, mut quantity: Lots) -> (Vec<(OrderId, Ticks, Lots)>, Lots) {
+        let mut fills = Vec::new();
+        let opposite = match side {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        };
+        while quantity > 0 {
+            let best = match side {
+                Side::Buy => self.asks.keys().next().cloned(),
+                Side::Sell => self.bids.keys().next_back().cloned(),
+            };
+            let Some(best) = best else { break };
+            let crosses = match (side, limit) {
+                (_, None) => true,
+                (Side::Buy, Some(limit)) => limit >= best,
+                (Side::Sell, Some(limit)) => limit <= best,
+            };
+            if !crosses {
+                break;
+            }
+            let makers: Vec<OrderId> = self.side(opposite)[&best].iter().cloned().collect();
+            for maker_id in makers {
+                if quantity == 0 {
+                    break;
+                }
+                let maker = self.orders.get_mut(&maker_id).unwrap();
+                let qty = maker.3.min(quantity);
+                maker.3 -= qty;
+                quantity -= qty;
+                fills.push((maker_id, best, qty));
+                if maker.3 == 0 {
+                    self.orders.remove(&maker_id);
+                    self.side(opposite).get_mut(&best).unwrap().retain(|id| id != &maker_id);
+                }
+            }
+            if self.side(opposite)[&best].is_empty() {
+                self.side(opposite).remove(&best);
+            }
+        }
+        (fills, quantity)
+    }
+
+    fn levels(&self, side: Side) -> Levels {
+        let book = match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+        book.iter()
+            .map(|(price, ids)| {
+                let orders: Vec<(OrderId, Lots)> = ids.iter().map(|id| (*id, self.orders[id].3)).collect();
+                let total = orders.iter().map(|(_, qty)| qty).sum();
+                (*price, orders, total)
+            })
+            .collect()
+    }
+}
+
+fn levels(book: &OrderBook, side: Side) -> Levels {
+    let levels = match side {
+        Side::Buy => &book.bids,
+        Side::Sell => &book.asks,
+    };
+    levels
+        .iter()
+        .map(|(price, level)| {
+            let orders: Vec<(OrderId, Lots)> = book.iter_level(level).map(|o| (o.order_id, o.remaining())).collect();
+            assert_eq!(level.len, orders.len(), "level length out of sync at {price}");
+            (*price, orders, level.total_qty)
+        })
+        .collect()
+}
+
+fn id_at(book: &OrderBook, handle: Option<OrderHandle>) -> Option<OrderId> {
+    handle.map(|handle| book.orders.get(handle).unwrap().order.order_id)
+}
+
+fn limit(user_id: UserId, side: Side, price: Ticks, quantity: Lots) -> Order {
+    Order::limit_order(LimitOrder { user_id, side, price, quantity, leverage: dec!(1) }).with_id(Uuid::new_v4())
+}
+
+fn market(user_id: UserId, side: Side, quantity: Lots) -> Order {
+    Order::market_order(MarketOrder { user_id, side, quantity, leverage: dec!(1) }).with_id(Uuid::new_v4())
+}
+
+// xorshift, enough to drive reproducible op sequences without extra dependencies
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+fn place(book: &mut OrderBook, reference: &mut ReferenceBook, order: Order) {
+    let side = order.side;
+    let limit = order.price;
+    let (ref_fills, ref_left) = reference.take(side, limit, order.quantity);
+    let (fills, rest) = book.match_order(order, 0);
+
+    let got: Vec<(OrderId, Ticks, Lots)> = fills.iter().map(|f| (f.maker_order_id, f.price, f.quantity)).collect();
+    assert_eq!(got, ref_fills);
+
+    if let Some(rest) = rest {
+        assert_eq!(rest.remaining(), ref_left);
+        reference.insert(rest.order_id, rest.user_id, rest.side, rest.price.unwrap(), rest.remaining());
+        book.insert_order(rest);
+    } else if limit.is_some() {
+        assert_eq!(ref_left, 0);
+    }
+}
+
+#[test]
+fn matches_reference_book_on_random_sequences() {
+    for seed in 1..=20u64 {
+        let mut rng = Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        let users: Vec<UserId> = (0..4).map(|_| Uuid::new_v4()).collect();
+        let mut book = OrderBook::new();
+        let mut reference = ReferenceBook::default();
+        let mut placed: Vec<(OrderId, UserId)> = Vec::new();
+
+        for _ in 0..2_000 {
+            let user = users[rng.below(users.len() as u64) as usize];
+            let side = if rng.below(2) == 0 { Side::Buy } else { Side::Sell };
+            match rng.below(10) {
+                0..=5 => {
+                    let order = limit(user, side, 95 + rng.below(10) as Ticks, 1 + rng.below(20));
+                    placed.push((order.order_id, user));
+                    place(&mut book, &mut reference, order);
+                }
+                6 => place(&mut book, &mut reference, market(user, side, 1 + rng.below(40))),
+                _ if !placed.is_empty() => {
+                    let (order_id, owner) = placed[rng.below(placed.len() as u64) as usize];
+                    // sometimes try to cancel someone else's order
+                    let user = if rng.below(5) == 0 { user } else { owner };
+                    let expected = reference.cancel(&order_id, &user);
+                    let got = book.cancel_order(&order_id, &user).map(|o| o.remaining());
+                    assert_eq!(got.ok(), expected.ok());
+                }
+                _ => {}
+            }
+
+            assert_eq!(levels(&book, Side::Buy), reference.levels(Side::Buy));
+            assert_eq!(levels(&book, Side::Sell), reference.levels(Side::Sell));
+            assert_eq!(book.orders.len(), reference.orders.len());
+            assert_eq!(book.order_index.len(), reference.orders.len());
+            assert_eq!(book.best_bid, reference.bids.keys().next_back().cloned());
+            assert_eq!(book.best_ask, reference.asks.keys().next().cloned());
+        }
+    }
+}
+
+#[test]
+fn cancel_unlinks_head_middle_and_tail() {
+    let user = Uuid::new_v4();
+    let mut book = OrderBook::new();
+    let ids: Vec<OrderId> = (0..5)
+        .map(|_| {
+            let order = limit(user, Side::Buy, 100, 10);
+            let id = order.order_id;
+            book.insert_order(order);
+            id
+        })
+        .collect();
+
+    for id in [ids[2], ids[0], ids[4]] {
+        book.cancel_order(&id, &user).unwrap();
+    }
+
+    let level = &book.bids[&100];
+    let left: Vec<OrderId> = book.iter_level(level).map(|o| o.order_id).collect();
+    assert_eq!(left, vec![ids[1], ids[3]]);
+    assert_eq!(id_at(&book, level.head), Some(ids[1]));
+    assert_eq!(id_at(&book, level.tail), Some(ids[3]));
+    assert_eq!(level.total_qty, 20);
+
+    for id in [ids[1], ids[3]] {
+        book.cancel_order(&id, &user).unwrap();
+    }
+    assert!(book.bids.is_empty());
+    assert!(book.user_orders.is_empty());
+    assert_eq!(book.best_bid, None);
+}
+
+#[test]
+fn partially_filled_maker_keeps_time_priority() {
+    let maker = Uuid::new_v4();
+    let taker = Uuid::new_v4();
+    let mut book = OrderBook::new();
+    let first = limit(maker, Side::Sell, 100, 10);
+    let second = limit(maker, Side::Sell, 100, 10);
+    let (first_id, second_id) = (first.order_id, second.order_id);
+    book.insert_order(first);
+    book.insert_order(second);
+
+    let (fills, rest) = book.match_order(market(taker, Side::Buy, 4), 0);
+    assert!(rest.is_none());
+    assert_eq!(fills.len(), 1);
+    assert_eq!(fills[0].maker_order_id, first_id);
+
+    let level = &book.asks[&100];
+    assert_eq!(id_at(&book, level.head), Some(first_id));
+    assert_eq!(level.total_qty, 16);
+
+    // cancelling the partially filled head only takes its remaining quantity off the level
+    let cancelled = book.cancel_order(&first_id, &maker).unwrap();
+    assert_eq!(cancelled.remaining(), 6);
+    let level = &book.asks[&100];
+    assert_eq!(id_at(&book, level.head), Some(second_id));
+    assert_eq!(level.total_qty, 10);
+}
+
+#[test]
+fn cancel_unknown_order_is_an_error() {
+    let mut book = OrderBook::new();
+    assert!(book.cancel_order(&Uuid::new_v4(), &Uuid::new_v4()).is_err());
+}
+
+#[test]
+fn market_order_sweeps_levels_and_never_rests() {
+    let maker = Uuid::new_v4();
+    let mut book = OrderBook::new();
+    for price in [101, 102, 103] {
+        book.insert_order(limit(maker, Side::Sell, price, 5));
+    }
+
+    let order = market(Uuid::new_v4(), Side::Buy, 12);
+    let (fills, rest) = book.match_order(order, 0);
+
+    assert!(rest.is_none());
+    let prices: Vec<Ticks> = fills.iter().map(|f| f.price).collect();
+    assert_eq!(prices, vec![101, 102, 103]);
+    assert_eq!(book.asks.len(), 1);
+    assert_eq!(book.asks[&103].total_qty, 3);
+    assert_eq!(book.best_ask, Some(103));
+}
+
+#[test]
+fn filled_and_cancelled_orders_free_their_slots() {
+    let user = Uuid::new_v4();
+    let mut book = OrderBook::new();
+    for _ in 0..100 {
+        let order = limit(user, Side::Sell, 100, 1);
+        let id = order.order_id;
+        book.insert_order(order);
+        book.cancel_order(&id, &user).unwrap();
+        book.insert_order(limit(user, Side::Sell, 101, 1));
+        book.match_order(market(Uuid::new_v4(), Side::Buy, 1), 0);
+    }
+    assert!(book.orders.is_empty());
+    assert!(book.order_index.is_empty());
+
+    // slots freed above are handed out again instead of growing the arena
+    let handles: Vec<OrderHandle> = (0..2)
+        .map(|_| {
+            let order = limit(user, Side::Buy, 90, 1);
+            let id = order.order_id;
+            book.insert_order(order);
+            book.order_index[&id]
+        })
+        .collect();
+    assert!(handles.iter().all(|h| h.index() < 2));
+}
@@ -0,0 +1,284 @@
+use std::collections::{HashMap, HashSet};
+
+use backend::{Fill, LimitOrder, Lots, MarketOrder, Order, OrderBook, OrderId, PriceLevel, Ticks, UserId, types::Side};
+use proptest::prelude::*;
+use rust_decimal_macros::dec;
+use uuid::Uuid;
+
+const USERS: usize = 4;
+
+#[derive(Debug, Clone)]
+enum Op {
+    Limit { user: usize, buy: bool, price: Ticks, quantity: Lots },
+    Market { user: usize, buy: bool, quantity: Lots },
+    // index into the orders placed so far, wrapped to the current count
+    Cancel { pick: usize, by_owner: bool },
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        5 => (0..USERS, any::<bool>(), 90..110i64, 1..50u64)
+            .prop_map(|(user, buy, price, quantity)| Op::Limit { user, buy, price, quantity }),
+        2 => (0..USERS, any::<bool>(), 1..120u64)
+            .prop_map(|(user, buy, quantity)| Op::Market { user, buy, quantity }),
+        3 => (any::<usize>(), prop::bool::weighted(0.8))
+            .prop_map(|(pick, by_owner)| Op::Cancel { pick, by_owner }),
+    ]
+}
+
+fn side(buy: bool) -> Side {
+    if buy { Side::Buy } else { Side::Sell }
+}
+
+// Naive matcher: a flat list of resting orders, best one found by a full scan on every fill.
+// Slow but obviously price-time ordered, which is what the real book has to agree with.
+#[derive(Default)]
+struct ReferenceMatcher {
+    resting: Vec<Resting>,
+    arrival: u64,
+}
+
+struct Resting {
+    order_id: OrderId,
+    user_id: UserId,
+    buy: bool,
+    price: Ticks,
+    remaining: Lots,
+    arrival: u64,
+}
+
+impl ReferenceMatcher {
+    fn best_against(&self, buy: bool, limit: Option<Ticks>) -> Option<usize> {
+        self.resting
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| r.buy != buy)
+            .filter(|(_, r)| match limit {
+                None => true,
+                Some(limit) if buy => r.price <= limit,
+                Some(limit) => r.price >= limit,
+            })
+            .min_by_key(|(_, r)| (if buy { r.price } else { -r.price }, r.arrival))
+            .map(|(idx, _)| idx)
+    }
+
+    // fills as (maker, price, qty) and the unfilled rest of the taker
+    fn submit(&mut self, order_id: OrderId, user_id: UserId, buy: bool, limit: Option<Ticks>, mut quantity: Lots) -> (Vec<(OrderId, Ticks, Lots)>, Lots) {
+        let mut fills = Vec::new();
+        while quantity > 0 {
+            let Some(idx) = self.best_against(buy, limit) else { break };
+            let maker = &mut self.resting[idx];
+            let qty = maker.remaining.min(quantity);
+            maker.remaining -= qty;
+            quantity -= qty;
+            fills.push((maker.order_id, maker.price, qty));
+            if maker.remaining == 0 {
+                self.resting.remove(idx);
+            }
+        }
+        if let (Some(price), true) = (limit, quantity > 0) {
+            self.arrival += 1;
+            self.resting.push(Resting { order_id, user_id, buy, price, remaining: quantity, arrival: self.arrival });
+        }
+        (fills, quantity)
+    }
+
+    fn cancel(&mut self, order_id: &OrderId, user_id: &UserId) -> Option<Lots> {
+        let idx = self.resting.iter().position(|r| &r.order_id == order_id && &r.user_id == user_id)?;
+        Some(self.resting.remove(idx).remaining)
+    }
+
+    fn resting_qty(&self) -> Lots {
+        self.resting.iter().map(|r| r.remaining).sum()
+    }
+}
+
+fn check_level(book: &OrderBook, level: &PriceLevel, side: Side) -> Result<Lots, TestCaseError> {
+    prop_assert!(!level.is_empty(), "empty level left in the book at {}", level.price);
+    let mut total = 0;
+    let mut count = 0;
+    for order in book.iter_level(level) {
+        prop_assert_eq!(order.price, Some(level.price));
+        prop_assert!(order.side == side);
+        prop_assert!(order.remaining() > 0);
+        total += order.remaining();
+        count += 1;
+    }
+    prop_assert_eq!(level.total_qty, total, "total_qty out of sync at {}", level.price);
+    prop_assert_eq!(level.len, count);
+    Ok(total)
+}
+
+// everything that must hold after any operation, returns the resting quantity
+fn check_invariants(book: &OrderBook) -> Result<Lots, TestCaseError> {
+    let mut resting = 0;
+    let mut count = 0;
+    for (side, levels) in [(Side::Buy, &book.bids), (Side::Sell, &book.asks)] {
+        for (price, level) in levels {
+            prop_assert_eq!(*price, level.price);
+            resting += check_level(book, level, side)?;
+            count += level.len;
+        }
+    }
+
+    prop_assert_eq!(book.best_bid, book.bids.keys().next_back().cloned());
+    prop_assert_eq!(book.best_ask, book.asks.keys().next().cloned());
+    if let (Some(bid), Some(ask)) = (book.best_bid, book.best_ask) {
+        prop_assert!(bid < ask, "crossed book: bid {} ask {}", bid, ask);
+    }
+
+    prop_assert_eq!(book.orders.len(), count);
+    prop_assert_eq!(book.order_index.len(), count);
+    for (handle, resting) in book.orders.iter() {
+        prop_assert_eq!(book.order_index.get(&resting.order.order_id), Some(&handle));
+    }
+
+    let mut seen = HashSet::new();
+    for (user_id, handles) in &book.user_orders {
+        prop_assert!(!handles.is_empty(), "empty user_orders entry kept");
+        for handle in handles {
+            let resting = book.orders.get(*handle);
+            prop_assert!(resting.is_some(), "user_orders points at a freed slot");
+            prop_assert_eq!(&resting.unwrap().order.user_id, user_id);
+            seen.insert(*handle);
+        }
+    }
+    prop_assert_eq!(seen.len(), count, "resting orders missing from user_orders");
+    Ok(resting)
+}
+
+fn check_fills(fills: &[Fill], taker_id: OrderId, buy: bool, limit: Option<Ticks>, last_seq: &mut u64) -> Result<(), TestCaseError> {
+    for fill in fills {
+        prop_assert!(fill.quantity > 0);
+        prop_assert_eq!(fill.taker_order_id, taker_id);
+        prop_assert!(fill.seq_no > *last_seq, "fill sequence went backwards");
+        *last_seq = fill.seq_no;
+        match limit {
+            Some(limit) if buy => prop_assert!(fill.price <= limit),
+            Some(limit) => prop_assert!(fill.price >= limit),
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+fn run(ops: Vec<Op>) -> Result<(), TestCaseError> {
+    let users: Vec<UserId> = (0..USERS).map(|_| Uuid::new_v4()).collect();
+    let mut book = OrderBook::new();
+    let mut reference = ReferenceMatcher::default();
+    let mut placed: Vec<(OrderId, UserId)> = Vec::new();
+    let mut last_seq = 0;
+    // per order remaining quantity, to follow every lot from placement to fill or cancel
+    let mut open: HashMap<OrderId, Lots> = HashMap::new();
+
+    for op in ops {
+        let before = check_invariants(&book)?;
+        match op {
+            Op::Limit { user, buy, price, quantity } => {
+                let order = Order::limit_order(LimitOrder { user_id: users[user], side: side(buy), price, quantity, leverage: dec!(1) }).with_id(Uuid::new_v4());
+                placed.push((order.order_id, order.user_id));
+                submit(&mut book, &mut reference, &mut open, &mut last_seq, order, before)?;
+            }
+            Op::Market { user, buy, quantity } => {
+                let order = Order::market_order(MarketOrder { user_id: users[user], side: side(buy), quantity, leverage: dec!(1) }).with_id(Uuid::new_v4());
+                submit(&mut book, &mut reference, &mut open, &mut last_seq, order, before)?;
+            }
+            Op::Cancel { pick, by_owner } => {
+                if placed.is_empty() {
+                    continue;
+                }
+                let (order_id, owner) = placed[pick % placed.len()];
+                let user_id = if by_owner { owner } else { users[pick % USERS] };
+                let expected = reference.cancel(&order_id, &user_id);
+                let got = book.cancel_order(&order_id, &user_id).ok().map(|o| o.remaining());
+                prop_assert_eq!(got, expected);
+                if let Some(left) = got {
+                    prop_assert_eq!(open.remove(&order_id), Some(left));
+                    prop_assert_eq!(check_invariants(&book)?, before - left);
+                }
+            }
+        }
+        prop_assert_eq!(check_invariants(&book)?, reference.resting_qty());
+    }
+    Ok(())
+}
+
+fn submit(book: &mut OrderBook, reference: &mut ReferenceMatcher, open: &mut HashMap<OrderId, Lots>, last_seq: &mut u64, order: Order, before: Lots) -> Result<(), TestCaseError> {
+    let (order_id, user_id, buy, limit, quantity) = (order.order_id, order.user_id, matches!(order.side, Side::Buy), order.price, order.quantity);
+    let (expected, expected_rest) = reference.submit(order_id, user_id, buy, limit, quantity);
+
+    let (fills, rest) = book.match_order(order, 0);
+    check_fills(&fills, order_id, buy, limit, last_seq)?;
+
+    let got: Vec<(OrderId, Ticks, Lots)> = fills.iter().map(|f| (f.maker_order_id, f.price, f.quantity)).collect();
+    prop_assert_eq!(&got, &expected);
+
+    // conservation: the taker's lots are either filled or resting, and makers lost exactly what was filled
+    let filled: Lots = fills.iter().map(|f| f.quantity).sum();
+    for fill in &fills {
+        let left = open.get_mut(&fill.maker_order_id).expect("fill against an order that is not open");
+        *left -= fill.quantity;
+        if *left == 0 {
+            open.remove(&fill.maker_order_id);
+        }
+    }
+    let rested = rest.as_ref().map(|o| o.remaining()).unwrap_or(0);
+    prop_assert!(filled + rested <= quantity);
+    if limit.is_some() {
+        prop_assert_eq!(filled + rested, quantity);
+        prop_assert_eq!(rested, expected_rest);
+    } else {
+        prop_assert!(rest.is_none(), "market order left resting");
+    }
+
+    if let Some(rest) = rest {
+        open.insert(order_id, rest.remaining());
+        book.insert_order(rest);
+    }
+    let after = check_invariants(book)?;
+    prop_assert_eq!(after, before - filled + rested);
+    prop_assert_eq!(after, open.values().sum::<Lots>());
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(256))]
+
+    #[test]
+    fn invariants_hold_and_match_reference(ops in prop::collection::vec(op(), 1..300)) {
+        run(ops)?;
+    }
+}
+
+// a tight band of prices keeps the book crossing often, which is where the bookkeeping is hardest
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(128))]
+
+    #[test]
+    fn invariants_hold_on_narrow_spread(
+        ops in prop::collection::vec(
+            prop_oneof![
+                (0..USERS, any::<bool>(), 99..101i64, 1..10u64)
+                    .prop_map(|(user, buy, price, quantity)| Op::Limit { user, buy, price, quantity }),
+                (any::<usize>(), any::<bool>()).prop_map(|(pick, by_owner)| Op::Cancel { pick, by_owner }),
+            ],
+            1..400,
+        )
+    ) {
+        run(ops)?;
+    }
+}
+
+#[test]
+fn reference_matcher_prefers_price_then_time() {
+    let mut reference = ReferenceMatcher::default();
+    let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+    let user = Uuid::new_v4();
+    reference.submit(a, user, false, Some(101), 5);
+    reference.submit(b, user, false, Some(100), 5);
+    reference.submit(c, user, false, Some(100), 5);
+
+    let (fills, rest) = reference.submit(Uuid::new_v4(), user, true, None, 12);
+    assert_eq!(fills, vec![(b, 100, 5), (c, 100, 5), (a, 101, 2)]);
+    assert_eq!(rest, 0);
+}
@@ -0,0 +1,43 @@
+use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version, password_hash::SaltString};
+use backend::{PasswordCheck, hash_password, verify_password};
+use db::User;
+use uuid::Uuid;
+
+fn user(password: Option<&str>, password_hash: Option<String>) -> User {
+    User { id: Uuid::new_v4(), email: "trader@example.com".to_string(), password: password.map(str::to_string), password_hash, role: "trader".to_string() }
+}
+
+#[test]
+fn hashed_passwords_verify() {
+    let hash = hash_password("correct horse").unwrap();
+    assert!(hash.starts_with("$argon2id$"));
+    let stored = user(None, Some(hash));
+    assert_eq!(verify_password(&stored, "correct horse"), PasswordCheck::Valid);
+    assert_eq!(verify_password(&stored, "correct horse "), PasswordCheck::Invalid);
+}
+
+#[test]
+fn every_hash_gets_its_own_salt() {
+    assert_ne!(hash_password("same").unwrap(), hash_password("same").unwrap());
+}
+
+#[test]
+fn legacy_plaintext_verifies_once_and_asks_for_a_rehash() {
+    let legacy = user(Some("hunter2"), None);
+    assert_eq!(verify_password(&legacy, "hunter2"), PasswordCheck::ValidNeedsRehash);
+    assert_eq!(verify_password(&legacy, "hunter3"), PasswordCheck::Invalid);
+    assert_eq!(verify_password(&legacy, ""), PasswordCheck::Invalid);
+}
+
+#[test]
+fn hashes_with_outdated_parameters_ask_for_a_rehash() {
+    let weak = Argon2::new(Algorithm::Argon2id, Version::V0x13, Params::new(8, 1, 1, None).unwrap());
+    let salt = SaltString::from_b64("c29tZXNhbHRzb21lc2FsdA").unwrap();
+    let hash = weak.hash_password(b"pw", &salt).unwrap().to_string();
+    assert_eq!(verify_password(&user(None, Some(hash)), "pw"), PasswordCheck::ValidNeedsRehash);
+}
+
+#[test]
+fn garbage_in_the_hash_column_never_verifies() {
+    assert_eq!(verify_password(&user(Some("pw"), Some("pw".to_string())), "pw"), PasswordCheck::Invalid);
+}
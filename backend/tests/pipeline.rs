@@ -0,0 +1,127 @@
+use std::sync::{Arc, Mutex, mpsc};
+
+use backend::{BroadcastRingBuilder, EventDispatcher, EventSink, Instrument, LimitOrder, MarketDataPublisher, MarketOrder, MatchingEngine, Order, RingBuffer, spawn_consumer, types::{Event, EventEnvelope, OrderBookMessage, Priority, Side}};
+use rust_decimal_macros::dec;
+use uuid::Uuid;
+
+// records everything it is given, optionally failing the first few calls
+#[derive(Clone, Default)]
+struct Collector {
+    events: Arc<Mutex<Vec<EventEnvelope>>>,
+    failures_left: Arc<Mutex<u32>>,
+}
+
+impl EventSink for Collector {
+    fn name(&self) -> &str {
+        "collector"
+    }
+
+    fn handle(&mut self, events: &[EventEnvelope]) -> Result<(), String> {
+        let mut failures_left = self.failures_left.lock().unwrap();
+        if *failures_left > 0 {
+            *failures_left -= 1;
+            return Err("not yet".to_string());
+        }
+        self.events.lock().unwrap().extend_from_slice(events);
+        Ok(())
+    }
+}
+
+fn messages() -> Vec<OrderBookMessage> {
+    let users: Vec<Uuid> = (0..4).map(|_| Uuid::new_v4()).collect();
+    (0..300u64)
+        .map(|i| {
+            let user_id = users[i as usize % users.len()];
+            let side = if i % 2 == 0 { Side::Buy } else { Side::Sell };
+            let order = match i % 7 {
+                0 => Order::market_order(MarketOrder { user_id, side, quantity: 3, leverage: dec!(1) }),
+                // rejected by validation
+                6 => Order::limit_order(LimitOrder { user_id, side, price: 100, quantity: 0, leverage: dec!(1) }),
+                _ => Order::limit_order(LimitOrder { user_id, side, price: 98 + (i % 5) as i64, quantity: 1 + i % 4, leverage: dec!(1) }),
+            };
+            OrderBookMessage::PlaceOrder { order, priority: Priority::Normal, responder: None }
+        })
+        .collect()
+}
+
+// a ring much smaller than the number of events, so the engine has to wait on the dispatcher
+fn run(dispatcher: EventDispatcher, ring: Arc<RingBuffer<EventEnvelope>>) -> u64 {
+    let handle = dispatcher.spawn();
+    let mut engine = MatchingEngine::new(ring, Instrument::new(dec!(0.01), dec!(0.001)));
+    let (tx, rx) = mpsc::sync_channel(512);
+    for message in messages() {
+        tx.send(message).unwrap();
+    }
+    drop(tx);
+    engine.run(rx);
+    handle.shutdown();
+    engine.event_seq()
+}
+
+fn assert_complete(events: &[EventEnvelope], published: u64) {
+    assert_eq!(events.len() as u64, published);
+    assert!(events.iter().enumerate().all(|(i, e)| e.seq == i as u64 + 1));
+}
+
+#[test]
+fn every_event_reaches_every_sink_despite_a_tiny_ring() {
+    let ring = Arc::new(RingBuffer::new(8));
+    let (first, second) = (Collector::default(), Collector::default());
+    let dispatcher = EventDispatcher::new(Arc::clone(&ring)).with_batch_size(3).with_sink(first.clone()).with_sink(second.clone());
+
+    let published = run(dispatcher, ring);
+    assert!(published > 300);
+    assert_complete(&first.events.lock().unwrap(), published);
+    assert_eq!(*first.events.lock().unwrap(), *second.events.lock().unwrap());
+}
+
+#[test]
+fn failing_sink_is_retried_without_losing_events() {
+    let ring = Arc::new(RingBuffer::new(16));
+    let flaky = Collector { failures_left: Arc::new(Mutex::new(3)), ..Collector::default() };
+    let steady = Collector::default();
+    let dispatcher = EventDispatcher::new(Arc::clone(&ring)).with_sink(flaky.clone()).with_sink(steady.clone());
+
+    let published = run(dispatcher, ring);
+    assert_eq!(*flaky.failures_left.lock().unwrap(), 0);
+    assert_complete(&flaky.events.lock().unwrap(), published);
+    assert_complete(&steady.events.lock().unwrap(), published);
+}
+
+#[test]
+fn market_data_leaves_out_rejections() {
+    let ring = Arc::new(RingBuffer::new(1024));
+    let publisher = MarketDataPublisher::new(4096);
+    let mut rx = publisher.subscribe();
+    let dispatcher = EventDispatcher::new(Arc::clone(&ring)).with_sink(publisher);
+
+    let published = run(dispatcher, ring);
+    let mut received = Vec::new();
+    while let Ok(envelope) = rx.try_recv() {
+        received.push(envelope);
+    }
+    assert!(!received.is_empty());
+    assert!(received.iter().all(|e| !matches!(e.event, Event::OrderRejected { .. })));
+    assert!(received.windows(2).all(|w| w[0].seq < w[1].seq));
+    // one rejection per seven orders
+    assert_eq!(received.len() as u64, published - 300 / 7);
+}
+
+#[test]
+fn market_data_feed_subscribes_after_the_publisher_moved_to_its_consumer() {
+    let ring = Arc::new(RingBuffer::new(1024));
+    let (producer, mut consumers) = BroadcastRingBuilder::new(64).consumer("market-data", &[]).build::<EventEnvelope>();
+    let publisher = MarketDataPublisher::new(4096);
+    let feed = publisher.feed();
+    let consumer = spawn_consumer(consumers.remove(0), publisher);
+    let mut rx = feed.subscribe();
+    assert_eq!(feed.subscribers(), 1);
+
+    let published = run(EventDispatcher::new(Arc::clone(&ring)).with_sink(producer), ring);
+    consumer.shutdown();
+    let mut received = 0;
+    while rx.try_recv().is_ok() {
+        received += 1;
+    }
+    assert_eq!(received, published - 300 / 7);
+}
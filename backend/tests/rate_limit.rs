@@ -0,0 +1,108 @@
+mod common;
+
+use std::{sync::Arc, time::{Duration, Instant}};
+
+use actix_web::{App, dev::ServiceResponse, http::{StatusCode, header::RETRY_AFTER}, test, web};
+use backend::{AuthError, OrderRateLimiter, RateLimit, routes, state::AppState, types::{Response, Role, TokenResponse}};
+use serde_json::{Value, json};
+use uuid::Uuid;
+
+#[actix_web::test]
+async fn buckets_refill_at_the_rate_of_the_role() {
+    let limiter = OrderRateLimiter::new().with_limit(Role::Trader, RateLimit::new(3, 2)).with_limit(Role::MarketMaker, RateLimit::new(10, 10));
+    let (user, start) = (Uuid::new_v4(), Instant::now());
+    for _ in 0..3 {
+        limiter.take(user, None, Role::Trader, 1, start).unwrap();
+    }
+    assert_eq!(limiter.take(user, None, Role::Trader, 1, start), Err(AuthError::RateLimited(1)));
+    // two a second, the bucket never holds more than the burst however long it waits
+    limiter.take(user, None, Role::Trader, 1, start + Duration::from_millis(500)).unwrap();
+    assert!(limiter.take(user, None, Role::Trader, 1, start + Duration::from_millis(500)).is_err());
+    let later = start + Duration::from_secs(60);
+    limiter.take(user, None, Role::Trader, 3, later).unwrap();
+    assert_eq!(limiter.take(user, None, Role::Trader, 3, later), Err(AuthError::RateLimited(2)));
+    // a bigger role gets a bigger bucket from the next request on
+    limiter.take(user, None, Role::MarketMaker, 3, later + Duration::from_secs(1)).unwrap();
+    assert!(matches!(limiter.take(user, None, Role::Trader, 4, later), Err(AuthError::InvalidInput(_))));
+    // other users have their own bucket
+    limiter.take(Uuid::new_v4(), None, Role::Trader, 3, later).unwrap();
+}
+
+#[actix_web::test]
+async fn api_keys_draw_from_their_own_bucket_and_the_users() {
+    let limiter = OrderRateLimiter::new().with_limit(Role::Trader, RateLimit::new(4, 1));
+    let (user, key, other_key, now) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4(), Instant::now());
+    limiter.take(user, Some(key), Role::Trader, 3, now).unwrap();
+    // the key has one left, the user too, so another key of the same user is not a way around it
+    assert_eq!(limiter.take(user, Some(other_key), Role::Trader, 2, now), Err(AuthError::RateLimited(1)));
+    // a refused request takes nothing from either bucket
+    limiter.take(user, Some(other_key), Role::Trader, 1, now).unwrap();
+    assert!(limiter.take(user, None, Role::Trader, 1, now).is_err());
+}
+
+#[actix_web::test]
+async fn limits_parse_from_burst_and_rate() {
+    assert_eq!(RateLimit::parse("100, 20"), Ok(RateLimit::new(100, 20)));
+    assert!(RateLimit::parse("100").is_err());
+    assert!(RateLimit::parse("100,0").is_err());
+    assert!(RateLimit::parse("x,1").is_err());
+    assert_eq!(OrderRateLimiter::new().limit(Role::MarketMaker), RateLimit::new(500, 200));
+}
+
+fn buy(price: f64) -> Value {
+    json!({ "type": "limit", "side": "buy", "quantity": 1, "price": price, "leverage": 1 })
+}
+
+// a zero quantity is refused before the engine
+fn batch(orders: &[(f64, u32)]) -> Value {
+    let orders: Vec<Value> = orders
+        .iter()
+        .map(|&(price, quantity)| json!({ "action": "place", "type": "limit", "side": "buy", "quantity": quantity, "price": price, "leverage": 1 }))
+        .collect();
+    json!({ "orders": orders })
+}
+
+#[actix_web::test]
+async fn order_routes_answer_429_with_retry_after() {
+    let order_limiter = Arc::new(OrderRateLimiter::new().with_limit(Role::Trader, RateLimit::new(3, 1)));
+    let state = web::Data::new(AppState { order_limiter, ..common::state_with_engine(common::database().await) });
+    let app = test::init_service(App::new().app_data(state.clone()).configure(routes)).await;
+    let email = format!("{}@rate-limit.test", Uuid::new_v4());
+    let req = test::TestRequest::post().uri("/signup").set_json(json!({ "email": email, "password": "correct horse" })).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    let req = test::TestRequest::post().uri("/signin").set_json(json!({ "email": email, "password": "correct horse" })).to_request();
+    let tokens: TokenResponse = test::read_body_json(test::call_service(&app, req).await).await;
+    let call = |uri: &str, body: Value| {
+        test::TestRequest::post()
+            .uri(uri)
+            .insert_header(("Authorization", format!("Bearer {}", tokens.token)))
+            .set_json(body)
+            .to_request()
+    };
+
+    // more orders in one batch than the bucket ever holds
+    let res: ServiceResponse = test::call_service(&app, call("/batch_order", batch(&[(10.0, 1), (10.0, 1), (10.0, 1), (10.0, 1)]))).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let body: Response = test::read_body_json(res).await;
+    assert_eq!(body.error, "More orders in one request than the rate limit allows");
+    // only the two items sent to the engine are charged
+    let res: ServiceResponse = test::call_service(&app, call("/batch_order", batch(&[(10.0, 1), (10.0, 0), (11.0, 1), (11.0, 0)]))).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: Value = test::read_body_json(res).await;
+    assert_eq!(body["results"].as_array().unwrap().iter().filter(|r| r["error"].is_null()).count(), 2);
+    assert_eq!(test::call_service(&app, call("/place_order", buy(12.0))).await.status(), StatusCode::OK);
+
+    // a batch that sends nothing costs nothing, one that would send something waits like the rest
+    assert_eq!(test::call_service(&app, call("/batch_order", batch(&[(13.0, 0)]))).await.status(), StatusCode::OK);
+    assert_eq!(test::call_service(&app, call("/batch_order", batch(&[(13.0, 1)]))).await.status(), StatusCode::TOO_MANY_REQUESTS);
+    let res: ServiceResponse = test::call_service(&app, call("/place_order", buy(13.0))).await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.headers().get(RETRY_AFTER).unwrap(), "1");
+    let body: Response = test::read_body_json(res).await;
+    assert_eq!(body.error, "Too many order requests, slow down");
+    // cancels come out of the same bucket
+    let res: ServiceResponse = test::call_service(&app, call("/cancel_order", json!({ "order_id": Uuid::new_v4() }))).await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    sqlx::query("DELETE FROM users WHERE email = $1").bind(&email).execute(&state.db.pool).await.unwrap();
+}
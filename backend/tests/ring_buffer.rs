@@ -0,0 +1,215 @@
+// also run under Miri: cargo +nightly miri test -p backend --test ring_buffer
+
+use std::{
+    sync::{Arc, mpsc},
+    thread,
+    time::Duration,
+};
+
+use backend::{Instrument, LimitOrder, MatchingEngine, Order, OverflowPolicy, RingBuffer, RingStats, types::{EventEnvelope, OrderBookMessage, Priority, Side}};
+use rust_decimal_macros::dec;
+use uuid::Uuid;
+
+#[test]
+fn stats_track_occupancy_high_water_and_full_pushes() {
+    let ring = RingBuffer::<u64>::new(8);
+    for item in 0..7 {
+        ring.try_push(item).unwrap();
+    }
+    assert_eq!(ring.try_push(7), Err(7));
+    ring.drain_batch(5);
+    ring.try_push(8).unwrap();
+    assert_eq!(ring.stats(), RingStats { capacity: 7, occupancy: 3, high_water_mark: 7, full: 1, dropped: 0 });
+}
+
+#[test]
+fn force_push_evicts_the_oldest_item() {
+    let ring = RingBuffer::<u64>::new(4);
+    for item in 0..3 {
+        assert_eq!(ring.force_push(item), None);
+    }
+    assert_eq!(ring.force_push(3), Some(0));
+    assert_eq!(ring.force_push(4), Some(1));
+    assert_eq!(ring.drain_batch(10), vec![2, 3, 4]);
+    assert_eq!(ring.stats().dropped, 2);
+}
+
+// owned items across several wraparounds, so Miri sees every slot written, read and reused
+#[test]
+fn owned_items_survive_wraparound() {
+    let ring = RingBuffer::<String>::new(4);
+    for round in 0..5 {
+        for i in 0..3 {
+            ring.try_push(format!("{round}-{i}")).unwrap();
+        }
+        assert_eq!(ring.try_pop().as_deref(), Some(format!("{round}-0").as_str()));
+        assert_eq!(ring.drain_batch(10), vec![format!("{round}-1"), format!("{round}-2")]);
+    }
+    assert!(ring.is_empty());
+}
+
+#[test]
+fn unread_items_are_dropped_with_the_ring() {
+    let item = Arc::new(());
+    let ring = RingBuffer::<Arc<()>>::new(4);
+    for _ in 0..5 {
+        ring.try_push(Arc::clone(&item)).unwrap();
+        drop(ring.try_pop());
+    }
+    // unread items straddle the end of the buffer
+    for _ in 0..3 {
+        ring.try_push(Arc::clone(&item)).unwrap();
+    }
+    assert_eq!(Arc::strong_count(&item), 4);
+    drop(ring);
+    assert_eq!(Arc::strong_count(&item), 1);
+}
+
+#[test]
+fn evicted_items_are_owned_by_the_producer() {
+    let item = Arc::new(());
+    let ring = RingBuffer::<Arc<()>>::new(2);
+    ring.try_push(Arc::clone(&item)).unwrap();
+    let evicted = ring.force_push(Arc::clone(&item)).unwrap();
+    assert_eq!(Arc::strong_count(&item), 3);
+    drop(evicted);
+    assert_eq!(ring.drain_batch(2).len(), 1);
+    assert_eq!(Arc::strong_count(&item), 1);
+}
+
+// small enough to run under Miri, which checks the slot handover between the two threads
+#[test]
+fn producer_and_consumer_threads_hand_over_owned_items() {
+    const ITEMS: usize = 100;
+    let ring = Arc::new(RingBuffer::<Box<usize>>::new(8));
+    let consumer = Arc::clone(&ring);
+    let reader = thread::spawn(move || {
+        let mut expected = 0;
+        while expected < ITEMS {
+            for item in consumer.drain_batch(3) {
+                assert_eq!(*item, expected);
+                expected += 1;
+            }
+            thread::yield_now();
+        }
+    });
+    for item in 0..ITEMS {
+        let mut item = Box::new(item);
+        while let Err(back) = ring.try_push(item) {
+            item = back;
+            thread::yield_now();
+        }
+    }
+    reader.join().unwrap();
+}
+
+#[test]
+#[cfg_attr(miri, ignore = "too many iterations for miri")]
+fn force_push_racing_a_consumer_loses_only_what_it_counts() {
+    const ITEMS: u64 = 20_000;
+    let ring = Arc::new(RingBuffer::<u64>::new(16));
+    let consumer = Arc::clone(&ring);
+    let reader = thread::spawn(move || {
+        let mut seen = Vec::new();
+        while seen.last() != Some(&(ITEMS - 1)) {
+            let batch = consumer.drain_batch(4);
+            if batch.is_empty() {
+                thread::yield_now();
+            }
+            seen.extend(batch);
+        }
+        seen
+    });
+    let mut evicted = Vec::new();
+    for item in 0..ITEMS {
+        evicted.extend(ring.force_push(item));
+    }
+    let seen = reader.join().unwrap();
+
+    assert!(seen.windows(2).all(|w| w[0] < w[1]), "items repeated or reordered");
+    assert_eq!(seen.len() as u64 + evicted.len() as u64, ITEMS);
+    assert_eq!(ring.stats().dropped, evicted.len() as u64);
+}
+
+#[test]
+fn overflow_policy_parses_from_config() {
+    assert_eq!("block".parse(), Ok(OverflowPolicy::Block));
+    assert_eq!("drop-oldest".parse(), Ok(OverflowPolicy::DropOldest));
+    assert_eq!("halt".parse(), Ok(OverflowPolicy::Halt));
+    assert_eq!("spin:50".parse(), Ok(OverflowPolicy::SpinThenFail(50)));
+    assert!("spin:".parse::<OverflowPolicy>().is_err());
+}
+
+// every order rests on its own price level, so each one emits exactly one event
+fn orders(count: i64) -> Vec<OrderBookMessage> {
+    let user_id = Uuid::new_v4();
+    (0..count)
+        .map(|i| OrderBookMessage::PlaceOrder {
+            order: Order::limit_order(LimitOrder { user_id, side: Side::Buy, price: 100 - i, quantity: 1, leverage: dec!(1) }),
+            priority: Priority::Normal,
+            responder: None,
+        })
+        .collect()
+}
+
+fn run(engine: &mut MatchingEngine, messages: Vec<OrderBookMessage>) {
+    let (tx, rx) = mpsc::sync_channel(messages.len().max(1));
+    for message in messages {
+        tx.send(message).unwrap();
+    }
+    drop(tx);
+    engine.run(rx);
+}
+
+fn engine(ring: &Arc<RingBuffer<EventEnvelope>>, overflow: OverflowPolicy) -> MatchingEngine {
+    MatchingEngine::new(Arc::clone(ring), Instrument::new(dec!(0.01), dec!(0.001))).with_overflow_policy(overflow)
+}
+
+#[test]
+#[cfg_attr(miri, ignore = "runs the whole engine")]
+fn spin_then_fail_drops_new_events_and_counts_them() {
+    let ring = Arc::new(RingBuffer::<EventEnvelope>::new(4));
+    let mut engine = engine(&ring, OverflowPolicy::SpinThenFail(10));
+    run(&mut engine, orders(10));
+
+    let kept: Vec<u64> = ring.drain_batch(10).iter().map(|e| e.seq).collect();
+    assert_eq!(kept, vec![1, 2, 3]);
+    assert_eq!(ring.stats().dropped, 7);
+    assert_eq!(engine.order_book().snapshot().bids.len(), 10, "matching goes on regardless");
+}
+
+#[test]
+#[cfg_attr(miri, ignore = "runs the whole engine")]
+fn drop_oldest_keeps_the_newest_events() {
+    let ring = Arc::new(RingBuffer::<EventEnvelope>::new(4));
+    let mut engine = engine(&ring, OverflowPolicy::DropOldest);
+    run(&mut engine, orders(10));
+
+    let kept: Vec<u64> = ring.drain_batch(10).iter().map(|e| e.seq).collect();
+    assert_eq!(kept, vec![8, 9, 10]);
+    assert_eq!(ring.stats().dropped, 7);
+}
+
+#[test]
+#[cfg_attr(miri, ignore = "runs the whole engine")]
+fn halt_delivers_the_batch_then_stops_taking_commands() {
+    let ring = Arc::new(RingBuffer::<EventEnvelope>::new(4));
+    let consumer = Arc::clone(&ring);
+    let reader = thread::spawn(move || {
+        let mut seen = Vec::new();
+        while seen.len() < 10 {
+            // slower than the engine, so the ring fills up
+            thread::sleep(Duration::from_millis(1));
+            seen.extend(consumer.drain_batch(1).into_iter().map(|e| e.seq));
+        }
+        seen
+    });
+    let mut engine = engine(&ring, OverflowPolicy::Halt);
+    run(&mut engine, orders(10));
+    assert_eq!(reader.join().unwrap(), (1..=10).collect::<Vec<_>>());
+    assert_eq!(engine.halted(), Some("engine halted: event ring overflow"));
+
+    run(&mut engine, orders(1));
+    assert!(ring.is_empty());
+    assert_eq!(engine.command_seq(), 10);
+}
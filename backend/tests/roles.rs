@@ -0,0 +1,241 @@
+// Roles through the real routes, the database and a running engine. Every test signs up its own
+// random emails and deletes them afterwards.
+
+mod common;
+
+use actix_web::{App, dev::ServiceResponse, http::{Method, StatusCode}, test, web};
+use backend::{create_jwt, routes, state::AppState, types::{DEFAULT_MAX_OPEN_ORDERS, ForceCancelResponse, MarketStatusResponse, Response, Role, TokenResponse, UserResponse}};
+use rust_decimal_macros::dec;
+use serde_json::{Value, json};
+use uuid::Uuid;
+
+async fn state() -> web::Data<AppState> {
+    web::Data::new(common::state_with_engine(common::database().await))
+}
+
+fn random_email() -> String {
+    format!("{}@roles.test", Uuid::new_v4())
+}
+
+async fn forget(state: &AppState, email: &str) {
+    sqlx::query("DELETE FROM users WHERE email = $1").bind(email).execute(&state.db.pool).await.unwrap();
+}
+
+macro_rules! app {
+    ($state:expr) => {
+        test::init_service(App::new().app_data($state.clone()).configure(routes)).await
+    };
+}
+
+// signs up and in with the role set in between, the user id and tokens
+macro_rules! user {
+    ($app:expr, $state:expr, $email:expr, $role:expr) => {{
+        let req = test::TestRequest::post().uri("/signup").set_json(json!({ "email": $email, "password": "correct horse" })).to_request();
+        let user: UserResponse = test::read_body_json(test::call_service(&$app, req).await).await;
+        assert!($state.db.set_user_role(user.id, $role.as_str()).await.unwrap());
+        let req = test::TestRequest::post().uri("/signin").set_json(json!({ "email": $email, "password": "correct horse" })).to_request();
+        let tokens: TokenResponse = test::read_body_json(test::call_service(&$app, req).await).await;
+        (user.id, tokens.token, tokens.refresh_token)
+    }};
+}
+
+macro_rules! call {
+    ($app:expr, $method:expr, $uri:expr, $token:expr, $body:expr) => {{
+        let req = test::TestRequest::default()
+            .method($method)
+            .uri($uri)
+            .insert_header(("Authorization", format!("Bearer {}", $token)))
+            .set_json($body)
+            .to_request();
+        let res: ServiceResponse = test::call_service(&$app, req).await;
+        res
+    }};
+}
+
+fn buy(price: f64) -> Value {
+    json!({ "type": "limit", "side": "buy", "quantity": 1, "price": price, "leverage": 1 })
+}
+
+// admin endpoints want a 2FA session, a token for one is minted here rather than enrolling TOTP
+fn with_mfa(state: &AppState, user_id: Uuid) -> String {
+    create_jwt(&state.keys, user_id, Uuid::new_v4(), true, Role::Admin)
+}
+
+#[actix_web::test]
+async fn tokens_carry_the_role_and_auditors_cannot_trade() {
+    let state = state().await;
+    let app = app!(state);
+    let (trader, auditor) = (random_email(), random_email());
+    let (_, trader_token, _) = user!(app, state, trader, Role::Trader);
+    let (_, auditor_token, _) = user!(app, state, auditor, Role::Auditor);
+    assert_eq!(state.keys.verify(&trader_token).unwrap().role, Role::Trader);
+    assert_eq!(state.keys.verify(&auditor_token).unwrap().role, Role::Auditor);
+
+    assert_eq!(call!(app, Method::POST, "/place_order", trader_token, buy(10.0)).status(), StatusCode::OK);
+    let refused = call!(app, Method::POST, "/place_order", auditor_token, buy(10.0));
+    assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+    let body: Response = test::read_body_json(refused).await;
+    assert_eq!(body.error, "Not permitted for this account");
+    assert_eq!(call!(app, Method::POST, "/batch_order", auditor_token, json!({ "orders": [] })).status(), StatusCode::FORBIDDEN);
+    assert_eq!(call!(app, Method::GET, "/me", auditor_token, json!({})).status(), StatusCode::OK);
+
+    forget(&state, &trader).await;
+    forget(&state, &auditor).await;
+}
+
+#[actix_web::test]
+async fn admin_routes_want_an_admin_with_a_second_factor() {
+    let state = state().await;
+    let app = app!(state);
+    let (trader, admin) = (random_email(), random_email());
+    let (trader_id, trader_token, _) = user!(app, state, trader, Role::Trader);
+    let (admin_id, admin_token, _) = user!(app, state, admin, Role::Admin);
+    let halt = json!({ "reason": "maintenance" });
+
+    assert_eq!(call!(app, Method::POST, "/admin/market/halt", trader_token, &halt).status(), StatusCode::FORBIDDEN);
+    // a trader's own token claiming mfa still has the trader role
+    let trader_mfa = create_jwt(&state.keys, trader_id, Uuid::new_v4(), true, Role::Trader);
+    assert_eq!(call!(app, Method::POST, "/admin/market/halt", trader_mfa, &halt).status(), StatusCode::FORBIDDEN);
+    let res = call!(app, Method::POST, "/admin/market/halt", admin_token, &halt);
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let body: Response = test::read_body_json(res).await;
+    assert_eq!(body.error, "Two-factor authentication required");
+
+    let admin_mfa = with_mfa(&state, admin_id);
+    assert_eq!(call!(app, Method::POST, "/admin/market/halt", admin_mfa, json!({ "reason": " " })).status(), StatusCode::BAD_REQUEST);
+    assert_eq!(call!(app, Method::PUT, "/admin/risk_limits", admin_mfa, json!({ "max_leverage": 500 })).status(), StatusCode::BAD_REQUEST);
+
+    forget(&state, &trader).await;
+    forget(&state, &admin).await;
+}
+
+#[actix_web::test]
+async fn admins_halt_and_resume_the_market_and_set_limits() {
+    let state = state().await;
+    let app = app!(state);
+    let (trader, admin) = (random_email(), random_email());
+    let (_, trader_token, _) = user!(app, state, trader, Role::Trader);
+    let (admin_id, _, _) = user!(app, state, admin, Role::Admin);
+    let admin_token = with_mfa(&state, admin_id);
+
+    let res = call!(app, Method::POST, "/admin/market/halt", admin_token, json!({ "reason": "maintenance" }));
+    assert_eq!(res.status(), StatusCode::OK);
+    let status: MarketStatusResponse = test::read_body_json(res).await;
+    assert_eq!(status.halted.as_deref(), Some("maintenance"));
+
+    let rejected = call!(app, Method::POST, "/place_order", trader_token, buy(10.0));
+    assert_eq!(rejected.status(), StatusCode::BAD_REQUEST);
+    let body: Response = test::read_body_json(rejected).await;
+    assert_eq!(body.error, "market halted: maintenance");
+
+    let res = call!(app, Method::POST, "/admin/market/resume", admin_token, json!({}));
+    let status: MarketStatusResponse = test::read_body_json(res).await;
+    assert_eq!(status.halted, None);
+    assert_eq!(call!(app, Method::POST, "/place_order", trader_token, buy(10.0)).status(), StatusCode::OK);
+
+    let res = call!(app, Method::PUT, "/admin/risk_limits", admin_token, json!({ "max_leverage": 10, "max_order_quantity": 0.5 }));
+    assert_eq!(res.status(), StatusCode::OK);
+    let status: MarketStatusResponse = test::read_body_json(res).await;
+    assert_eq!((status.max_leverage, status.max_order_quantity), (dec!(10), Some(dec!(0.5))));
+    // left out, the open order cap stays at the default, only an explicit null lifts it
+    assert_eq!(status.max_open_orders, Some(DEFAULT_MAX_OPEN_ORDERS));
+    assert_eq!(call!(app, Method::POST, "/place_order", trader_token, buy(10.0)).status(), StatusCode::BAD_REQUEST);
+    let res = call!(app, Method::PUT, "/admin/risk_limits", admin_token, json!({ "max_leverage": 10, "max_open_orders": null }));
+    let status: MarketStatusResponse = test::read_body_json(res).await;
+    assert_eq!(status.max_open_orders, None);
+    let res = call!(app, Method::PUT, "/admin/risk_limits", admin_token, json!({ "max_leverage": 10, "max_open_orders": 0 }));
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    forget(&state, &trader).await;
+    forget(&state, &admin).await;
+}
+
+#[actix_web::test]
+async fn admins_cancel_all_orders_of_a_user() {
+    let state = state().await;
+    let app = app!(state);
+    let (trader, admin) = (random_email(), random_email());
+    let (trader_id, trader_token, _) = user!(app, state, trader, Role::Trader);
+    let (admin_id, _, _) = user!(app, state, admin, Role::Admin);
+    for price in [10.0, 11.0] {
+        assert_eq!(call!(app, Method::POST, "/place_order", trader_token, buy(price)).status(), StatusCode::OK);
+    }
+
+    let res = call!(app, Method::POST, &format!("/admin/users/{trader_id}/cancel_orders"), with_mfa(&state, admin_id), json!({}));
+    assert_eq!(res.status(), StatusCode::OK);
+    let cancelled: ForceCancelResponse = test::read_body_json(res).await;
+    assert_eq!(cancelled.user_id, trader_id);
+    assert_eq!(cancelled.cancelled.len(), 2);
+
+    forget(&state, &trader).await;
+    forget(&state, &admin).await;
+}
+
+#[actix_web::test]
+async fn role_changes_reach_the_token_on_refresh() {
+    let state = state().await;
+    let app = app!(state);
+    let (trader, admin) = (random_email(), random_email());
+    let (trader_id, _, refresh_token) = user!(app, state, trader, Role::Trader);
+    let (admin_id, _, _) = user!(app, state, admin, Role::Admin);
+    let admin_token = with_mfa(&state, admin_id);
+
+    let res = call!(app, Method::PUT, &format!("/admin/users/{trader_id}/role"), admin_token, json!({ "role": "market_maker" }));
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    let req = test::TestRequest::post().uri("/token/refresh").set_json(json!({ "refresh_token": refresh_token })).to_request();
+    let tokens: TokenResponse = test::read_body_json(test::call_service(&app, req).await).await;
+    assert_eq!(state.keys.verify(&tokens.token).unwrap().role, Role::MarketMaker);
+
+    let unknown = call!(app, Method::PUT, &format!("/admin/users/{}/role", Uuid::new_v4()), admin_token, json!({ "role": "trader" }));
+    assert_eq!(unknown.status(), StatusCode::NOT_FOUND);
+    let demote_self = call!(app, Method::PUT, &format!("/admin/users/{admin_id}/role"), admin_token, json!({ "role": "trader" }));
+    assert_eq!(demote_self.status(), StatusCode::BAD_REQUEST);
+    let bogus = call!(app, Method::PUT, &format!("/admin/users/{trader_id}/role"), admin_token, json!({ "role": "root" }));
+    assert_eq!(bogus.status(), StatusCode::BAD_REQUEST);
+
+    forget(&state, &trader).await;
+    forget(&state, &admin).await;
+}
+
+#[actix_web::test]
+async fn cancelling_an_unknown_or_foreign_order_is_a_bad_request() {
+    let state = state().await;
+    let app = app!(state);
+    let (owner, other) = (random_email(), random_email());
+    let (_, owner_token, _) = user!(app, state, owner, Role::Trader);
+    let (_, other_token, _) = user!(app, state, other, Role::Trader);
+
+    let res = call!(app, Method::POST, "/cancel_order", owner_token, json!({ "order_id": Uuid::new_v4() }));
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let body: Response = test::read_body_json(res).await;
+    assert_eq!(body.error, "order is not found");
+
+    let res = call!(app, Method::POST, "/batch_order", owner_token, json!({ "orders": [{ "action": "place", "type": "limit", "side": "buy", "quantity": 1, "price": 10.0, "leverage": 1 }] }));
+    let placed: Value = test::read_body_json(res).await;
+    let order_id = placed["results"][0]["order_id"].clone();
+    assert_eq!(call!(app, Method::POST, "/cancel_order", other_token, json!({ "order_id": order_id })).status(), StatusCode::BAD_REQUEST);
+    assert_eq!(call!(app, Method::POST, "/cancel_order", owner_token, json!({ "order_id": order_id })).status(), StatusCode::OK);
+
+    forget(&state, &owner).await;
+    forget(&state, &other).await;
+}
+
+#[actix_web::test]
+async fn only_admins_read_the_event_ring() {
+    let state = state().await;
+    let app = app!(state);
+    let (trader, admin) = (random_email(), random_email());
+    let (_, trader_token, _) = user!(app, state, trader, Role::Trader);
+    let (_, admin_token, _) = user!(app, state, admin, Role::Admin);
+
+    let req = test::TestRequest::get().uri("/admin/engine/event_ring").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(call!(app, Method::GET, "/admin/engine/event_ring", trader_token, json!({})).status(), StatusCode::FORBIDDEN);
+    let res = call!(app, Method::GET, "/admin/engine/event_ring", admin_token, json!({}));
+    assert_eq!(res.status(), StatusCode::OK);
+    let stats: Value = test::read_body_json(res).await;
+    assert_eq!(stats["capacity"], state.event_ring.stats().capacity);
+
+    forget(&state, &trader).await;
+    forget(&state, &admin).await;
+}
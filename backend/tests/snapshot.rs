@@ -0,0 +1,140 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{Arc, mpsc},
+};
+
+use backend::{EngineSnapshot, Instrument, Journal, LimitOrder, Lots, MarketOrder, MatchingEngine, Order, RingBuffer, SnapshotStore, Ticks, UserId, types::{EventEnvelope, OrderBookMessage, Priority, Side}};
+use rust_decimal_macros::dec;
+use uuid::Uuid;
+
+const EVERY: u64 = 50;
+
+fn temp_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("perp-snapshot-{}", Uuid::new_v4()))
+}
+
+fn engine() -> MatchingEngine {
+    MatchingEngine::new(Arc::new(RingBuffer::<EventEnvelope>::new(1 << 16)), Instrument::new(dec!(0.01), dec!(0.001)))
+}
+
+fn with_snapshots(dir: &Path) -> MatchingEngine {
+    engine()
+        .with_journal(Journal::open(dir.join("engine.journal")).unwrap())
+        .with_snapshots(SnapshotStore::new(dir.join("snapshots"), 3).unwrap(), EVERY)
+}
+
+fn place(order: Order) -> OrderBookMessage {
+    OrderBookMessage::PlaceOrder { order, priority: Priority::Normal, responder: None }
+}
+
+fn limit(user_id: UserId, side: Side, price: Ticks, quantity: Lots) -> Order {
+    Order::limit_order(LimitOrder { user_id, side, price, quantity, leverage: dec!(2) }).with_id(Uuid::new_v4())
+}
+
+// feeds the messages in small chunks so the engine gets to snapshot between batches
+fn run(engine: &mut MatchingEngine, messages: Vec<OrderBookMessage>) {
+    let mut messages = messages.into_iter().peekable();
+    while messages.peek().is_some() {
+        let (tx, rx) = mpsc::sync_channel(40);
+        for message in messages.by_ref().take(40) {
+            tx.send(message).unwrap();
+        }
+        drop(tx);
+        engine.run(rx);
+    }
+}
+
+fn workload() -> Vec<OrderBookMessage> {
+    let users: Vec<UserId> = (0..3).map(|_| Uuid::new_v4()).collect();
+    let mut messages = Vec::new();
+    let mut resting = Vec::new();
+    for i in 0..250i64 {
+        let user = users[i as usize % users.len()];
+        let side = if i % 2 == 0 { Side::Buy } else { Side::Sell };
+        let order = limit(user, side, 100 + (i * 5) % 11 - 5, 1 + (i as u64 * 7) % 19);
+        resting.push((order.order_id, user));
+        messages.push(place(order));
+        if i % 4 == 0 {
+            let (order_id, user_id) = resting[(i as usize * 17) % resting.len()];
+            messages.push(OrderBookMessage::CancelOrder { order_id, user_id, responder: None });
+        }
+        if i % 13 == 0 {
+            messages.push(place(Order::market_order(MarketOrder { user_id: user, side, quantity: 4, leverage: dec!(1) })));
+        }
+    }
+    messages
+}
+
+fn live(dir: &Path) -> (MatchingEngine, Vec<(u64, PathBuf)>) {
+    let mut live = with_snapshots(dir);
+    run(&mut live, workload());
+    let snapshots = SnapshotStore::new(dir.join("snapshots"), 3).unwrap().list().unwrap();
+    assert!(snapshots.len() >= 2, "expected several snapshots, got {}", snapshots.len());
+    assert!(snapshots[0].0 < live.command_seq(), "latest snapshot should leave a journal tail");
+    (live, snapshots)
+}
+
+#[test]
+fn snapshot_plus_tail_matches_live_and_full_replay() {
+    let dir = temp_dir();
+    let (live, snapshots) = live(&dir);
+    let total = live.command_seq();
+
+    let mut recovered = with_snapshots(&dir);
+    assert_eq!(recovered.recover().unwrap() as u64, total - snapshots[0].0);
+    assert_eq!(recovered.command_seq(), total);
+    assert_eq!(recovered.order_book().state_hash(), live.order_book().state_hash());
+
+    let mut replayed = engine().with_journal(Journal::open(dir.join("engine.journal")).unwrap());
+    assert_eq!(replayed.recover().unwrap() as u64, total);
+    assert_eq!(replayed.order_book().state_hash(), recovered.order_book().state_hash());
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn unreadable_latest_snapshot_falls_back_to_an_older_one() {
+    let dir = temp_dir();
+    let (live, snapshots) = live(&dir);
+
+    let bytes = fs::read(&snapshots[0].1).unwrap();
+    fs::write(&snapshots[0].1, &bytes[..bytes.len() / 2]).unwrap();
+
+    let mut recovered = with_snapshots(&dir);
+    assert_eq!(recovered.recover().unwrap() as u64, live.command_seq() - snapshots[1].0);
+    assert_eq!(recovered.order_book().state_hash(), live.order_book().state_hash());
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn snapshot_failing_the_hash_check_is_skipped() {
+    let dir = temp_dir();
+    let (live, snapshots) = live(&dir);
+
+    // drop a resting order but keep the recorded hash
+    let mut snapshot: EngineSnapshot = SnapshotStore::read(&snapshots[0].1).unwrap();
+    let level = snapshot.book.bids.iter_mut().chain(snapshot.book.asks.iter_mut()).find(|l| l.orders.len() > 1).unwrap();
+    level.orders.pop();
+    fs::write(&snapshots[0].1, serde_json::to_vec(&snapshot).unwrap()).unwrap();
+
+    let mut recovered = with_snapshots(&dir);
+    assert_eq!(recovered.recover().unwrap() as u64, live.command_seq() - snapshots[1].0);
+    assert_eq!(recovered.order_book().state_hash(), live.order_book().state_hash());
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn snapshot_ahead_of_the_journal_is_an_error() {
+    let dir = temp_dir();
+    live(&dir);
+    fs::remove_file(dir.join("engine.journal")).unwrap();
+
+    let mut recovered = with_snapshots(&dir);
+    assert_eq!(recovered.recover().err().unwrap().kind(), ErrorKind::InvalidData);
+
+    fs::remove_dir_all(dir).unwrap();
+}
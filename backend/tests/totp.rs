@@ -0,0 +1,290 @@
+// TOTP codes against the RFC 6238 vectors, and enrollment and two step sign-in through the real
+// routes and database.
+
+mod common;
+
+use std::sync::Arc;
+
+use actix_web::{App, dev::ServiceResponse, http::{StatusCode, header::RETRY_AFTER}, test, web};
+use backend::{
+    LoginLimiter, LoginPolicy, MAX_MFA_ATTEMPTS, RECOVERY_CODES, check_totp, hash_recovery_code, new_recovery_codes, otpauth_uri, routes,
+    state::AppState, totp_code, totp_step,
+    types::{MfaChallengeResponse, RecoveryCodesResponse, SigninResponse, TokenResponse, TotpEnrollmentResponse},
+};
+use chrono::{Duration, Utc};
+use serde_json::{Value, json};
+use uuid::Uuid;
+
+// "12345678901234567890" from the RFC, base32 encoded
+const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+#[actix_web::test]
+async fn codes_match_the_rfc_vectors() {
+    // the RFC lists eight digits, authenticators show the last six
+    for (time, code) in [(59, "287082"), (1111111109, "081804"), (1234567890, "005924"), (2000000000, "279037")] {
+        assert_eq!(totp_code(RFC_SECRET, totp_step(time)).unwrap(), code);
+    }
+    assert_eq!(totp_code(&RFC_SECRET.to_lowercase(), 1).unwrap(), "287082");
+    assert!(totp_code("not base32!", 1).is_none());
+}
+
+#[actix_web::test]
+async fn one_step_of_drift_is_accepted() {
+    let now = 1_700_000_000;
+    let step = totp_step(now);
+    let code = |step| totp_code(RFC_SECRET, step).unwrap();
+    assert_eq!(check_totp(RFC_SECRET, &code(step), now), Some(step));
+    assert_eq!(check_totp(RFC_SECRET, &code(step - 1), now), Some(step - 1));
+    assert_eq!(check_totp(RFC_SECRET, &format!(" {} ", code(step + 1)), now), Some(step + 1));
+    assert_eq!(check_totp(RFC_SECRET, &code(step - 2), now), None);
+    assert_eq!(check_totp(RFC_SECRET, "12345", now), None);
+    assert_eq!(check_totp(RFC_SECRET, "12a456", now), None);
+}
+
+#[actix_web::test]
+async fn enrollment_material_is_what_apps_expect() {
+    let uri = otpauth_uri("trader@example.com", RFC_SECRET);
+    assert_eq!(uri, format!("otpauth://totp/Perp%3Atrader%40example%2Ecom?secret={RFC_SECRET}&issuer=Perp&algorithm=SHA1&digits=6&period=30"));
+
+    let (codes, hashes) = new_recovery_codes();
+    assert_eq!(codes.len(), RECOVERY_CODES);
+    assert_eq!(hashes[0], hash_recovery_code(&codes[0].to_uppercase().replace('-', " ")));
+    let mut unique = codes.clone();
+    unique.sort();
+    unique.dedup();
+    assert_eq!(unique.len(), RECOVERY_CODES);
+}
+
+async fn state() -> web::Data<AppState> {
+    state_with(LoginLimiter::in_memory()).await
+}
+
+async fn state_with(login_limiter: LoginLimiter) -> web::Data<AppState> {
+    web::Data::new(AppState { login_limiter: Arc::new(login_limiter), ..common::state(common::database().await) })
+}
+
+async fn forget(state: &AppState, email: &str) {
+    sqlx::query("DELETE FROM users WHERE email = $1").bind(email).execute(&state.db.pool).await.unwrap();
+    sqlx::query("DELETE FROM login_failures WHERE email = $1").bind(email).execute(&state.db.pool).await.unwrap();
+}
+
+fn code_at(secret: &str, steps_ahead: i64) -> String {
+    totp_code(secret, totp_step(Utc::now().timestamp()) + steps_ahead).unwrap()
+}
+
+macro_rules! app {
+    ($state:expr) => {
+        test::init_service(App::new().app_data($state.clone()).configure(routes)).await
+    };
+}
+
+macro_rules! call {
+    ($app:expr, $req:expr) => {{
+        let res: ServiceResponse = test::call_service(&$app, $req.to_request()).await;
+        res
+    }};
+}
+
+macro_rules! post {
+    ($app:expr, $uri:expr, $bearer:expr, $body:expr) => {
+        call!($app, test::TestRequest::post().uri($uri).insert_header(("Authorization", $bearer.as_str())).set_json($body))
+    };
+}
+
+macro_rules! signin {
+    ($app:expr, $email:expr) => {{
+        let res = call!($app, test::TestRequest::post().uri("/signin").set_json(json!({ "email": $email, "password": "correct horse" })));
+        assert_eq!(res.status(), StatusCode::OK);
+        let signin: SigninResponse = test::read_body_json(res).await;
+        signin
+    }};
+}
+
+macro_rules! second_factor {
+    ($app:expr, $body:expr) => {
+        call!($app, test::TestRequest::post().uri("/signin/2fa").set_json($body))
+    };
+}
+
+// signs up and turns 2FA on, returns the email, the secret, the recovery codes, the tokens of
+// the session the enrollment happened in and the code that confirmed it
+macro_rules! enrolled {
+    ($app:expr) => {{
+        let email = format!("{}@totp.test", Uuid::new_v4());
+        let credentials = json!({ "email": email, "password": "correct horse" });
+        assert_eq!(call!($app, test::TestRequest::post().uri("/signup").set_json(&credentials)).status(), StatusCode::OK);
+        let SigninResponse::Tokens(tokens) = signin!($app, &email) else { panic!("no 2FA yet") };
+        let bearer = format!("Bearer {}", tokens.token);
+
+        let res = post!($app, "/2fa/totp", bearer, json!({}));
+        assert_eq!(res.status(), StatusCode::OK);
+        let enrollment: TotpEnrollmentResponse = test::read_body_json(res).await;
+        assert!(enrollment.otpauth_uri.contains(&enrollment.secret));
+        let confirmed_with = code_at(&enrollment.secret, 0);
+        let res = post!($app, "/2fa/totp/confirm", bearer, json!({ "code": confirmed_with }));
+        assert_eq!(res.status(), StatusCode::OK);
+        let RecoveryCodesResponse { recovery_codes } = test::read_body_json(res).await;
+        (email, enrollment.secret, recovery_codes, tokens, confirmed_with)
+    }};
+}
+
+fn challenge(signin: SigninResponse) -> String {
+    match signin {
+        SigninResponse::MfaRequired(MfaChallengeResponse { mfa_token, .. }) => mfa_token,
+        SigninResponse::Tokens(_) => panic!("signed in without a second factor"),
+    }
+}
+
+#[actix_web::test]
+async fn enrollment_needs_a_confirming_code() {
+    let state = state().await;
+    let app = app!(state);
+    let email = format!("{}@totp.test", Uuid::new_v4());
+    let credentials = json!({ "email": email, "password": "correct horse" });
+    assert_eq!(call!(app, test::TestRequest::post().uri("/signup").set_json(&credentials)).status(), StatusCode::OK);
+    let SigninResponse::Tokens(tokens) = signin!(app, &email) else { panic!("no 2FA yet") };
+    let bearer = format!("Bearer {}", tokens.token);
+
+    assert_eq!(post!(app, "/2fa/totp/confirm", bearer, json!({ "code": "123456" })).status(), StatusCode::NOT_FOUND);
+    let first: TotpEnrollmentResponse = test::read_body_json(post!(app, "/2fa/totp", bearer, json!({}))).await;
+    let second: TotpEnrollmentResponse = test::read_body_json(post!(app, "/2fa/totp", bearer, json!({}))).await;
+    assert_ne!(first.secret, second.secret);
+    // the restarted enrollment replaced the first secret
+    assert_eq!(post!(app, "/2fa/totp/confirm", bearer, json!({ "code": code_at(&first.secret, 0) })).status(), StatusCode::UNAUTHORIZED);
+    // until confirmed the password alone still signs in
+    assert!(matches!(signin!(app, &email), SigninResponse::Tokens(_)));
+
+    let res = post!(app, "/2fa/totp/confirm", bearer, json!({ "code": code_at(&second.secret, 0) }));
+    assert_eq!(res.status(), StatusCode::OK);
+    let codes: RecoveryCodesResponse = test::read_body_json(res).await;
+    assert_eq!(codes.recovery_codes.len(), RECOVERY_CODES);
+    assert_eq!(post!(app, "/2fa/totp", bearer, json!({})).status(), StatusCode::CONFLICT);
+    forget(&state, &email).await;
+}
+
+#[actix_web::test]
+async fn signin_takes_a_second_step_that_marks_the_session() {
+    let state = state().await;
+    let app = app!(state);
+    let (email, secret, _, enrolling, confirmed_with) = enrolled!(app);
+
+    let mfa_token = challenge(signin!(app, &email));
+    // the confirming code's step is spent
+    let res = second_factor!(app, json!({ "mfa_token": mfa_token, "code": confirmed_with }));
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let body: Value = test::read_body_json(res).await;
+    assert_eq!(body["error"], "Invalid two-factor code");
+    assert_eq!(second_factor!(app, json!({ "mfa_token": "made up", "code": code_at(&secret, 1) })).status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(second_factor!(app, json!({ "mfa_token": mfa_token })).status(), StatusCode::BAD_REQUEST);
+
+    let res = second_factor!(app, json!({ "mfa_token": mfa_token, "code": code_at(&secret, 1) }));
+    assert_eq!(res.status(), StatusCode::OK);
+    let tokens: TokenResponse = test::read_body_json(res).await;
+    assert!(state.keys.verify(&tokens.token).unwrap().mfa);
+    // a challenge opens one session
+    assert_eq!(second_factor!(app, json!({ "mfa_token": mfa_token, "code": code_at(&secret, 1) })).status(), StatusCode::UNAUTHORIZED);
+
+    // confirming counted as the second factor of the enrolling session, its next token says so
+    assert!(!state.keys.verify(&enrolling.token).unwrap().mfa);
+    let res = call!(app, test::TestRequest::post().uri("/token/refresh").set_json(json!({ "refresh_token": enrolling.refresh_token })));
+    let refreshed: TokenResponse = test::read_body_json(res).await;
+    assert!(state.keys.verify(&refreshed.token).unwrap().mfa);
+    forget(&state, &email).await;
+}
+
+#[actix_web::test]
+async fn recovery_codes_work_once() {
+    let state = state().await;
+    let app = app!(state);
+    let (email, _, recovery_codes, _, _) = enrolled!(app);
+
+    let res = second_factor!(app, json!({ "mfa_token": challenge(signin!(app, &email)), "recovery_code": recovery_codes[3].to_uppercase() }));
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = second_factor!(app, json!({ "mfa_token": challenge(signin!(app, &email)), "recovery_code": recovery_codes[3] }));
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let user = state.db.get_user(&email).await.unwrap().unwrap();
+    assert_eq!(state.db.remaining_recovery_codes(user.id).await.unwrap(), RECOVERY_CODES as i64 - 1);
+    forget(&state, &email).await;
+}
+
+#[actix_web::test]
+async fn challenges_run_out_of_attempts() {
+    // the default limiter would hold the account before the challenge runs out
+    let lenient = LoginPolicy { free_attempts: MAX_MFA_ATTEMPTS as u32, ..LoginPolicy::per_account() };
+    let state = state_with(LoginLimiter::in_memory().with_policies(lenient, LoginPolicy::per_ip())).await;
+    let app = app!(state);
+    let (email, secret, _, _, _) = enrolled!(app);
+
+    let mfa_token = challenge(signin!(app, &email));
+    for _ in 0..MAX_MFA_ATTEMPTS {
+        assert_eq!(second_factor!(app, json!({ "mfa_token": mfa_token, "code": "000000" })).status(), StatusCode::UNAUTHORIZED);
+    }
+    let res = second_factor!(app, json!({ "mfa_token": mfa_token, "code": code_at(&secret, 1) }));
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let body: Value = test::read_body_json(res).await;
+    assert_eq!(body["error"], "Invalid or expired two-factor challenge");
+    forget(&state, &email).await;
+}
+
+#[actix_web::test]
+async fn sensitive_endpoints_want_a_verified_session() {
+    let state = state().await;
+    let app = app!(state);
+    let (email, secret, _, enrolling, _) = enrolled!(app);
+    let unverified = format!("Bearer {}", enrolling.token);
+    let key = json!({ "label": "bot", "scopes": ["trade"] });
+
+    let res = post!(app, "/api_keys", unverified, &key);
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let body: Value = test::read_body_json(res).await;
+    assert_eq!(body["error"], "Two-factor authentication required");
+    let disable = test::TestRequest::delete().uri("/2fa/totp").insert_header(("Authorization", unverified.as_str())).set_json(json!({ "code": code_at(&secret, 1) }));
+    assert_eq!(call!(app, disable).status(), StatusCode::FORBIDDEN);
+
+    let signed_in_with = code_at(&secret, 1);
+    let res = second_factor!(app, json!({ "mfa_token": challenge(signin!(app, &email)), "code": signed_in_with }));
+    let tokens: TokenResponse = test::read_body_json(res).await;
+    let verified = format!("Bearer {}", tokens.token);
+    assert_eq!(post!(app, "/api_keys", verified, &key).status(), StatusCode::OK);
+
+    // turning 2FA off takes a fresh code too, and every step up to the one just used is spent
+    let disable = |code: &str| test::TestRequest::delete().uri("/2fa/totp").insert_header(("Authorization", verified.as_str())).set_json(json!({ "code": code }));
+    assert_eq!(call!(app, disable(&signed_in_with)).status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(call!(app, disable(&code_at(&secret, 0))).status(), StatusCode::UNAUTHORIZED);
+    // as if a minute had passed
+    sqlx::query("UPDATE totp_credentials SET last_used_step = last_used_step - 2 FROM users WHERE users.id = user_id AND email = $1")
+        .bind(&email)
+        .execute(&state.db.pool)
+        .await
+        .unwrap();
+    assert_eq!(call!(app, disable(&code_at(&secret, 0))).status(), StatusCode::NO_CONTENT);
+    assert!(matches!(signin!(app, &email), SigninResponse::Tokens(_)));
+    forget(&state, &email).await;
+}
+
+#[actix_web::test]
+async fn wrong_codes_across_fresh_challenges_lock_the_account() {
+    let per_account = LoginPolicy { free_attempts: 2, base_delay: Duration::minutes(1), max_delay: Duration::minutes(5), ..LoginPolicy::per_account() };
+    let state = state_with(LoginLimiter::in_memory().with_policies(per_account, LoginPolicy::per_ip())).await;
+    let app = app!(state);
+    let (email, secret, _, _, _) = enrolled!(app);
+    // far outside the accepted window
+    let wrong = code_at(&secret, 20);
+
+    let spare = challenge(signin!(app, &email));
+    for _ in 0..3 {
+        let mfa_token = challenge(signin!(app, &email));
+        assert_eq!(second_factor!(app, json!({ "mfa_token": mfa_token, "code": wrong })).status(), StatusCode::UNAUTHORIZED);
+    }
+    // the password is right but the account has to wait, and so does a challenge opened earlier
+    let res = call!(app, test::TestRequest::post().uri("/signin").set_json(json!({ "email": email, "password": "correct horse" })));
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    let res = second_factor!(app, json!({ "mfa_token": spare, "code": code_at(&secret, 0) }));
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.headers().get(RETRY_AFTER).unwrap(), "60");
+
+    let reasons: Vec<String> = state.db.login_failures(&email).await.unwrap().into_iter().map(|f| f.reason).collect();
+    assert_eq!(&reasons[..3], ["wrong or reused code", "wrong or reused code", "wrong or reused code"]);
+    assert_eq!(reasons.len(), 5);
+    forget(&state, &email).await;
+}
@@ -0,0 +1,104 @@
+use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use backend::{BroadcastRingBuilder, BusySpin, RingBuffer, SpinThenPark, SpinThenYield, TimeoutBlocking, WaitStrategy};
+
+const ITEMS: u64 = 20_000;
+
+fn spsc_round_trip(wait: impl WaitStrategy + 'static) {
+    let ring = Arc::new(RingBuffer::new(64).with_wait_strategy(wait));
+    let consumer = Arc::clone(&ring);
+    let reader = thread::spawn(move || {
+        let mut expected = 0;
+        while expected < ITEMS {
+            if let Some(item) = consumer.pop_wait() {
+                assert_eq!(item, expected);
+                expected += 1;
+            }
+        }
+    });
+    for item in 0..ITEMS {
+        while !ring.push(item) {
+            thread::yield_now();
+        }
+    }
+    reader.join().unwrap();
+}
+
+#[test]
+fn busy_spin_delivers_everything() {
+    spsc_round_trip(BusySpin);
+}
+
+#[test]
+fn spin_then_yield_delivers_everything() {
+    spsc_round_trip(SpinThenYield::new(10, 10));
+}
+
+#[test]
+fn spin_then_park_delivers_everything() {
+    spsc_round_trip(SpinThenPark::new(10));
+}
+
+#[test]
+fn timeout_blocking_delivers_everything() {
+    spsc_round_trip(TimeoutBlocking::new(Duration::from_secs(5)));
+}
+
+#[test]
+fn timeout_blocking_gives_up_after_its_timeout() {
+    let ring = RingBuffer::<u64>::new(8).with_wait_strategy(TimeoutBlocking::new(Duration::from_millis(30)));
+    let started = Instant::now();
+    assert_eq!(ring.pop_wait(), None);
+    assert!(started.elapsed() >= Duration::from_millis(30));
+}
+
+#[test]
+fn parked_consumer_is_woken_by_a_push() {
+    // the timeout is far longer than the test may take, only notify can end the wait in time
+    let ring = Arc::new(RingBuffer::<u64>::new(8).with_wait_strategy(TimeoutBlocking::new(Duration::from_secs(30))));
+    let producer = Arc::clone(&ring);
+    let writer = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(20));
+        producer.push(7);
+    });
+    let started = Instant::now();
+    assert_eq!(ring.pop_wait(), Some(7));
+    assert!(started.elapsed() < Duration::from_secs(10));
+    writer.join().unwrap();
+}
+
+#[test]
+fn broadcast_consumers_wait_their_own_way() {
+    let (mut producer, consumers) = BroadcastRingBuilder::new(32)
+        .consumer_with_wait("fast", &[], BusySpin)
+        .consumer_with_wait("audit", &["fast"], TimeoutBlocking::new(Duration::from_secs(30)))
+        .build::<u64>();
+
+    let readers: Vec<_> = consumers
+        .into_iter()
+        .map(|mut consumer| {
+            thread::spawn(move || {
+                let mut expected = 0;
+                while expected < ITEMS {
+                    if !consumer.wait_for_items() {
+                        continue;
+                    }
+                    for item in consumer.drain_batch(16) {
+                        assert_eq!(item, expected);
+                        expected += 1;
+                    }
+                }
+            })
+        })
+        .collect();
+    for item in 0..ITEMS {
+        producer.publish(item);
+    }
+    for reader in readers {
+        reader.join().unwrap();
+    }
+}
@@ -0,0 +1,17 @@
+-- fills published by the matching engine, keyed by the engine event seq so redelivery is a no-op
+CREATE TABLE trades (
+    seq BIGINT PRIMARY KEY,
+    command_seq BIGINT NOT NULL,
+    fill_seq BIGINT NOT NULL,
+    maker_order_id UUID NOT NULL,
+    taker_order_id UUID NOT NULL,
+    maker_user_id UUID NOT NULL,
+    taker_user_id UUID NOT NULL,
+    maker_side text NOT NULL,
+    price NUMERIC NOT NULL,
+    quantity NUMERIC NOT NULL,
+    executed_at_ns BIGINT NOT NULL
+);
+
+CREATE INDEX trades_maker_user_idx ON trades (maker_user_id);
+CREATE INDEX trades_taker_user_idx ON trades (taker_user_id);
@@ -0,0 +1,4 @@
+-- argon2id hashes in PHC string format; password keeps legacy plaintext until the user's next sign-in
+ALTER TABLE users ADD COLUMN password_hash text;
+ALTER TABLE users ALTER COLUMN password DROP NOT NULL;
+ALTER TABLE users ADD CONSTRAINT users_credentials CHECK (password_hash IS NOT NULL OR password IS NOT NULL);
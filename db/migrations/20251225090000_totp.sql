@@ -0,0 +1,29 @@
+-- one authenticator per user, unusable until a first code confirms the enrollment. The secret
+-- has to be readable to check codes; last_used_step stops a code from being used twice
+CREATE TABLE totp_credentials (
+    user_id UUID PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    secret TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    confirmed_at TIMESTAMPTZ,
+    last_used_step BIGINT
+);
+
+-- handed out once when the authenticator is confirmed, only sha-256 hashes are kept
+CREATE TABLE recovery_codes (
+    code_hash TEXT PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    used_at TIMESTAMPTZ
+);
+
+CREATE INDEX recovery_codes_user_id ON recovery_codes (user_id);
+
+-- a correct password of a user with 2FA opens one of these instead of a session
+CREATE TABLE mfa_challenges (
+    token_hash TEXT PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    attempts INT NOT NULL DEFAULT 0
+);
+
+-- whether the session was opened with a second factor, refreshed tokens keep it
+ALTER TABLE sessions ADD COLUMN mfa BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,4 @@
+-- what a user may do, read into every token and checked by RequireRole. Admins assign roles, new
+-- accounts trade
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'trader'
+    CHECK (role IN ('trader', 'market_maker', 'admin', 'auditor'));
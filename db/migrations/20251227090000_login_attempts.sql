@@ -0,0 +1,19 @@
+-- every failed sign-in, for audits. Kept apart from the counters below, which are only there
+-- to slow attackers down and may live in memory instead
+CREATE TABLE login_failures (
+    id BIGSERIAL PRIMARY KEY,
+    email TEXT NOT NULL,
+    ip TEXT,
+    reason TEXT NOT NULL,
+    attempted_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX login_failures_email ON login_failures (email, attempted_at);
+
+-- failure counters of the Postgres login limiter, keyed by account or client address
+CREATE TABLE login_throttle (
+    key TEXT PRIMARY KEY,
+    failures INT NOT NULL,
+    last_failure_at TIMESTAMPTZ NOT NULL,
+    locked_until TIMESTAMPTZ
+);
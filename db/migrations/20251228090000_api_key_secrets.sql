@@ -0,0 +1,4 @@
+-- signing secrets are random per key now and kept encrypted under a server key, instead of being
+-- derived from the key id. Keys issued the old way cannot be carried over and have to be reissued
+ALTER TABLE api_keys RENAME COLUMN secret_hash TO secret_encrypted;
+UPDATE api_keys SET revoked_at = now() WHERE revoked_at IS NULL;
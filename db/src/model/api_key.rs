@@ -0,0 +1,76 @@
+use anyhow::{Ok, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::Db;
+
+// scopes and allowed_ips are kept as text, the backend parses them
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiKey {
+    pub id : Uuid,
+    pub user_id : Uuid,
+    pub label : String,
+    pub secret_encrypted : String,
+    pub scopes : Vec<String>,
+    pub allowed_ips : Vec<String>,
+    pub created_at : DateTime<Utc>
+}
+
+impl Db {
+    // the id is chosen by the caller since the secret is encrypted with it as associated data
+    pub async fn create_api_key(&self, id: Uuid, user_id: Uuid, label: &str, secret_encrypted: &str, scopes: &[String], allowed_ips: &[String]) -> Result<ApiKey> {
+        let key = sqlx::query_as!(
+            ApiKey,
+            "INSERT INTO api_keys (id, user_id, label, secret_encrypted, scopes, allowed_ips) VALUES ($1, $2, $3, $4, $5, $6)
+             RETURNING id, user_id, label, secret_encrypted, scopes, allowed_ips, created_at",
+            id,
+            user_id,
+            label,
+            secret_encrypted,
+            scopes,
+            allowed_ips
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(key)
+    }
+
+    // None for unknown and revoked keys
+    pub async fn get_api_key(&self, id: Uuid) -> Result<Option<ApiKey>> {
+        let key = sqlx::query_as!(
+            ApiKey,
+            "SELECT id, user_id, label, secret_encrypted, scopes, allowed_ips, created_at FROM api_keys
+             WHERE id = $1 AND revoked_at IS NULL",
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(key)
+    }
+
+    pub async fn list_api_keys(&self, user_id: Uuid) -> Result<Vec<ApiKey>> {
+        let keys = sqlx::query_as!(
+            ApiKey,
+            "SELECT id, user_id, label, secret_encrypted, scopes, allowed_ips, created_at FROM api_keys
+             WHERE user_id = $1 AND revoked_at IS NULL
+             ORDER BY created_at DESC",
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(keys)
+    }
+
+    // false when this user has no such live key
+    pub async fn revoke_api_key(&self, id: Uuid, user_id: Uuid) -> Result<bool> {
+        let revoked = sqlx::query!(
+            "UPDATE api_keys SET revoked_at = now() WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL",
+            id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(revoked.rows_affected() == 1)
+    }
+}
@@ -0,0 +1,74 @@
+use anyhow::{Ok, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::Db;
+
+// a failed sign-in as the audit keeps it
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LoginFailure {
+    pub email : String,
+    pub ip : Option<String>,
+    pub reason : String,
+    pub attempted_at : DateTime<Utc>
+}
+
+impl Db {
+    pub async fn record_login_failure(&self, email: &str, ip: Option<&str>, reason: &str) -> Result<()> {
+        sqlx::query!("INSERT INTO login_failures (email, ip, reason) VALUES ($1, $2, $3)", email, ip, reason)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    // the failed sign-ins of an email, oldest first
+    pub async fn login_failures(&self, email: &str) -> Result<Vec<LoginFailure>> {
+        let failures = sqlx::query_as!(
+            LoginFailure,
+            "SELECT email, ip, reason, attempted_at FROM login_failures WHERE email = $1 ORDER BY id",
+            email
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(failures)
+    }
+
+    pub async fn login_locked_until(&self, key: &str) -> Result<Option<DateTime<Utc>>> {
+        let locked_until = sqlx::query_scalar!("SELECT locked_until FROM login_throttle WHERE key = $1", key)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(locked_until.flatten())
+    }
+
+    // Counts a failure and returns how many there are in a row now. Failures from before
+    // `forget_before` do not count anymore, the run starts over at one.
+    pub async fn count_login_failure(&self, key: &str, now: DateTime<Utc>, forget_before: DateTime<Utc>) -> Result<i32> {
+        let failures = sqlx::query_scalar!(
+            "INSERT INTO login_throttle (key, failures, last_failure_at) VALUES ($1, 1, $2)
+             ON CONFLICT (key) DO UPDATE SET
+                failures = CASE WHEN login_throttle.last_failure_at < $3 THEN 1 ELSE login_throttle.failures + 1 END,
+                last_failure_at = $2
+             RETURNING failures",
+            key,
+            now,
+            forget_before
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(failures)
+    }
+
+    pub async fn lock_login(&self, key: &str, until: DateTime<Utc>) -> Result<()> {
+        sqlx::query!("UPDATE login_throttle SET locked_until = $2 WHERE key = $1", key, until)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn clear_login_failures(&self, key: &str) -> Result<()> {
+        sqlx::query!("DELETE FROM login_throttle WHERE key = $1", key)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
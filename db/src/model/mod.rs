@@ -1,2 +1,12 @@
 pub mod user;
-pub use user::*;
\ No newline at end of file
+pub use user::*;
+pub mod trade;
+pub use trade::*;
+pub mod session;
+pub use session::*;
+pub mod api_key;
+pub use api_key::*;
+pub mod totp;
+pub use totp::*;
+pub mod login;
+pub use login::*;
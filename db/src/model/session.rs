@@ -0,0 +1,119 @@
+use anyhow::{Ok, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::Db;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Session {
+    pub id : Uuid,
+    pub user_id : Uuid,
+    pub created_at : DateTime<Utc>,
+    pub last_used_at : DateTime<Utc>,
+    pub expires_at : DateTime<Utc>,
+    pub mfa : bool
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RefreshOutcome {
+    Rotated { session_id: Uuid, user_id: Uuid, mfa: bool, role: String },
+    // the token had been rotated before, so someone replayed it; its session is revoked now
+    Reused { session_id: Uuid, user_id: Uuid },
+    // unknown token, or its session expired or was revoked
+    Invalid,
+}
+
+impl Db {
+    // a new session together with its first refresh token
+    pub async fn create_session(&self, user_id: Uuid, expires_at: DateTime<Utc>, token_hash: &str, mfa: bool) -> Result<Uuid> {
+        let mut tx = self.pool.begin().await?;
+        let session_id = sqlx::query_scalar!("INSERT INTO sessions (user_id, expires_at, mfa) VALUES ($1, $2, $3) RETURNING id", user_id, expires_at, mfa)
+            .fetch_one(&mut *tx)
+            .await?;
+        sqlx::query!("INSERT INTO refresh_tokens (token_hash, session_id) VALUES ($1, $2)", token_hash, session_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(session_id)
+    }
+
+    // Swaps the token for a new one in the same session. Rows are locked, so of two concurrent
+    // refreshes with the same token one rotates and the other counts as reuse.
+    pub async fn rotate_refresh_token(&self, token_hash: &str, new_token_hash: &str) -> Result<RefreshOutcome> {
+        let mut tx = self.pool.begin().await?;
+        let found = sqlx::query!(
+            r#"SELECT t.session_id, t.used_at, s.user_id, s.revoked_at, s.expires_at, s.mfa, u.role
+               FROM refresh_tokens t JOIN sessions s ON s.id = t.session_id JOIN users u ON u.id = s.user_id
+               WHERE t.token_hash = $1
+               FOR UPDATE OF t, s"#,
+            token_hash
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(token) = found else {
+            return Ok(RefreshOutcome::Invalid);
+        };
+        if token.revoked_at.is_some() || token.expires_at <= Utc::now() {
+            return Ok(RefreshOutcome::Invalid);
+        }
+        if token.used_at.is_some() {
+            sqlx::query!("UPDATE sessions SET revoked_at = now() WHERE id = $1", token.session_id)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            return Ok(RefreshOutcome::Reused { session_id: token.session_id, user_id: token.user_id });
+        }
+
+        sqlx::query!("UPDATE refresh_tokens SET used_at = now() WHERE token_hash = $1", token_hash)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!("INSERT INTO refresh_tokens (token_hash, session_id) VALUES ($1, $2)", new_token_hash, token.session_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!("UPDATE sessions SET last_used_at = now() WHERE id = $1", token.session_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(RefreshOutcome::Rotated { session_id: token.session_id, user_id: token.user_id, mfa: token.mfa, role: token.role })
+    }
+
+    // sessions that can still be refreshed, most recently used first
+    pub async fn list_sessions(&self, user_id: Uuid) -> Result<Vec<Session>> {
+        let sessions = sqlx::query_as!(
+            Session,
+            "SELECT id, user_id, created_at, last_used_at, expires_at, mfa FROM sessions
+             WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > now()
+             ORDER BY last_used_at DESC",
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(sessions)
+    }
+
+    // marks a live session as having passed a second factor
+    pub async fn set_session_mfa(&self, session_id: Uuid, user_id: Uuid) -> Result<bool> {
+        let updated = sqlx::query!(
+            "UPDATE sessions SET mfa = true WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL",
+            session_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(updated.rows_affected() == 1)
+    }
+
+    // false when there is no such live session of this user
+    pub async fn revoke_session(&self, session_id: Uuid, user_id: Uuid) -> Result<bool> {
+        let revoked = sqlx::query!(
+            "UPDATE sessions SET revoked_at = now() WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL",
+            session_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(revoked.rows_affected() == 1)
+    }
+}
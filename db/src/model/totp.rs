@@ -0,0 +1,158 @@
+use anyhow::{Ok, Result};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::Db;
+
+pub struct TotpCredential {
+    pub user_id : Uuid,
+    pub secret : String,
+    pub confirmed_at : Option<DateTime<Utc>>,
+    pub last_used_step : Option<i64>
+}
+
+impl Db {
+    // starts or restarts an enrollment, false when the user already has a confirmed authenticator
+    pub async fn set_pending_totp(&self, user_id: Uuid, secret: &str) -> Result<bool> {
+        let stored = sqlx::query!(
+            "INSERT INTO totp_credentials (user_id, secret) VALUES ($1, $2)
+             ON CONFLICT (user_id) DO UPDATE SET secret = EXCLUDED.secret, created_at = now()
+             WHERE totp_credentials.confirmed_at IS NULL",
+            user_id,
+            secret
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(stored.rows_affected() == 1)
+    }
+
+    pub async fn get_totp(&self, user_id: Uuid) -> Result<Option<TotpCredential>> {
+        let credential = sqlx::query_as!(
+            TotpCredential,
+            "SELECT user_id, secret, confirmed_at, last_used_step FROM totp_credentials WHERE user_id = $1",
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(credential)
+    }
+
+    // Confirms a pending enrollment with the step of the code that proved it and replaces the
+    // recovery codes. False when there was nothing pending.
+    pub async fn confirm_totp(&self, user_id: Uuid, step: i64, recovery_code_hashes: &[String]) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        let confirmed = sqlx::query!(
+            "UPDATE totp_credentials SET confirmed_at = now(), last_used_step = $2 WHERE user_id = $1 AND confirmed_at IS NULL",
+            user_id,
+            step
+        )
+        .execute(&mut *tx)
+        .await?;
+        if confirmed.rows_affected() == 0 {
+            return Ok(false);
+        }
+        sqlx::query!("DELETE FROM recovery_codes WHERE user_id = $1", user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!(
+            "INSERT INTO recovery_codes (code_hash, user_id) SELECT unnest($2::TEXT[]), $1",
+            user_id,
+            recovery_code_hashes
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    // Records a code's time step. Steps only move forward, so a code that was accepted once, or
+    // an older one, is refused even when two requests race.
+    pub async fn use_totp_step(&self, user_id: Uuid, step: i64) -> Result<bool> {
+        let used = sqlx::query!(
+            "UPDATE totp_credentials SET last_used_step = $2
+             WHERE user_id = $1 AND confirmed_at IS NOT NULL AND (last_used_step IS NULL OR last_used_step < $2)",
+            user_id,
+            step
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(used.rows_affected() == 1)
+    }
+
+    // false when the code is unknown or was used before
+    pub async fn use_recovery_code(&self, user_id: Uuid, code_hash: &str) -> Result<bool> {
+        let used = sqlx::query!(
+            "UPDATE recovery_codes SET used_at = now() WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL",
+            user_id,
+            code_hash
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(used.rows_affected() == 1)
+    }
+
+    pub async fn remaining_recovery_codes(&self, user_id: Uuid) -> Result<i64> {
+        let remaining = sqlx::query_scalar!("SELECT count(*) FROM recovery_codes WHERE user_id = $1 AND used_at IS NULL", user_id)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(remaining.unwrap_or(0))
+    }
+
+    pub async fn delete_totp(&self, user_id: Uuid) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        let deleted = sqlx::query!("DELETE FROM totp_credentials WHERE user_id = $1", user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!("DELETE FROM recovery_codes WHERE user_id = $1", user_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(deleted.rows_affected() == 1)
+    }
+
+    pub async fn create_mfa_challenge(&self, token_hash: &str, user_id: Uuid, expires_at: DateTime<Utc>) -> Result<()> {
+        sqlx::query!(
+            "INSERT INTO mfa_challenges (token_hash, user_id, expires_at) VALUES ($1, $2, $3)",
+            token_hash,
+            user_id,
+            expires_at
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    // Counts an attempt at a challenge and returns its user, None once it expired or ran out of
+    // attempts. The attempt is counted before the code is checked, so guesses cannot race past it.
+    pub async fn attempt_mfa_challenge(&self, token_hash: &str, max_attempts: i32) -> Result<Option<Uuid>> {
+        let user_id = sqlx::query_scalar!(
+            "UPDATE mfa_challenges SET attempts = attempts + 1
+             WHERE token_hash = $1 AND expires_at > now() AND attempts < $2
+             RETURNING user_id",
+            token_hash,
+            max_attempts
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(user_id)
+    }
+
+    // the email of the user a live challenge belongs to, without spending an attempt
+    pub async fn mfa_challenge_email(&self, token_hash: &str) -> Result<Option<String>> {
+        let email = sqlx::query_scalar!(
+            "SELECT u.email FROM mfa_challenges c JOIN users u ON u.id = c.user_id
+             WHERE c.token_hash = $1 AND c.expires_at > now()",
+            token_hash
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(email)
+    }
+
+    pub async fn delete_mfa_challenge(&self, token_hash: &str) -> Result<()> {
+        sqlx::query!("DELETE FROM mfa_challenges WHERE token_hash = $1 OR expires_at <= now()", token_hash)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
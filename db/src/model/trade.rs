@@ -0,0 +1,69 @@
+use anyhow::{Ok, Result};
+use sqlx::types::Decimal;
+use uuid::Uuid;
+
+use crate::Db;
+
+pub struct TradeRecord {
+    pub seq : i64,
+    pub command_seq : i64,
+    pub fill_seq : i64,
+    pub maker_order_id : Uuid,
+    pub taker_order_id : Uuid,
+    pub maker_user_id : Uuid,
+    pub taker_user_id : Uuid,
+    pub maker_side : String,
+    pub price : Decimal,
+    pub quantity : Decimal,
+    pub executed_at_ns : i64
+}
+
+impl Db {
+    //one round trip per batch, rows already stored (same seq) are skipped so redelivery is harmless
+    pub async fn insert_trades(&self, trades: &[TradeRecord]) -> Result<u64> {
+        let mut seq = Vec::with_capacity(trades.len());
+        let mut command_seq = Vec::with_capacity(trades.len());
+        let mut fill_seq = Vec::with_capacity(trades.len());
+        let mut maker_order_id = Vec::with_capacity(trades.len());
+        let mut taker_order_id = Vec::with_capacity(trades.len());
+        let mut maker_user_id = Vec::with_capacity(trades.len());
+        let mut taker_user_id = Vec::with_capacity(trades.len());
+        let mut maker_side = Vec::with_capacity(trades.len());
+        let mut price = Vec::with_capacity(trades.len());
+        let mut quantity = Vec::with_capacity(trades.len());
+        let mut executed_at_ns = Vec::with_capacity(trades.len());
+        for t in trades {
+            seq.push(t.seq);
+            command_seq.push(t.command_seq);
+            fill_seq.push(t.fill_seq);
+            maker_order_id.push(t.maker_order_id);
+            taker_order_id.push(t.taker_order_id);
+            maker_user_id.push(t.maker_user_id);
+            taker_user_id.push(t.taker_user_id);
+            maker_side.push(t.maker_side.clone());
+            price.push(t.price);
+            quantity.push(t.quantity);
+            executed_at_ns.push(t.executed_at_ns);
+        }
+
+        let res = sqlx::query!(
+            "INSERT INTO trades (seq, command_seq, fill_seq, maker_order_id, taker_order_id, maker_user_id, taker_user_id, maker_side, price, quantity, executed_at_ns)
+             SELECT * FROM UNNEST($1::BIGINT[], $2::BIGINT[], $3::BIGINT[], $4::UUID[], $5::UUID[], $6::UUID[], $7::UUID[], $8::TEXT[], $9::NUMERIC[], $10::NUMERIC[], $11::BIGINT[])
+             ON CONFLICT (seq) DO NOTHING",
+            &seq,
+            &command_seq,
+            &fill_seq,
+            &maker_order_id,
+            &taker_order_id,
+            &maker_user_id,
+            &taker_user_id,
+            &maker_side,
+            &price,
+            &quantity,
+            &executed_at_ns
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(res.rows_affected())
+    }
+}
@@ -11,27 +11,58 @@ pub struct  CreateUserResponse{
     pub id :Uuid
 }
 
+// password is only set on rows created before hashing, password_hash replaces it on the next sign-in
 #[derive(Serialize,Deserialize)]
 pub struct User {
     pub id : Uuid,
     pub email : String,
-    pub password : String
+    pub password : Option<String>,
+    pub password_hash : Option<String>,
+    pub role : String
 }
 
 impl Db {
-    pub async fn create_user(&self,email:&String,password:&String)->Result<CreateUserResponse>{
-        let u = sqlx::query_as!(CreateUserResponse,"INSERT INTO users (email,password) VALUES ($1,$2) RETURNING id",email,password)
-           .fetch_one(&self.pool)
+    // None when the email is already taken
+    pub async fn create_user(&self,email:&str,password_hash:&str)->Result<Option<CreateUserResponse>>{
+        let u = sqlx::query_as!(CreateUserResponse,"INSERT INTO users (email,password_hash) VALUES ($1,$2) ON CONFLICT (email) DO NOTHING RETURNING id",email,password_hash)
+           .fetch_optional(&self.pool)
            .await?;
-        Ok(CreateUserResponse { 
-            id: u.id
-        })
-
+        Ok(u)
+    }
+    pub  async  fn get_user(&self , email:&str)->Result<Option<User>>{
+        let u = sqlx::query_as!(User,"SELECT id , email,password,password_hash,role FROM users WHERE email=$1",email)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(u)
     }
-    pub  async  fn get_user(&self , email:&String)->Result<User>{
-        let u = sqlx::query_as!(User,"SELECT id , email,password FROM users WHERE email=$1",email)
-            .fetch_one(&self.pool)
+
+    pub async fn get_user_by_id(&self, id: Uuid) -> Result<Option<User>> {
+        let u = sqlx::query_as!(User,"SELECT id, email, password, password_hash, role FROM users WHERE id = $1",id)
+            .fetch_optional(&self.pool)
             .await?;
         Ok(u)
     }
-}
\ No newline at end of file
+
+    // swaps whatever credential the user had for a new hash, dropping a legacy plaintext password
+    pub async fn set_password_hash(&self, id: Uuid, password_hash: &str) -> Result<()> {
+        sqlx::query!("UPDATE users SET password_hash = $2, password = NULL WHERE id = $1", id, password_hash)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_user_role(&self, id: Uuid) -> Result<Option<String>> {
+        let role = sqlx::query_scalar!("SELECT role FROM users WHERE id = $1", id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(role)
+    }
+
+    // false when there is no such user
+    pub async fn set_user_role(&self, id: Uuid, role: &str) -> Result<bool> {
+        let updated = sqlx::query!("UPDATE users SET role = $2 WHERE id = $1", id, role)
+            .execute(&self.pool)
+            .await?;
+        Ok(updated.rows_affected() == 1)
+    }
+}